@@ -0,0 +1,70 @@
+//! Compares one-shot damage through the AddEffect observer with the ApplyInstant fast path.
+//! Run with `cargo bench --bench instant_damage`.
+
+use std::hint::black_box;
+
+use bevy::prelude::*;
+use bevy_gameplay_effects::prelude::*;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+const ENTITIES: usize = 10_000;
+
+stats! (
+    CharacterStats {
+        Health,
+        Strength,
+    }
+);
+
+fn setup() -> (App, Vec<Entity>) {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, GameplayEffectsPlugin::<CharacterStats>::default()));
+    let entities = (0..ENTITIES)
+        .map(|_| app.world_mut().spawn((
+            GameplayStats::<CharacterStats>::new(|stat| match stat {
+                CharacterStats::Health => 1_000_000.,
+                CharacterStats::Strength => 5.,
+                _ => 0.,
+            }),
+            ActiveEffects::<CharacterStats>::new(std::iter::empty()),
+        )).id())
+        .collect();
+    app.update();
+    (app, entities)
+}
+
+fn instant_damage(c: &mut Criterion) {
+    let mut group = c.benchmark_group("instant_damage");
+    let damage = GameplayEffect::new(
+        None,
+        CharacterStats::Health,
+        EffectMagnitude::Fixed(-5.),
+        EffectCalculation::Additive,
+        EffectDuration::Immediate,
+    );
+
+    group.bench_function("add_effect_observer", |b| b.iter_batched_ref(
+        setup,
+        |(app, entities)| {
+            for &entity in entities.iter() {
+                app.world_mut().trigger(AddEffect(AddEffectData::new(entity, damage.clone(), None)));
+            }
+            black_box(app);
+        },
+        BatchSize::LargeInput,
+    ));
+    group.bench_function("apply_instant", |b| b.iter_batched_ref(
+        setup,
+        |(app, entities)| {
+            for &entity in entities.iter() {
+                app.world_mut().trigger(ApplyInstant { target_entity: entity, stat: CharacterStats::Health, amount: -5., source_entity: None });
+            }
+            black_box(app);
+        },
+        BatchSize::LargeInput,
+    ));
+    group.finish();
+}
+
+criterion_group!(benches, instant_damage);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+// Run with: cargo run --example debug_ui --features debug_ui
+
+use bevy::prelude::*;
+use bevy_egui::EguiPlugin;
+use bevy_hierarchical_tags::prelude::*;
+use bevy_gameplay_effects::prelude::*;
+
+stats! (
+    CharacterStats {
+        Health,
+        HealthRegen,
+        Strength,
+    }
+);
+
+fn main() {
+    let mut app = App::new();
+    let mut tag_registry: TagRegistry = TagRegistry::new();
+    let burning = tag_registry.register("Effect.Status.Burning");
+    app.insert_resource(tag_registry);
+
+    app.add_plugins((
+        DefaultPlugins,
+        EguiPlugin::default(),
+        GameplayEffectsPlugin::<CharacterStats>::default(),
+        EffectsDebugPlugin::<CharacterStats>::default(),
+    ));
+    app.add_systems(Startup, move |mut commands: Commands| spawn_entities(&mut commands, burning));
+    app.run();
+}
+
+fn spawn_entities(commands: &mut Commands, burning: TagId) {
+    commands.spawn(Camera2d);
+    for i in 0..3 {
+        let stats = GameplayStats::<CharacterStats>::new(|stat| match stat {
+            CharacterStats::Health => 100.,
+            CharacterStats::HealthRegen => 2.,
+            CharacterStats::Strength => 5. + i as f32,
+            CharacterStats::None => 0.,
+        });
+        let effects = ActiveEffects::<CharacterStats>::new([
+            GameplayEffect::new(
+                None,
+                CharacterStats::Health,
+                EffectMagnitude::LocalStat(CharacterStats::HealthRegen, StatScalingParams::default()),
+                EffectCalculation::Additive,
+                EffectDuration::continuous_forever(),
+            ),
+            GameplayEffect::new(
+                Some(burning),
+                CharacterStats::Health,
+                EffectMagnitude::Fixed(-3.),
+                EffectCalculation::Additive,
+                EffectDuration::repeating(1., 30.),
+            ),
+        ]);
+        commands.spawn((stats, effects));
+    }
+}
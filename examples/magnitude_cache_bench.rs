@@ -0,0 +1,61 @@
+// Compares continuous NonlocalStat effects with and without magnitude caching.
+// Run with: cargo run --release --example magnitude_cache_bench
+
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_gameplay_effects::prelude::*;
+
+const ENTITIES: usize = 50_000;
+const FRAMES: u32 = 200;
+
+stats! (
+    CharacterStats {
+        Health,
+        Strength,
+    }
+);
+
+fn main() {
+    for cached in [false, true] {
+        let elapsed = run(cached);
+        println!("cached: {cached:<5} {:?} per frame", elapsed / FRAMES);
+    }
+}
+
+fn run(cached: bool) -> Duration {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        GameplayEffectsPlugin::<CharacterStats>::default(),
+    ));
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(16)));
+
+    let stats = GameplayStats::<CharacterStats>::new(|stat| match stat {
+        CharacterStats::Health => 1_000_000.,
+        CharacterStats::Strength => 5.,
+        CharacterStats::None => 0.,
+    });
+    let source = app.world_mut().spawn((stats.clone(), ActiveEffects::<CharacterStats>::new([]))).id();
+
+    let mut effect = GameplayEffect::new(
+        None,
+        CharacterStats::Health,
+        EffectMagnitude::NonlocalStat(CharacterStats::Strength, StatScalingParams { multiplier: -1., exponent: 1.5, ..default() }, source),
+        EffectCalculation::Additive,
+        EffectDuration::continuous_forever(),
+    );
+    if cached {
+        effect = effect.with_cached_magnitude();
+    }
+    let effects = ActiveEffects::<CharacterStats>::new([effect]);
+    app.world_mut().spawn_batch((0..ENTITIES).map(move |_| (stats.clone(), effects.clone())));
+    app.update();
+
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        app.update();
+    }
+    start.elapsed()
+}
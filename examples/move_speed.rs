@@ -0,0 +1,63 @@
+// Slows a custom MoveSpeed component through an effect on its backing stat.
+// Run with: cargo run --example move_speed
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_gameplay_effects::prelude::*;
+
+stats! (
+    CharacterStats {
+        Health,
+        MoveSpeed,
+    }
+);
+
+/// Movement speed read by the rest of the game, kept outside GameplayStats
+#[derive(Component)]
+struct MoveSpeed(f32);
+
+impl EffectTarget<CharacterStats> for MoveSpeed {
+    fn stat() -> CharacterStats {
+        CharacterStats::MoveSpeed
+    }
+
+    fn write(&mut self, value: f32) {
+        self.0 = value;
+    }
+}
+
+fn main() {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        GameplayEffectsPlugin::<CharacterStats>::default(),
+        EffectTargetPlugin::<CharacterStats, MoveSpeed>::default(),
+    ));
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(500)));
+
+    let stats = GameplayStats::<CharacterStats>::new(|stat| match stat {
+        CharacterStats::Health => 100.,
+        CharacterStats::MoveSpeed => 6.,
+        CharacterStats::None => 0.,
+    });
+    let entity = app.world_mut().spawn((stats, ActiveEffects::<CharacterStats>::new([]), MoveSpeed(0.))).id();
+    app.update();
+
+    // Halve the movement speed for 2 seconds
+    let slow = GameplayEffect::new(
+        None,
+        CharacterStats::MoveSpeed,
+        EffectMagnitude::Fixed(0.5),
+        EffectCalculation::Multiplicative,
+        EffectDuration::persistent_for(2.),
+    );
+    app.world_mut().trigger(AddEffect(AddEffectData::new(entity, slow, None)));
+
+    for frame in 0..8 {
+        app.update();
+        let speed = app.world().get::<MoveSpeed>(entity).unwrap().0;
+        println!("t = {:.1}s  move speed {speed}", frame as f32 * 0.5);
+    }
+}
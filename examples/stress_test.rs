@@ -12,6 +12,11 @@ use bevy_gameplay_effects::prelude::*;
 
 const ENTITIES_TO_SPAWN: usize = 150_000;
 
+// Set to true to deal the damage through commands.damage instead of the AddEffect
+// observer, then compare the frame times reported by the diagnostics.  The instant_damage
+// bench measures the same comparison without rendering.
+const USE_INSTANT_DAMAGE: bool = false;
+
 stats! (
     CharacterStats {
         Health,
@@ -20,14 +25,6 @@ stats! (
     }
 );
 
-/// Unfortunately we have to feed in the variants as an array
-/// since rust can't infer them at compile time.
-const VARIANTS: [CharacterStats; 3] = [
-    CharacterStats::Health,
-    CharacterStats::HealthRegen,
-    CharacterStats::Strength
-];
-
 #[derive(Resource)]
 struct Tags {
     on_fire_tag: TagId,
@@ -59,6 +56,7 @@ fn main() {
         LogDiagnosticsPlugin::default(),
         FrameTimeDiagnosticsPlugin::default(),
         GameplayEffectsPlugin::<CharacterStats>::new(stacking_behaviors),
+        GameplayEffectsDiagnosticsPlugin::<CharacterStats>::default(),
     ));
 
     app.add_systems(Startup, spawn_entities);
@@ -72,13 +70,13 @@ fn main() {
 }
 
 fn spawn_entities(mut commands: Commands) {
-    let active_effects = ActiveEffects::new([
+    let active_effects = ActiveEffects::<CharacterStats>::new([
         GameplayEffect::new(
             None,
             CharacterStats::Health,
             EffectMagnitude::Fixed(0.),
             EffectCalculation::LowerBound,
-            EffectDuration::Persistent(None),
+            EffectDuration::persistent(),
         ),
     ]);
     let stats = GameplayStats::new(
@@ -92,6 +90,7 @@ fn spawn_entities(mut commands: Commands) {
         }
     );
 
+    // ActiveTags is inserted by the plugin when ActiveEffects is added
     commands.spawn_batch((0..ENTITIES_TO_SPAWN).map(
         move |_| {
             (
@@ -111,14 +110,19 @@ fn do_some_effects(
         CharacterStats::Health,
         EffectMagnitude::LocalStat(CharacterStats::Strength, StatScalingParams{multiplier: -1.0, ..default()}),
         EffectCalculation::Additive,
-        EffectDuration::Immediate,
+        EffectDuration::immediate(),
     );
 
     for entity in entities {
         // Take some damage
-        commands.trigger(AddEffect(AddEffectData::new(
-            entity, damage_effect.clone(), None
-        )));
+        if USE_INSTANT_DAMAGE {
+            // Same as the effect above since every entity has 5 Strength
+            commands.damage(entity, CharacterStats::Health, 5.);
+        } else {
+            commands.trigger(AddEffect(AddEffectData::new(
+                entity, damage_effect.clone(), None
+            )));
+        }
     }
 }
 
@@ -138,7 +142,7 @@ fn check_deaths(
         CharacterStats::Health,
         EffectMagnitude::Fixed(100.0),
         EffectCalculation::Additive,
-        EffectDuration::Continuous(Some(5.0.into())),
+        EffectDuration::continuous(5.),
     );
     for event in events.read() {
         if event.0.stat == CharacterStats::Health && event.0.bound == EffectCalculation::LowerBound {
@@ -0,0 +1,69 @@
+use bevy::prelude::*;
+use bevy_hierarchical_tags::TagId;
+use crate::prelude::*;
+
+/// What changed a stat in a StatMutation
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MutationCause {
+    /// An effect or ApplyInstant applied to the stat's current value
+    Application,
+    /// Persistent effects were added or removed, or a stat the value depends on changed
+    Recalculation,
+    Regen,
+}
+
+/// One change to a stat's current value, handed to the AuditSink
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StatMutation<T: StatTrait> {
+    pub entity: Entity,
+    pub stat: T,
+    pub old: f32,
+    pub new: f32,
+    /// Tag and source of the effect applied, or added or removed for a recalculation, None for regen
+    /// and for recalculations no effect triggered, e.g. a StatFormulas derivation
+    pub tag: Option<TagId>,
+    pub source: Option<Entity>,
+    pub cause: MutationCause,
+}
+
+/// Optional callback receiving every stat mutation made by the effect systems, e.g. for server side
+/// validation or logging.  Applications are reported even when bounds leave the value unchanged,
+/// recalculations only for the stats whose current value moved.
+#[derive(Resource)]
+pub struct AuditSink<T: StatTrait>(Box<dyn Fn(&StatMutation<T>) + Send + Sync>);
+
+impl<T: StatTrait> AuditSink<T> {
+    pub fn new(f: impl Fn(&StatMutation<T>) + Send + Sync + 'static) -> Self {
+        Self(Box::new(f))
+    }
+
+    pub(crate) fn record(&self, mutation: StatMutation<T>) {
+        (self.0)(&mutation);
+    }
+
+    /// Report every stat whose current value differs between the two snapshots, attributed to the
+    /// triggering effect on that stat, or else the first one, e.g. for a capped stat
+    pub(crate) fn record_changes(
+        &self,
+        entity: Entity,
+        before: &GameplayStats<T>,
+        after: &GameplayStats<T>,
+        triggers: &[GameplayEffect<T>],
+        cause: MutationCause,
+    ) {
+        for &stat in T::variants() {
+            let (Some(old), Some(new)) = (before.try_get(stat), after.try_get(stat)) else { continue };
+            if old.current_value == new.current_value { continue }
+            let trigger = triggers.iter().find(|effect| effect.stat_target == stat).or(triggers.first());
+            self.record(StatMutation {
+                entity,
+                stat,
+                old: old.current_value,
+                new: new.current_value,
+                tag: trigger.and_then(|effect| effect.tag),
+                source: trigger.and_then(|effect| effect.source_entity),
+                cause,
+            });
+        }
+    }
+}
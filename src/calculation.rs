@@ -1,5 +1,8 @@
-use crate::prelude::*;
-use bevy::prelude::*;
+use std::{collections::HashMap, fmt, marker::PhantomData, sync::Arc};
+use smallvec::SmallVec;
+use crate::{prelude::*, gameplay_stats::{Bound, STAT_LIMIT}, headless::{base_magnitude, final_amount, apply_amount, combine_bounds, rebase}};
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_hierarchical_tags::{TagId, TagRegistry};
 
 
 #[derive(Default, Copy, Clone)]
@@ -9,6 +12,18 @@ pub enum StackingPolicy {
     NoStackingResetDuration,
     MultipleEffects(u8),
     MultipleEffectsResetDurations(u8),
+    /// Fold a Fixed effect into a stored one on the same stat with the same calculation and duration,
+    /// summing Additive and multiplying Multiplicative magnitudes.  Otherwise it is stored separately.
+    Merge,
+    /// Keep only the strongest effect, comparing how far a new effect's amount is from its calculation's
+    /// identity with the stored ones, i.e. |x| for Additive and |x - 1| for Multiplicative.
+    /// A stronger one replaces them, a weaker or equal one is blocked, e.g. only the best haste applies.
+    KeepStrongest,
+    /// Stack up to max_stacks while the stacks' combined remaining time stays within max_total_secs.
+    /// Otherwise the stored stacks are topped back up towards the new duration as far as the time cap allows,
+    /// and the effect is blocked as Refreshed, or as StackLimit if there was nothing to top up.  Effects
+    /// without a timer only count towards max_stacks.
+    CappedDurationStacks { max_stacks: u8, max_total_secs: f32 },
 }
 
 #[derive(Clone, PartialEq)]
@@ -17,9 +32,53 @@ pub enum EffectMagnitude<T: StatTrait> {
     Fixed(f32),
     LocalStat(T, StatScalingParams),
     NonlocalStat(T, StatScalingParams, Entity),
+    WeightedTable(Vec<(f32, f32)>), // (weight, value) pairs, rolled once when the effect is added
+    /// `below` while the target's stat is under threshold * its reference stat, otherwise `above`
+    Piecewise { stat: T, reference: T, threshold: f32, below: f32, above: f32 },
+    /// base + per_entity for each candidate carrying the tag, e.g. an aura empowered by nearby allies.
+    /// The candidates come from the caller, e.g. a spatial query, and are counted once when the effect is added.
+    TagCount { tag: TagId, candidates: Vec<Entity>, base: f32, per_entity: f32 },
+    /// Per-stack value indexed by the number of active effects sharing the tag, the last entry
+    /// covering any higher count.  Re-evaluated whenever a stack is added or removed.
+    StackTable(Vec<f32>),
+    /// a.1 * a.0 + b.1 * b.0 passed through scaling, e.g. 50% of Strength plus 50% of Intelligence.
+    /// Reads the target's stats, or source's like NonlocalStat when set.
+    Blend { a: (T, f32), b: (T, f32), scaling: StatScalingParams, source: Option<Entity> },
+    /// base scaled down by the curve for the distance between source and target, e.g. an explosion.
+    /// The caller measures the distance from its own transforms or physics when building the effect.
+    Falloff { base: f32, distance: f32, curve: FalloffCurve },
 }
 
-#[derive(Clone, PartialEq)]
+impl<T: StatTrait> EffectMagnitude<T> {
+    /// fraction of the target's stat, e.g. percent_of(HealthMax, 0.3) for 30% of max health
+    pub fn percent_of(stat: T, fraction: f32) -> Self {
+        EffectMagnitude::LocalStat(stat, StatScalingParams { multiplier: fraction, ..default() })
+    }
+
+    /// Other entity whose stats the magnitude reads, for NonlocalStat and Blend with a source
+    pub(crate) fn source_entity(&self) -> Option<Entity> {
+        match self {
+            EffectMagnitude::NonlocalStat(_, _, entity) | EffectMagnitude::Blend { source: Some(entity), .. } => Some(*entity),
+            _ => None,
+        }
+    }
+}
+
+/// User-defined calculation for EffectCalculation::Custom
+pub trait CalculationFn: Send + Sync {
+    /// New current value of the stat after applying amount to it
+    fn apply(&self, current: f32, amount: f32) -> f32;
+
+    /// Whether persistent effects fold into the modified base during recalculation, after
+    /// additive and multiplicative effects.  Otherwise persistent effects using it are ignored.
+    fn affects_base(&self) -> bool {
+        false
+    }
+}
+
+/// Custom shares its calculation through an Arc, so one built at runtime, e.g. from mod data, is
+/// wrapped with Arc::new.  Two Custom calculations are equal when they point to the same instance.
+#[derive(Clone)]
 pub enum EffectCalculation {
     None,
     Additive,
@@ -27,6 +86,52 @@ pub enum EffectCalculation {
     SetValue,
     LowerBound,
     UpperBound,
+    Custom(Arc<dyn CalculationFn>),
+}
+
+impl EffectCalculation {
+    /// Effects applying in the same frame run additive first, then multiplicative, then custom, then SetValue
+    pub(crate) fn phase(&self) -> u8 {
+        match self {
+            EffectCalculation::Additive => 0,
+            EffectCalculation::Multiplicative => 1,
+            EffectCalculation::Custom(_) => 2,
+            EffectCalculation::SetValue => 3,
+            _ => 4,
+        }
+    }
+
+    /// How far an amount moves a stat away from this calculation's identity, e.g. a 0.5
+    /// Multiplicative slow is stronger than a 0.8 one.  Used by StackingPolicy::KeepStrongest.
+    pub(crate) fn strength(&self, amount: f32) -> f32 {
+        match self {
+            EffectCalculation::Multiplicative => (amount - 1.).abs(),
+            _ => amount.abs(),
+        }
+    }
+}
+
+impl PartialEq for EffectCalculation {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (EffectCalculation::Custom(a), EffectCalculation::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl fmt::Debug for EffectCalculation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EffectCalculation::None => "None",
+            EffectCalculation::Additive => "Additive",
+            EffectCalculation::Multiplicative => "Multiplicative",
+            EffectCalculation::SetValue => "SetValue",
+            EffectCalculation::LowerBound => "LowerBound",
+            EffectCalculation::UpperBound => "UpperBound",
+            EffectCalculation::Custom(_) => "Custom",
+        })
+    }
 }
 
 #[derive(Clone, PartialEq)]
@@ -59,6 +164,18 @@ impl StatScalingParams {
             out = out.powf(self.exponent);
         }
         out = self.shift + self.multiplier * out;
+        // powf on a negative base with a fractional exponent gives NaN, large exponents overflow to Inf
+        if !out.is_finite() {
+            #[cfg(debug_assertions)]
+            warn!("StatScalingParams produced {} from stat value {}, sanitizing", out, stat);
+            out = if out.is_nan() {
+                self.min.unwrap_or(0.)
+            } else if out > 0. {
+                f32::MAX
+            } else {
+                f32::MIN
+            };
+        }
         if let Some(min) = self.min {
             out = f32::max(min, out);
         }
@@ -69,6 +186,372 @@ impl StatScalingParams {
     }
 }
 
+/// How a LowerBound or UpperBound effect resolves a stat based magnitude
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BoundMode {
+    /// Resolved once when the effect is added
+    Snapshot,
+    /// Follows the reference stat for the life of the effect
+    #[default]
+    Live,
+}
+
+/// Scales continuous and repeating effects by how much of their duration is left.
+/// The multiplier moves linearly from `start` when applied to `end` at expiry.
+#[derive(Clone, Copy, PartialEq)]
+pub struct DurationScaling {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl DurationScaling {
+    pub(crate) fn apply(&self, fraction_remaining: f32) -> f32 {
+        self.end + (self.start - self.end) * fraction_remaining
+    }
+}
+
+/// How a Falloff magnitude shrinks with distance
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FalloffCurve {
+    /// Full strength at distance 0, falling linearly to nothing at radius
+    Linear { radius: f32 },
+    /// Inverse square of the distance, at full strength within min_distance
+    InverseSquare { min_distance: f32 },
+}
+
+impl FalloffCurve {
+    pub fn factor(&self, distance: f32) -> f32 {
+        match *self {
+            FalloffCurve::Linear { radius } if radius > 0. => (1. - distance / radius).clamp(0., 1.),
+            FalloffCurve::Linear { .. } => 0.,
+            FalloffCurve::InverseSquare { min_distance } => {
+                let ratio = min_distance / distance.max(min_distance);
+                if ratio.is_finite() { ratio * ratio } else { 1. }
+            },
+        }
+    }
+}
+
+/// Routes a fraction of the damage an effect deals to another stat, possibly on another entity, as a gain,
+/// e.g. lifesteal healing the source for 20% of the damage dealt is a fraction of 0.2.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Leech<T: StatTrait> {
+    pub stat: T,
+    pub entity: Entity,
+    pub fraction: f32,
+}
+
+/// Seedable random number generator used to sample weighted magnitude tables
+#[derive(Resource, Clone)]
+pub struct EffectRng(u64);
+
+impl Default for EffectRng {
+    fn default() -> Self {
+        Self::seeded(0)
+    }
+}
+
+impl EffectRng {
+    pub fn seeded(seed: u64) -> Self {
+        // xorshift gets stuck on a zero state
+        let state = seed ^ 0x9E37_79B9_7F4A_7C15;
+        Self(if state == 0 { 1 } else { state })
+    }
+
+    /// Uniform sample in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let x = self.0.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        (x >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniformly chosen element, None for an empty slice
+    pub fn pick<V: Copy>(&mut self, items: &[V]) -> Option<V> {
+        if items.is_empty() { return None }
+        let index = (self.next_f32() * items.len() as f32) as usize;
+        Some(items[index.min(items.len() - 1)])
+    }
+}
+
+/// Pick a value from a (weight, value) table. Weights are normalized, an empty table gives 0.
+pub(crate) fn sample_weighted(table: &[(f32, f32)], rng: &mut EffectRng) -> f32 {
+    let total: f32 = table.iter().map(|(w, _)| f32::max(*w, 0.)).sum();
+    if total <= 0. { return 0. }
+    let mut roll = rng.next_f32() * total;
+    for (weight, value) in table.iter() {
+        let weight = f32::max(*weight, 0.);
+        if roll < weight {
+            return *value;
+        }
+        roll -= weight;
+    }
+    // Float rounding can leave a tiny remainder, fall back to the last weighted entry
+    table.iter().rev()
+        .find(|(w, _)| *w > 0.)
+        .map_or(0., |(_, v)| *v)
+}
+
+/// Maps a stat to another stat on the same entity which scales incoming reductions to it,
+/// e.g. a Vulnerability stat of 2 doubles all damage applied to Health
+#[derive(Resource, Clone)]
+pub struct IncomingMultipliers<T: StatTrait>([Option<T>; STAT_LIMIT]);
+
+impl<T: StatTrait> Default for IncomingMultipliers<T> {
+    fn default() -> Self {
+        Self([None; STAT_LIMIT])
+    }
+}
+
+impl<T: StatTrait> IncomingMultipliers<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn scale(mut self, stat: T, multiplier_stat: T) -> Self {
+        if let Some(slot) = stat.slot() {
+            self.0[slot] = Some(multiplier_stat);
+        }
+        self
+    }
+
+    pub(crate) fn get(&self, stat: T) -> Option<T> {
+        stat.slot().and_then(|slot| self.0[slot])
+    }
+
+    /// The factor an amount of the effect is scaled by when applied to these stats, 1 unless it is damage
+    pub fn multiplier(&self, stats: &GameplayStats<T>, effect: &GameplayEffect<T>, amount: f32) -> f32 {
+        if amount >= 0. || effect.calculation != EffectCalculation::Additive { return 1. }
+        self.get(effect.stat_target)
+            .and_then(|m| stats.try_get(m))
+            .map_or(1., |m| m.current_value)
+    }
+}
+
+/// Maps a stat to another stat on the same entity whose current value caps it, e.g. HealthMax
+/// for Health.  The capped stat is clamped again whenever its max stat is recalculated.
+#[derive(Resource, Clone)]
+pub struct StatCaps<T: StatTrait>([Option<T>; STAT_LIMIT]);
+
+impl<T: StatTrait> Default for StatCaps<T> {
+    fn default() -> Self {
+        Self([None; STAT_LIMIT])
+    }
+}
+
+impl<T: StatTrait> StatCaps<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cap(mut self, stat: T, max_stat: T) -> Self {
+        if let Some(slot) = stat.slot() {
+            self.0[slot] = Some(max_stat);
+        }
+        self
+    }
+
+    pub(crate) fn get(&self, stat: T) -> Option<T> {
+        stat.slot().and_then(|slot| self.0[slot])
+    }
+}
+
+/// Stats effects may read but never change, e.g. Level or a faction id stored as a stat.  Effects
+/// targeting them are blocked with a warning.  StatFormulas still derive them.
+#[derive(Resource, Clone)]
+pub struct ImmutableStats<T: StatTrait>([bool; STAT_LIMIT], PhantomData<T>);
+
+impl<T: StatTrait> Default for ImmutableStats<T> {
+    fn default() -> Self {
+        Self([false; STAT_LIMIT], PhantomData)
+    }
+}
+
+impl<T: StatTrait> ImmutableStats<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn immutable(mut self, stat: T) -> Self {
+        if let Some(slot) = stat.slot() {
+            self.0[slot] = true;
+        }
+        self
+    }
+
+    pub fn contains(&self, stat: T) -> bool {
+        stat.slot().is_some_and(|slot| self.0[slot])
+    }
+
+    /// Whether an effect on the entity must leave the stat alone, warning each time
+    pub(crate) fn rejects(&self, entity: Entity, stat: T) -> bool {
+        let immutable = self.contains(stat);
+        if immutable {
+            warn!("Ignoring effect on entity {} targeting immutable stat {}", entity, Into::<usize>::into(stat));
+        }
+        immutable
+    }
+}
+
+/// Stat on the source entity which scales the Additive effects it applies, e.g. SpellPower
+#[derive(Resource, Clone)]
+pub struct OutgoingMultiplier<T: StatTrait>(pub Option<T>);
+
+impl<T: StatTrait> Default for OutgoingMultiplier<T> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<T: StatTrait> OutgoingMultiplier<T> {
+    pub fn new(stat: T) -> Self {
+        Self(Some(stat))
+    }
+}
+
+/// Damage school of an effect, e.g. Fire, Ice or Physical, numbered however the game likes
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SchoolId(pub u16);
+
+/// Global multipliers on the Additive effects of a school, e.g. 1.3 for all Fire damage +30%.
+/// Schools without a multiplier are unaffected.
+#[derive(Resource, Clone, Default)]
+pub struct SchoolModifiers(HashMap<SchoolId, f32>);
+
+impl SchoolModifiers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn multiplier(mut self, school: SchoolId, multiplier: f32) -> Self {
+        self.set(school, multiplier);
+        self
+    }
+
+    /// Change a school's multiplier at runtime, e.g. from a talent
+    pub fn set(&mut self, school: SchoolId, multiplier: f32) {
+        self.0.insert(school, multiplier);
+    }
+
+    pub fn get(&self, school: SchoolId) -> f32 {
+        self.0.get(&school).copied().unwrap_or(1.)
+    }
+}
+
+/// Fractional reductions to an incoming effect, e.g. 0.5 halves it
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct Resistance {
+    pub duration: f32,
+    pub magnitude: f32,
+}
+
+/// Resistances of an entity to effects by tag, e.g. tenacity against crowd control.
+/// A resistance to a tag also covers its descendants when a TagRegistry resource exists.
+#[derive(Component, Clone, Default)]
+pub struct Resistances(Vec<(TagId, Resistance)>);
+
+impl Resistances {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn resist(mut self, tag: TagId, resistance: Resistance) -> Self {
+        self.set(tag, resistance);
+        self
+    }
+
+    pub fn set(&mut self, tag: TagId, resistance: Resistance) {
+        match self.0.iter_mut().find(|(t, _)| *t == tag) {
+            Some((_, r)) => *r = resistance,
+            None => self.0.push((tag, resistance)),
+        }
+    }
+
+    pub fn remove(&mut self, tag: TagId) {
+        self.0.retain(|(t, _)| *t != tag);
+    }
+
+    /// Resistance for a tag, falling back to the nearest ancestor tag with one
+    pub fn get(&self, tag: TagId, registry: Option<&TagRegistry>) -> Option<Resistance> {
+        let mut current = Some(tag);
+        while let Some(tag) = current {
+            if let Some((_, resistance)) = self.0.iter().find(|(t, _)| *t == tag) {
+                return Some(*resistance);
+            }
+            current = registry.and_then(|r| r.parent(tag));
+        }
+        None
+    }
+}
+
+/// Running totals of stat changes applied by each source entity
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct LedgerEntry {
+    pub positive: f32, // e.g. healing
+    pub negative: f32, // e.g. damage, stored as a negative number
+}
+
+/// Tracks how much each source entity has changed each stat, for scoreboards and analytics.
+/// Only effects added with a source entity are recorded.
+#[derive(Resource)]
+pub struct DamageLedger<T: StatTrait>(HashMap<(Entity, usize), LedgerEntry>, PhantomData<T>);
+
+impl<T: StatTrait> Default for DamageLedger<T> {
+    fn default() -> Self {
+        Self(HashMap::new(), PhantomData)
+    }
+}
+
+impl<T: StatTrait> DamageLedger<T> {
+    pub fn get(&self, source: Entity, stat: T) -> LedgerEntry {
+        stat.slot()
+            .and_then(|slot| self.0.get(&(source, slot)).copied())
+            .unwrap_or_default()
+    }
+
+    /// Totals for every stat this source has changed
+    pub fn iter_source(&self, source: Entity) -> impl Iterator<Item = (T, LedgerEntry)> + '_ {
+        T::variants().iter()
+            .filter_map(move |&stat| Some((stat, *self.0.get(&(source, stat.slot()?))?)))
+    }
+
+    pub fn clear_source(&mut self, source: Entity) {
+        self.0.retain(|(entity, _), _| *entity != source);
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub(crate) fn record(&mut self, source: Entity, stat: T, delta: f32) {
+        let Some(slot) = stat.slot() else { return };
+        let entry = self.0.entry((source, slot)).or_default();
+        if delta > 0. {
+            entry.positive += delta;
+        } else {
+            entry.negative += delta;
+        }
+    }
+}
+
+/// Resources shared by the effect calculations, and the writers for tag changes made along the way
+#[derive(SystemParam)]
+pub(crate) struct EffectContext<'w, 's, T: StatTrait> {
+    pub(crate) rng: ResMut<'w, EffectRng>,
+    pub(crate) incoming: Res<'w, IncomingMultipliers<T>>,
+    pub(crate) outgoing: Res<'w, OutgoingMultiplier<T>>,
+    pub(crate) schools: Res<'w, SchoolModifiers>,
+    pub(crate) caps: Res<'w, StatCaps<T>>,
+    pub(crate) immutable: Res<'w, ImmutableStats<T>>,
+    pub(crate) ledger: ResMut<'w, DamageLedger<T>>,
+    pub(crate) settings: Res<'w, GameplayEffectsSettings>,
+    pub(crate) audit: Option<Res<'w, AuditSink<T>>>,
+    pub(crate) tag_added_writer: MessageWriter<'w, OnTagAdded>,
+    pub(crate) tag_removed_writer: MessageWriter<'w, OnTagRemoved>,
+    pub(crate) commands: Commands<'w, 's>,
+}
+
 /// Apply changes to a stat's current value
 #[inline]
 pub(crate) fn apply_immediate<T: StatTrait> (
@@ -76,85 +559,253 @@ pub(crate) fn apply_immediate<T: StatTrait> (
     effect: &GameplayEffect<T>, 
     stats_query: &mut Query<&mut GameplayStats<T>>,
     amount: f32,
-    effects:&ActiveEffects<T>,
+    effects: &[GameplayEffect<T>],
+    ctx: &mut EffectContext<T>,
 ) -> Option<OnBoundsBreached<T>> {
-    if effect.stat_target.into() == u8::MAX { return None }
-    let (upper_bound, lower_bound) = get_bounds(entity, effect.stat_target, effects, stats_query);
-    let mut stats = stats_query.get_mut(entity).expect("Missing GameplayStats component");
-    let stat = stats.get_mut(effect.stat_target);
-
-    match &effect.calculation {
-        EffectCalculation::Additive => { stat.current_value += amount },
-        EffectCalculation::Multiplicative => { stat.current_value *= amount },
-        EffectCalculation::SetValue => { stat.current_value = amount },
-        _ => { }
-    }
-    if stat.current_value >= upper_bound {
-        stat.current_value = upper_bound;
-        Some(OnBoundsBreached(BoundsBreachedMetadata::new(entity, effect.stat_target, EffectCalculation::UpperBound)))
-    } else if stat.current_value <= lower_bound {
-        stat.current_value = lower_bound;
-        Some(OnBoundsBreached(BoundsBreachedMetadata::new(entity, effect.stat_target, EffectCalculation::LowerBound)))
-    } else { None }
+    if effect.stat_target == T::NONE || ctx.immutable.contains(effect.stat_target) { return None }
+    let (upper_bound, lower_bound) = get_bounds(entity, effect.stat_target, effects, stats_query, ctx);
+    let mut stats = stats_query.get_mut(entity).ok()?;
+    let previous = apply_amount(&mut stats, effect, amount, &ctx.incoming)?;
+    let stat = stats.try_get_mut(effect.stat_target)?;
+    let breached = clamp_current(entity, effect.stat_target, stat, upper_bound, lower_bound, ctx.settings.bound_epsilon);
+    if let Some(audit) = &ctx.audit {
+        audit.record(StatMutation {
+            entity,
+            stat: effect.stat_target,
+            old: previous,
+            new: stat.current_value,
+            tag: effect.tag,
+            source: effect.source_entity,
+            cause: MutationCause::Application,
+        });
+    }
+
+    let delta = stat.current_value - previous;
+    if let Some(source) = effect.source_entity {
+        if delta != 0. {
+            ctx.ledger.record(source, effect.stat_target, delta);
+        }
+    }
+    if let Some(leech) = effect.leech {
+        if delta < 0. {
+            ctx.commands.trigger(ApplyInstant {
+                target_entity: leech.entity,
+                stat: leech.stat,
+                amount: -delta * leech.fraction,
+                source_entity: effect.source_entity,
+            });
+        }
+    }
+    breached
 }
 
 /// After persistent effects are added/removed recalulate base and current stat values
 #[inline]
 pub(crate) fn recalculate_stats<T: StatTrait>(
     entity: Entity,
-    effects: &Mut<ActiveEffects<T>>,
+    effects: &[GameplayEffect<T>],
+    stat_target: T, 
+    triggers: &[GameplayEffect<T>],
+    stats_query: &mut Query<&mut GameplayStats<T>>,
+    ctx: &mut EffectContext<T>,
+) -> Option<OnBoundsBreached<T>> {
+    recalculate_stats_multi(entity, effects, &[stat_target], triggers, stats_query, ctx).into_iter().next()
+}
+
+/// Recalculate several stats of one entity, e.g. after removing an effect group touching several stats.
+/// Each stat is recomputed once even if listed twice, the audit snapshot is taken once and every breach is
+/// returned.  The stats are still recomputed in turn, each looking the entity up again, since a stat's
+/// bounds and magnitudes may read a stat recomputed before it.
+/// Triggers are the effects added or removed, which the audit records name.
+pub(crate) fn recalculate_stats_multi<T: StatTrait>(
+    entity: Entity,
+    effects: &[GameplayEffect<T>],
+    stat_targets: &[T],
+    triggers: &[GameplayEffect<T>],
+    stats_query: &mut Query<&mut GameplayStats<T>>,
+    ctx: &mut EffectContext<T>,
+) -> SmallVec<[OnBoundsBreached<T>; 2]> {
+    let mut breaches = SmallVec::new();
+    let Ok(stats) = stats_query.get(entity) else { return breaches };
+    let before = ctx.audit.as_ref().map(|_| stats.clone());
+    for (i, &stat_target) in stat_targets.iter().enumerate() {
+        if stat_targets[..i].contains(&stat_target) { continue }
+        let breached = if is_unmodified(entity, effects, stat_target, stats_query) {
+            clamp_unmodified(entity, effects, stat_target, stats_query, ctx)
+        } else if ctx.settings.full_recalculation {
+            recalculate_stats_full(entity, effects, stat_target, stats_query, ctx)
+        } else {
+            recalculate_stats_incremental(entity, effects, stat_target, stats_query, ctx)
+        };
+        breaches.extend(breached.or(clamp_capped_stats(entity, effects, stat_target, stats_query, ctx)));
+    }
+    if let (Some(audit), Some(before), Ok(after)) = (&ctx.audit, before, stats_query.get(entity)) {
+        audit.record_changes(entity, &before, after, triggers, MutationCause::Recalculation);
+    }
+    breaches
+}
+
+/// No persistent effect targets the stat and none changed its modified base before, so recalculating
+/// would only rescale the current value by 1 and risk float noise.  Tracked effects count as unmodified
+/// too, they changed the current value once and are never part of the modified base.
+fn is_unmodified<T: StatTrait>(
+    entity: Entity,
+    effects: &[GameplayEffect<T>],
+    stat_target: T,
+    stats_query: &Query<&mut GameplayStats<T>>,
+) -> bool {
+    !effects.iter().any(|e| e.stat_target == stat_target && matches!(e.duration, EffectDuration::Persistent(_)))
+        && stats_query.get(entity).ok()
+            .and_then(|stats| stats.try_get(stat_target))
+            .is_some_and(|stat| stat.modified_base == stat.base_value)
+}
+
+/// Leave an unmodified stat's values alone, only enforcing its cap
+fn clamp_unmodified<T: StatTrait>(
+    entity: Entity,
+    effects: &[GameplayEffect<T>],
+    stat_target: T,
+    stats_query: &mut Query<&mut GameplayStats<T>>,
+    ctx: &mut EffectContext<T>,
+) -> Option<OnBoundsBreached<T>> {
+    let (upper_bound, lower_bound) = get_bounds(entity, stat_target, effects, stats_query, ctx);
+    let mut stats = stats_query.get_mut(entity).ok()?;
+    let stat = stats.try_get_mut(stat_target)?;
+    clamp_current(entity, stat_target, stat, upper_bound, lower_bound, ctx.settings.bound_epsilon)
+}
+
+/// Clamp the current values of the stats whose StatCaps max stat is max_stat
+fn clamp_capped_stats<T: StatTrait>(
+    entity: Entity,
+    effects: &[GameplayEffect<T>],
+    max_stat: T,
+    stats_query: &mut Query<&mut GameplayStats<T>>,
+    ctx: &mut EffectContext<T>,
+) -> Option<OnBoundsBreached<T>> {
+    let mut breached = None;
+    for &stat_target in T::variants() {
+        if ctx.caps.get(stat_target) != Some(max_stat) { continue }
+        let (upper_bound, lower_bound) = get_bounds(entity, stat_target, effects, stats_query, ctx);
+        let Ok(mut stats) = stats_query.get_mut(entity) else { return breached };
+        let Some(stat) = stats.try_get_mut(stat_target) else { continue };
+        let e = clamp_current(entity, stat_target, stat, upper_bound, lower_bound, ctx.settings.bound_epsilon);
+        breached = breached.or(e);
+    }
+    breached
+}
+
+/// Rescale the current value by how much the modified base changed
+pub(crate) fn recalculate_stats_incremental<T: StatTrait>(
+    entity: Entity,
+    effects: &[GameplayEffect<T>],
     stat_target: T, 
     stats_query: &mut Query<&mut GameplayStats<T>>,
+    ctx: &mut EffectContext<T>,
 ) -> Option<OnBoundsBreached<T>> {
-    if stat_target.into() == u8::MAX {
+    if stat_target == T::NONE || !stats_query.contains(entity) {
         return None;
     }
     let mut additive: f32 = 0.;
     let mut multiplicative: f32 = 1.;
+    let mut custom = SmallVec::<[(&dyn CalculationFn, f32); 2]>::new();
 
-    for effect in effects.0.iter() {
+    // Only persistent effects are part of the modified base, as in recalculate_stats_full.  The others,
+    // Tracked included, changed the current value when they applied.
+    let persistent = effects.iter()
+        .filter(|e| e.stat_target == stat_target && matches!(e.duration, EffectDuration::Persistent(_)));
+    for effect in persistent {
         let source = get_effect_source_stats(effect, entity, stats_query);
-        let amount = get_effect_amount(effect, source);
-        
-        if effect.stat_target == stat_target {
-            match effect.calculation {
-                EffectCalculation::Additive => { additive += amount },
-                EffectCalculation::Multiplicative => { multiplicative *= amount },
-                _ => { }
-            }
+        let amount = get_effect_amount(effect, source, stats_query, ctx);
+        match &effect.calculation {
+            EffectCalculation::Additive => { additive += amount },
+            EffectCalculation::Multiplicative => { multiplicative *= amount },
+            EffectCalculation::Custom(calculation) if calculation.affects_base() => { custom.push((calculation.as_ref(), amount)) },
+            _ => { }
         }
     }
 
-    let (upper_bound, lower_bound) = get_bounds(entity, stat_target, effects, stats_query);
-    let mut stats = stats_query.get_mut(entity)
-        .expect("No stats component found");
-    let stat = stats.get_mut(stat_target);
+    let (upper_bound, lower_bound) = get_bounds(entity, stat_target, effects, stats_query, ctx);
+    let mut stats = stats_query.get_mut(entity).ok()?;
+    let stat = stats.try_get_mut(stat_target)?;
     let prev_base = stat.modified_base;
-    let mut new_base = (stat.base_value + additive) * multiplicative;
+    let mut new_base = custom.iter()
+        .fold((stat.base_value + additive) * multiplicative, |base, (calculation, amount)| calculation.apply(base, *amount));
     new_base = f32::min(upper_bound, new_base);
     new_base = f32::max(lower_bound, new_base);
     stat.modified_base = new_base;
-    stat.current_value *= new_base / prev_base;
+    // Current keeps its proportion of the modified base.  Ratios compose, so this is independent of
+    // the order of buffs and direct base_value changes, but a zero base has no proportion to keep.
+    if prev_base != 0. {
+        stat.current_value *= new_base / prev_base;
+    } else {
+        stat.current_value += new_base;
+    }
+    clamp_current(entity, stat_target, stat, upper_bound, lower_bound, ctx.settings.bound_epsilon)
+}
+
+/// Rebuild the modified base from base_value and the persistent effects only, applying
+/// additive, then multiplicative, then custom, then SetValue effects and finally bounds.  The current
+/// value is restored as the same fraction of the modified base it was before.
+pub(crate) fn recalculate_stats_full<T: StatTrait>(
+    entity: Entity,
+    effects: &[GameplayEffect<T>],
+    stat_target: T, 
+    stats_query: &mut Query<&mut GameplayStats<T>>,
+    ctx: &mut EffectContext<T>,
+) -> Option<OnBoundsBreached<T>> {
+    if stat_target == T::NONE || !stats_query.contains(entity) {
+        return None;
+    }
+    let mut amounts = SmallVec::<[(EffectCalculation, f32); 4]>::new();
+    let persistent = effects.iter()
+        .filter(|e| e.stat_target == stat_target && matches!(e.duration, EffectDuration::Persistent(_)));
+    for effect in persistent {
+        let source = get_effect_source_stats(effect, entity, stats_query);
+        amounts.push((effect.calculation.clone(), get_effect_amount(effect, source, stats_query, ctx)));
+    }
+
+    let (upper_bound, lower_bound) = get_bounds(entity, stat_target, effects, stats_query, ctx);
+    let mut stats = stats_query.get_mut(entity).ok()?;
+    let stat = stats.try_get_mut(stat_target)?;
+    rebase(stat, amounts, upper_bound, lower_bound);
+    clamp_current(entity, stat_target, stat, upper_bound, lower_bound, ctx.settings.bound_epsilon)
+}
 
-    if stat.current_value >= upper_bound {
+pub(crate) fn clamp_current<T: StatTrait>(
+    entity: Entity,
+    stat_target: T,
+    stat: &mut GameplayStat,
+    upper_bound: f32,
+    lower_bound: f32,
+    epsilon: f32,
+) -> Option<OnBoundsBreached<T>> {
+    stat.at_bound = None;
+    let breached = if stat.current_value >= upper_bound - epsilon {
+        let overshoot = f32::max(stat.current_value - upper_bound, 0.);
+        stat.at_bound = Some(Bound::Upper);
         stat.current_value = upper_bound;
         Some(OnBoundsBreached(
             BoundsBreachedMetadata {
                 stat: stat_target,
                 bound: EffectCalculation::UpperBound,
                 target_entity: entity,
+                overshoot,
             }
         ))
-    } else if stat.current_value <= lower_bound {
+    } else if stat.current_value <= lower_bound + epsilon {
+        let overshoot = f32::max(lower_bound - stat.current_value, 0.);
+        stat.at_bound = Some(Bound::Lower);
         stat.current_value = lower_bound;
         Some(OnBoundsBreached(
             BoundsBreachedMetadata {
                 stat: stat_target,
                 bound: EffectCalculation::LowerBound,
                 target_entity: entity,
+                overshoot,
             }
         ))
-    } else { None }
+    } else { None };
+    breached
 }
 
 /// Get the magnitude of the effect on the stat
@@ -162,60 +813,73 @@ pub(crate) fn recalculate_stats<T: StatTrait>(
 pub(crate) fn get_effect_amount<T:StatTrait>(
     effect: &GameplayEffect<T>,
     source: Option<&GameplayStats<T>>,
+    stats_query: &Query<&mut GameplayStats<T>>,
+    ctx: &mut EffectContext<T>,
 )  -> f32 {
-    match &effect.magnitude {
-        EffectMagnitude::None => 0.,
-        EffectMagnitude::Fixed(x) => *x,
-        EffectMagnitude::LocalStat(stat, f) => {
-            let stats = source.unwrap();
-            f.apply(stats.get(*stat).current_value)
-        },
-        EffectMagnitude::NonlocalStat(stat, f, _) => {
-            let stats = source.unwrap();
-            f.apply(stats.get(*stat).current_value)
-        },
-    }
+    let magnitude = get_base_magnitude(effect, source, ctx);
+    scale_effect_amount(effect, magnitude, stats_query, ctx)
 }
 
+/// Magnitude before duration scaling, resistances and multipliers
+#[inline]
+pub(crate) fn get_base_magnitude<T: StatTrait>(
+    effect: &GameplayEffect<T>,
+    source: Option<&GameplayStats<T>>,
+    ctx: &mut EffectContext<T>,
+) -> f32 {
+    base_magnitude(effect, source, &mut ctx.rng)
+}
+
+#[inline]
+pub(crate) fn scale_effect_amount<T: StatTrait>(
+    effect: &GameplayEffect<T>,
+    magnitude: f32,
+    stats_query: &Query<&mut GameplayStats<T>>,
+    ctx: &mut EffectContext<T>,
+) -> f32 {
+    let outgoing = ctx.outgoing.0
+        .zip(effect.source_entity)
+        .and_then(|(stat, source_entity)| stats_query.get(source_entity).ok()?.try_get(stat).copied());
+    final_amount(effect, magnitude, &ctx.schools, outgoing.map(|multiplier| multiplier.current_value))
+}
+
+/// Combined (upper, lower) bounds of a stat.  The tightest bound of each kind wins, i.e. the
+/// minimum of the upper bounds and the maximum of the lower bounds.  If those overlap the upper bound wins.
 #[inline]
 pub(crate) fn get_bounds<T: StatTrait>(
     entity: Entity,
     stat_target: T,
-    effects: &ActiveEffects<T>,
+    effects: &[GameplayEffect<T>],
     stats_query: &mut Query<&mut GameplayStats<T>>,
+    ctx: &mut EffectContext<T>,
 ) -> (f32, f32) {
-    let mut ub = f32::MAX;
-    let mut lb = f32::MIN;
-
-    for effect in effects.iter().filter(|x| x.stat_target == stat_target) {
+    let cap = ctx.caps.get(stat_target)
+        .and_then(|max_stat| stats_query.get(entity).ok()?.try_get(max_stat).copied())
+        .map(|max| max.current_value);
+    let mut bounds = SmallVec::<[(EffectCalculation, f32); 2]>::new();
+    let bound_effects = effects.iter().filter(|x| {
+        x.stat_target == stat_target
+            && matches!(x.calculation, EffectCalculation::LowerBound | EffectCalculation::UpperBound)
+    });
+    for effect in bound_effects {
         let source = get_effect_source_stats(effect, entity, stats_query);
-        let amount = get_effect_amount(effect, source);
-        match effect.calculation {
-            EffectCalculation::LowerBound => {
-                lb = f32::max(lb, amount);
-            },
-            EffectCalculation::UpperBound => {
-                ub = f32::min(ub, amount);
-            },
-            _ => { }
-        }
+        bounds.push((effect.calculation.clone(), get_effect_amount(effect, source, stats_query, ctx)));
     }
-    (ub, lb)
+    combine_bounds(cap, bounds)
 }
 
 #[inline]
 pub(crate) fn get_effect_source_stats<'a, T: StatTrait>(
     effect: &GameplayEffect<T>,
     entity: Entity,
-    stats_query: &'a mut Query<&mut GameplayStats<T>>,
+    stats_query: &'a Query<&mut GameplayStats<T>>,
 ) -> Option<&'a GameplayStats<T>> {
+    // A source which is the target itself falls back to the target's stats like LocalStat
+    if let Some(source_entity) = effect.magnitude.source_entity().filter(|&source| source != entity) {
+        return stats_query.get(source_entity).ok();
+    }
     match &effect.magnitude {
-        EffectMagnitude::NonlocalStat(_, _, source_entity) => {
-            if let Ok(stats) = stats_query.get(*source_entity) {
-                return Some(stats)
-            } else { return None; }
-        },
-        EffectMagnitude::LocalStat(..) => return stats_query.get(entity).ok(),
-        _ => return None,
-    };
+        EffectMagnitude::LocalStat(..) | EffectMagnitude::NonlocalStat(..) | EffectMagnitude::Piecewise { .. } | EffectMagnitude::Blend { .. } => stats_query.get(entity).ok(),
+        _ => None,
+    }
 }
\ No newline at end of file
@@ -0,0 +1,55 @@
+use bevy::prelude::*;
+use crate::prelude::*;
+
+/// Despawns the entity once its stat breaches the given bound, e.g. Health reaching a LowerBound of 0.
+/// OnDeath fires at the breach and the entity is despawned after delay seconds, leaving time for a
+/// death animation.  While waiting the entity is marked as dying and further breaches are ignored.
+#[derive(Component, Clone)]
+pub struct DespawnOnBound<T: StatTrait> {
+    pub stat: T,
+    pub bound: EffectCalculation,
+    pub delay: f32,
+    pub(crate) remaining: Option<f32>,
+}
+
+impl<T: StatTrait> DespawnOnBound<T> {
+    pub fn new(stat: T, bound: EffectCalculation) -> Self {
+        Self { stat, bound, delay: 0., remaining: None }
+    }
+
+    pub fn with_delay(mut self, delay: f32) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Seconds left before the entity is despawned, if the bound has been breached
+    pub fn remaining(&self) -> Option<f32> {
+        self.remaining
+    }
+}
+
+/// Marks entities whose DespawnOnBound bound was breached and despawns them once their delay runs out
+pub(crate) fn despawn_on_bound<T: StatTrait>(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut breached_reader: MessageReader<OnBoundsBreached<T>>,
+    mut death_writer: MessageWriter<OnDeath<T>>,
+    mut query: Query<(Entity, &mut DespawnOnBound<T>)>,
+) {
+    for (_, mut despawn) in query.iter_mut() {
+        if let Some(remaining) = despawn.remaining.as_mut() {
+            *remaining -= time.delta_secs();
+        }
+    }
+    for event in breached_reader.read() {
+        let Ok((_, mut despawn)) = query.get_mut(event.target_entity) else { continue };
+        if despawn.remaining.is_some() || despawn.stat != event.stat || despawn.bound != event.bound { continue }
+        despawn.remaining = Some(despawn.delay);
+        death_writer.write(OnDeath { entity: event.target_entity, stat: event.stat });
+    }
+    for (entity, despawn) in query.iter() {
+        if despawn.remaining.is_some_and(|remaining| remaining <= 0.) {
+            commands.entity(entity).try_despawn();
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::{fmt::Debug, marker::PhantomData};
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts, EguiPrimaryContextPass};
+use crate::prelude::*;
+
+/// Optional egui window for inspecting the active effects, tags and stats of an entity.
+/// Requires the `debug_ui` feature and bevy_egui's EguiPlugin.
+pub struct EffectsDebugPlugin<T: StatTrait + Debug>(PhantomData<T>);
+
+impl<T: StatTrait + Debug> Default for EffectsDebugPlugin<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: StatTrait + Debug> Plugin for EffectsDebugPlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EffectsDebugSelection>();
+        app.add_systems(EguiPrimaryContextPass, draw_effects_debug::<T>);
+    }
+}
+
+/// Entity shown in the debug window, picked from the window or set directly
+#[derive(Resource, Default)]
+pub struct EffectsDebugSelection(pub Option<Entity>);
+
+fn draw_effects_debug<T: StatTrait + Debug>(
+    mut contexts: EguiContexts,
+    mut selection: ResMut<EffectsDebugSelection>,
+    entities: Query<(Entity, &ActiveEffects<T>, &ActiveTags, Option<&GameplayStats<T>>)>,
+) -> Result {
+    let ctx = contexts.ctx_mut()?;
+    egui::Window::new("Gameplay Effects").show(ctx, |ui| {
+        let selected_text = selection.0.map_or("None".to_string(), |entity| entity.to_string());
+        egui::ComboBox::from_label("Entity")
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                for (entity, ..) in entities.iter() {
+                    ui.selectable_value(&mut selection.0, Some(entity), entity.to_string());
+                }
+            });
+
+        let Some(entity) = selection.0 else { return };
+        let Ok((_, effects, tags, stats)) = entities.get(entity) else {
+            ui.label("Selected entity has no ActiveEffects");
+            return;
+        };
+
+        ui.collapsing("Stats", |ui| {
+            let Some(stats) = stats else {
+                ui.label("No GameplayStats");
+                return;
+            };
+            egui::Grid::new("stats").striped(true).show(ui, |ui| {
+                ui.strong("Stat");
+                ui.strong("Current");
+                ui.strong("Base");
+                ui.end_row();
+                for &stat in T::variants() {
+                    let Some(value) = stats.try_get(stat) else { continue };
+                    ui.label(format!("{:?}", stat));
+                    ui.label(format!("{:.2}", value.current_value));
+                    ui.label(format!("{:.2}", value.base_value));
+                    ui.end_row();
+                }
+            });
+        });
+
+        ui.collapsing(format!("Tags ({})", tags.len()), |ui| {
+            for tag in tags.iter() {
+                ui.label(format!("{}", **tag));
+            }
+        });
+
+        ui.collapsing(format!("Effects ({})", effects.len()), |ui| {
+            egui::Grid::new("effects").striped(true).show(ui, |ui| {
+                ui.strong("Tag");
+                ui.strong("Stat");
+                ui.strong("Calculation");
+                ui.strong("Remaining");
+                ui.end_row();
+                for effect in effects.iter() {
+                    let summary = EffectSummary::from(effect);
+                    ui.label(summary.tag.map_or("-".to_string(), |tag| format!("{}", *tag)));
+                    ui.label(format!("{:?}", summary.stat_target));
+                    ui.label(calculation_label(&summary.calculation));
+                    ui.label(summary.remaining.map_or("-".to_string(), |secs| format!("{:.1}s", secs)));
+                    ui.end_row();
+                }
+            });
+        });
+    });
+    Ok(())
+}
+
+fn calculation_label(calculation: &EffectCalculation) -> &'static str {
+    match calculation {
+        EffectCalculation::Additive => "Additive",
+        EffectCalculation::Multiplicative => "Multiplicative",
+        EffectCalculation::LowerBound => "LowerBound",
+        EffectCalculation::UpperBound => "UpperBound",
+        EffectCalculation::SetValue => "SetValue",
+        EffectCalculation::None => "None",
+        EffectCalculation::Custom(_) => "Custom",
+    }
+}
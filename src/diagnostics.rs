@@ -0,0 +1,33 @@
+use std::marker::PhantomData;
+use bevy::{diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic}, prelude::*};
+use crate::{prelude::*, effects::ACTIVE_EFFECTS_SIZE};
+
+/// Optional plugin reporting how many entities have outgrown the inline
+/// capacity of ActiveEffects and spilled their effects to the heap.
+pub struct GameplayEffectsDiagnosticsPlugin<T: StatTrait, const N: usize = ACTIVE_EFFECTS_SIZE>(PhantomData<T>);
+
+impl<T: StatTrait, const N: usize> Default for GameplayEffectsDiagnosticsPlugin<T, N> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: StatTrait, const N: usize> GameplayEffectsDiagnosticsPlugin<T, N> {
+    pub const SPILLED_ACTIVE_EFFECTS: DiagnosticPath = DiagnosticPath::const_new("gameplay_effects/spilled_active_effects");
+}
+
+impl<T: StatTrait, const N: usize> Plugin for GameplayEffectsDiagnosticsPlugin<T, N> {
+    fn build(&self, app: &mut App) {
+        app.register_diagnostic(Diagnostic::new(Self::SPILLED_ACTIVE_EFFECTS));
+        app.add_systems(Update, count_spilled_active_effects::<T, N>.after(GameplayEffectsSystemSet));
+    }
+}
+
+fn count_spilled_active_effects<T: StatTrait, const N: usize>(
+    mut diagnostics: Diagnostics,
+    effects_query: Query<&ActiveEffects<T, N>>,
+) {
+    diagnostics.add_measurement(&GameplayEffectsDiagnosticsPlugin::<T, N>::SPILLED_ACTIVE_EFFECTS, || {
+        effects_query.iter().filter(|effects| effects.0.spilled()).count() as f64
+    });
+}
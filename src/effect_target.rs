@@ -0,0 +1,38 @@
+use std::marker::PhantomData;
+use bevy::{ecs::component::Mutable, prelude::*};
+use crate::prelude::*;
+
+/// A component effects write through to, e.g. a movement speed kept outside GameplayStats.
+/// Effects target the backing stat as usual, with bounds, stacking and timers, and the
+/// stat's current value is written into the component whenever it changes.
+pub trait EffectTarget<T: StatTrait>: Component<Mutability = Mutable> {
+    /// Stat whose current value drives this component
+    fn stat() -> T;
+
+    fn write(&mut self, value: f32);
+}
+
+/// Registers C as an effect target, writing its stat into it after the effect systems run
+pub struct EffectTargetPlugin<T: StatTrait, C: EffectTarget<T>>(PhantomData<(T, C)>);
+
+impl<T: StatTrait, C: EffectTarget<T>> Default for EffectTargetPlugin<T, C> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: StatTrait, C: EffectTarget<T>> Plugin for EffectTargetPlugin<T, C> {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, write_effect_target::<T, C>.after(GameplayEffectsSystemSet));
+    }
+}
+
+fn write_effect_target<T: StatTrait, C: EffectTarget<T>>(
+    mut query: Query<(&GameplayStats<T>, &mut C), Or<(Changed<GameplayStats<T>>, Added<C>)>>,
+) {
+    for (stats, mut target) in query.iter_mut() {
+        if let Some(stat) = stats.try_get(C::stat()) {
+            target.write(stat.current_value);
+        }
+    }
+}
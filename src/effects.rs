@@ -1,17 +1,97 @@
-use bevy::prelude::*;
+use std::{collections::HashMap, marker::PhantomData, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+use bevy::{ecs::{component::Tick, entity::Entities, system::SystemParam}, prelude::*};
 use bevy_hierarchical_tags::prelude::*;
 use smallvec::SmallVec;
 use crate::{
     prelude::*,
-    calculation::{apply_immediate, get_effect_amount, get_effect_source_stats, recalculate_stats},
+    calculation::{apply_immediate, get_effect_amount, get_base_magnitude, scale_effect_amount, sample_weighted, EffectContext, get_effect_source_stats, recalculate_stats, recalculate_stats_multi},
     events::EffectMetadata,
-    timing::SmallTimer, StackingBehaviors
+    headless::{stack_effect, stack_value, in_stack},
+    timing::{SmallTimer, FixedStepClock}, ProcessingBudget, StackingBehaviors, MutexGroups, Suppressions, SuppressionMode
 };
 
-const ACTIVE_EFFECTS_SIZE: usize = 24;
-const ACTIVE_TAGS_SIZE: usize = 32;
+pub(crate) const ACTIVE_EFFECTS_SIZE: usize = 24;
+pub(crate) const ACTIVE_TAGS_SIZE: usize = 32;
 
 
+/// Stable identifier for an effect instance which survives serialization, for netcode
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct NetEffectId(pub u64);
+
+/// Predicate gating a continuous or repeating effect each frame, given the target entity.
+/// Not serializable, so it must be reattached after replication.
+#[derive(Clone)]
+pub struct EffectCondition(pub Arc<dyn Fn(Entity, &World) -> bool + Send + Sync>);
+
+/// Opaque data an effect forwards to user systems through OnEffectPayload, e.g. a knockback impulse
+/// for the physics engine.  Read it back with payload.downcast_ref::<Knockback>().
+#[derive(Clone, Deref)]
+pub struct EffectPayload(pub Arc<dyn Reflect>);
+
+/// Per-entity checks run on every AddEffect targeting the entity before anything is applied,
+/// e.g. for scripts.  If any returns false the effect is blocked with EffectBlockedReason::Vetoed.
+/// Observers on AddEffect cannot do this since they run in no particular order and their commands are deferred.
+#[derive(Component, Clone)]
+pub struct EffectVetoes<T: StatTrait>(Vec<Arc<dyn Fn(&AddEffectData<T>) -> bool + Send + Sync>>);
+
+impl<T: StatTrait> Default for EffectVetoes<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: StatTrait> EffectVetoes<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn veto(mut self, allow: impl Fn(&AddEffectData<T>) -> bool + Send + Sync + 'static) -> Self {
+        self.0.push(Arc::new(allow));
+        self
+    }
+
+    pub(crate) fn allows(&self, data: &AddEffectData<T>) -> bool {
+        self.0.iter().all(|allow| allow(data))
+    }
+}
+
+/// Sends effects aimed at this entity to another one instead, e.g. a guardian intercepting damage for an ally.
+/// Chains are followed to the last guardian, stopping at a cycle or a guardian without ActiveEffects.
+#[derive(Component, Clone, Copy)]
+pub struct RedirectTo {
+    pub target: Entity,
+    /// Only redirect effects with this tag
+    pub tag: Option<TagId>,
+    /// Only redirect harmful effects, i.e. Fixed Additive magnitudes below 0 and Multiplicative ones below 1
+    pub harmful_only: bool,
+}
+
+impl RedirectTo {
+    pub fn new(target: Entity) -> Self {
+        Self { target, tag: None, harmful_only: false }
+    }
+
+    pub fn with_tag(mut self, tag: TagId) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    pub fn harmful_only(mut self) -> Self {
+        self.harmful_only = true;
+        self
+    }
+
+    pub(crate) fn applies<T: StatTrait>(&self, effect: &GameplayEffect<T>) -> bool {
+        if self.tag.is_some() && effect.tag != self.tag { return false }
+        let harmful = match (&effect.calculation, &effect.magnitude) {
+            (EffectCalculation::Additive, EffectMagnitude::Fixed(x)) => *x < 0.,
+            (EffectCalculation::Multiplicative, EffectMagnitude::Fixed(x)) => *x < 1.,
+            _ => false,
+        };
+        !self.harmful_only || harmful
+    }
+}
+
 #[derive(Clone)]
 pub struct GameplayEffect<T: StatTrait> {
     pub stat_target: T,
@@ -19,16 +99,47 @@ pub struct GameplayEffect<T: StatTrait> {
     pub calculation: EffectCalculation,
     pub duration: EffectDuration,
     pub tag: Option<TagId>,
+    pub net_id: Option<NetEffectId>,
+    pub source_entity: Option<Entity>,
+    pub group_id: Option<u64>,
+    pub duration_scaling: Option<DurationScaling>,
+    pub condition: Option<EffectCondition>,
+    pub leech: Option<Leech<T>>,
+    pub align_ticks: bool,
+    pub bound_mode: BoundMode,
+    pub cache_magnitude: bool,
+    pub random_target: bool,
+    pub warmup: Option<f32>,
+    pub budget: Option<f32>,
+    pub warn_before: Option<f32>,
+    pub payload: Option<EffectPayload>,
+    pub cooldown: Option<(TagId, f32)>,
+    /// Carried by the source entity while the effect is active on the target, e.g. Channeling on the caster
+    pub source_tag: Option<TagId>,
+    /// Scales Additive amounts by the school's multiplier in SchoolModifiers
+    pub school: Option<SchoolId>,
+    /// Survives RemoveEffect and RetainEffects purges, only RemoveEffectByNetId and RemoveEffectGroup remove it
+    pub undispellable: bool,
+    pub(crate) warned: bool,
+    pub(crate) magnitude_cache: Option<(Tick, f32)>,
+    pub(crate) condition_met: bool,
+    /// Scales Additive amounts and how far Multiplicative ones are from 1, lowered by the target's Resistances
+    pub(crate) magnitude_factor: f32,
+    /// Active effects sharing this one's tag, including itself, for StackTable magnitudes
+    pub(crate) stack_count: u32,
+    /// Change a Tracked effect made to its stat, taken back out on removal
+    pub(crate) applied: f32,
 }
 
 impl<T: StatTrait> GameplayEffect<T> {
     pub fn set_duration(&mut self, duration: impl Into<SmallTimer>) -> Result<(), &'static str> {
         match &mut self.duration {
             EffectDuration::Continuous(Some(timer)) => { timer.set_duration(duration); },
-            EffectDuration::Persistent(Some(timer)) => { timer.set_duration(duration); },
+            EffectDuration::Persistent(Some(timer)) | EffectDuration::Tracked(Some(timer)) => { timer.set_duration(duration); },
             EffectDuration::Repeating(_, Some(timer)) => { timer.set_duration(duration); },
             _ => { return Err("Effect has no duration timer set") }
         }
+        self.warned = false;
         Ok(())
     }
 }
@@ -41,7 +152,173 @@ impl<T: StatTrait> GameplayEffect<T> {
         calculation: EffectCalculation,
         duration: EffectDuration,
     ) -> Self {
-        Self { stat_target, magnitude, calculation, duration, tag }
+        Self {
+            stat_target,
+            magnitude,
+            calculation,
+            duration,
+            tag,
+            net_id: None,
+            source_entity: None,
+            group_id: None,
+            duration_scaling: None,
+            condition: None,
+            leech: None,
+            align_ticks: false,
+            bound_mode: BoundMode::Live,
+            cache_magnitude: false,
+            random_target: false,
+            warmup: None,
+            budget: None,
+            warn_before: None,
+            payload: None,
+            cooldown: None,
+            source_tag: None,
+            school: None,
+            undispellable: false,
+            warned: false,
+            magnitude_cache: None,
+            condition_met: true,
+            magnitude_factor: 1.,
+            stack_count: 1,
+            applied: 0.,
+        }
+    }
+
+    pub fn with_duration_scaling(mut self, scaling: DurationScaling) -> Self {
+        self.duration_scaling = Some(scaling);
+        self
+    }
+
+    /// Only apply on frames where the condition holds. The effect keeps ticking towards expiry either way.
+    pub fn with_condition(mut self, condition: impl Fn(Entity, &World) -> bool + Send + Sync + 'static) -> Self {
+        self.condition = Some(EffectCondition(Arc::new(condition)));
+        self
+    }
+
+    /// Whenever this effect lowers its stat, raise another stat by that fraction of the damage, attributed
+    /// to this effect's source, e.g. 0.2 for lifesteal.  Negative fractions count as 0.
+    pub fn with_leech(mut self, stat: T, entity: Entity, fraction: f32) -> Self {
+        self.leech = Some(Leech { stat, entity, fraction: fraction.max(0.) });
+        self
+    }
+
+    /// Repeating effects trigger on a global grid of their period, so aligned effects tick on the same frames
+    pub fn with_aligned_ticks(mut self) -> Self {
+        self.align_ticks = true;
+        self
+    }
+
+    /// Whether a bound effect resolves its magnitude once when added or on every recalculation
+    pub fn with_bound_mode(mut self, mode: BoundMode) -> Self {
+        self.bound_mode = mode;
+        self
+    }
+
+    /// Send the payload in an OnEffectPayload whenever AddEffect applies this effect
+    pub fn with_payload(mut self, payload: impl Reflect) -> Self {
+        self.payload = Some(EffectPayload(Arc::new(payload)));
+        self
+    }
+
+    /// Grant the tag for secs whenever this effect is applied, and refuse to apply it while the tag is active
+    pub fn with_cooldown(mut self, tag: TagId, secs: f32) -> Self {
+        self.cooldown = Some((tag, secs));
+        self
+    }
+
+    /// Tag the source entity carries while this effect is active on the target, e.g. Channeling
+    pub fn with_source_tag(mut self, tag: TagId) -> Self {
+        self.source_tag = Some(tag);
+        self
+    }
+
+    /// Put the effect in a school, e.g. Fire, so the school's multiplier in SchoolModifiers scales it
+    pub fn with_school(mut self, school: SchoolId) -> Self {
+        self.school = Some(school);
+        self
+    }
+
+    /// Protect the effect from generic cleanses, e.g. a boss debuff or a permanent curse
+    pub fn undispellable(mut self) -> Self {
+        self.undispellable = true;
+        self
+    }
+
+    /// Reuse a NonlocalStat magnitude between frames until the source's stats change
+    pub fn with_cached_magnitude(mut self) -> Self {
+        self.cache_magnitude = true;
+        self
+    }
+
+    /// Apply to a stat picked from T::variants() with the EffectRng instead of stat_target.  Immediate and
+    /// persistent effects pick once when added, the others pick again on every application.
+    pub fn with_random_target(mut self) -> Self {
+        self.random_target = true;
+        self
+    }
+
+    /// Hold the effect for secs before it lands, during which CancelPending can interrupt it
+    pub fn with_warmup(mut self, secs: f32) -> Self {
+        self.warmup = Some(secs);
+        self
+    }
+
+    /// Cap the total amount a continuous, repeating or scheduled Additive effect applies over its lifetime,
+    /// e.g. a shield absorbing at most 100, counted after the target's IncomingMultipliers.  The effect
+    /// is removed once the budget is used up.  Other calculations ignore the budget.
+    pub fn with_budget(mut self, total: f32) -> Self {
+        self.budget = Some(total);
+        self
+    }
+
+    /// Read the magnitude from the target itself when it is a NonlocalStat or Blend of this entity
+    pub fn localize(mut self, entity: Entity) -> Self {
+        if let EffectMagnitude::NonlocalStat(stat, params, source) = &self.magnitude {
+            if *source == entity {
+                self.magnitude = EffectMagnitude::LocalStat(*stat, params.clone());
+            }
+        }
+        if let EffectMagnitude::Blend { source, .. } = &mut self.magnitude {
+            if *source == Some(entity) {
+                *source = None;
+            }
+        }
+        self
+    }
+
+    /// Emit OnEffectExpiring once when the remaining duration drops to secs, e.g. to flash a buff icon
+    pub fn with_expiry_warning(mut self, secs: f32) -> Self {
+        self.warn_before = Some(secs);
+        self
+    }
+
+    /// Effects sharing a group id can be removed together with RemoveEffectGroup
+    pub fn with_group(mut self, group_id: u64) -> Self {
+        self.group_id = Some(group_id);
+        self
+    }
+
+    /// Immediately set stat to the current value of max_stat
+    pub fn heal_to_max(stat: T, max_stat: T) -> Self {
+        Self::new(
+            None,
+            stat,
+            EffectMagnitude::LocalStat(max_stat, StatScalingParams::default()),
+            EffectCalculation::SetValue,
+            EffectDuration::Immediate,
+        )
+    }
+
+    /// Immediately set stat to zero, or to its lower bound if one is active
+    pub fn drain_to_min(stat: T) -> Self {
+        Self::new(
+            None,
+            stat,
+            EffectMagnitude::Fixed(0.),
+            EffectCalculation::SetValue,
+            EffectDuration::Immediate,
+        )
     }
 
     pub fn tag_effect(tag: TagId, duration: Option<f32>) -> Self {
@@ -52,25 +329,92 @@ impl<T: StatTrait> GameplayEffect<T> {
             calculation: EffectCalculation::None,
             duration: EffectDuration::Persistent(duration),
             tag: Some(tag),
+            net_id: None,
+            source_entity: None,
+            group_id: None,
+            duration_scaling: None,
+            condition: None,
+            leech: None,
+            align_ticks: false,
+            bound_mode: BoundMode::Live,
+            cache_magnitude: false,
+            random_target: false,
+            warmup: None,
+            budget: None,
+            warn_before: None,
+            payload: None,
+            cooldown: None,
+            source_tag: None,
+            school: None,
+            undispellable: false,
+            warned: false,
+            magnitude_cache: None,
+            condition_met: true,
+            magnitude_factor: 1.,
+            stack_count: 1,
+            applied: 0.,
         }
     }
 }
 
 impl<T: StatTrait> GameplayEffect<T> {
-    fn get_duration_timer(&self) -> Option<&SmallTimer> {
+    /// Seconds left before the effect expires, None if it has no duration
+    pub fn remaining_duration(&self) -> Option<f32> {
+        self.get_duration_timer().map(|timer| timer.remaining)
+    }
+
+    /// Amount after the target's magnitude resistance, which pulls Multiplicative amounts towards 1
+    pub(crate) fn resisted(&self, amount: f32) -> f32 {
+        match self.calculation {
+            EffectCalculation::Additive => amount * self.magnitude_factor,
+            EffectCalculation::Multiplicative => 1. + (amount - 1.) * self.magnitude_factor,
+            _ => amount,
+        }
+    }
+
+    /// Fold other's Fixed magnitude into this effect, returning false if they are not compatible
+    pub(crate) fn merge(&mut self, other: &GameplayEffect<T>) -> bool {
+        if self.stat_target != other.stat_target
+            || self.calculation != other.calculation
+            || self.duration != other.duration
+            || self.magnitude_factor != other.magnitude_factor {
+            return false;
+        }
+        let (EffectMagnitude::Fixed(current), EffectMagnitude::Fixed(added)) = (&mut self.magnitude, &other.magnitude) else {
+            return false;
+        };
+        match self.calculation {
+            EffectCalculation::Additive => { *current += added },
+            EffectCalculation::Multiplicative => { *current *= added },
+            _ => { return false }
+        }
+        true
+    }
+
+    pub(crate) fn get_duration_timer(&self) -> Option<&SmallTimer> {
         match &self.duration {
             EffectDuration::Continuous(Some(timer)) => Some(timer),
-            EffectDuration::Persistent(Some(timer)) => Some(timer),
+            EffectDuration::Persistent(Some(timer)) | EffectDuration::Tracked(Some(timer)) => Some(timer),
+            EffectDuration::Repeating(_, Some(timer)) => Some(timer),
+            _ => None
+        }
+    }
+
+    pub(crate) fn get_duration_timer_mut(&mut self) -> Option<&mut SmallTimer> {
+        match &mut self.duration {
+            EffectDuration::Continuous(Some(timer)) => Some(timer),
+            EffectDuration::Persistent(Some(timer)) | EffectDuration::Tracked(Some(timer)) => Some(timer),
             EffectDuration::Repeating(_, Some(timer)) => Some(timer),
             _ => None
         }
     }
 }
 
+/// Tags currently active on an entity, stored inline up to N tags
 #[derive(Component, Deref, DerefMut, Default)]
-pub struct ActiveTags(TagList<ACTIVE_TAGS_SIZE>);
+pub struct ActiveTags<const N: usize = ACTIVE_TAGS_SIZE>(TagList<N>);
 
-impl ActiveTags {
+impl<const N: usize> ActiveTags<N> {
     pub fn new() -> Self {
         Self::default()
     }
@@ -96,15 +440,29 @@ impl ActiveTags {
             self.remove(*tag);
         }
     }
+
+    /// Remove a tag whose effect is gone, unless another active effect still grants it.  The
+    /// effects carrying a tag act as its reference count, so stacked or duplicate effects keep it.
+    /// Returns whether the tag was removed.
+    pub(crate) fn release<T: StatTrait>(&mut self, tag: TagId, effects: &[GameplayEffect<T>]) -> bool {
+        let released = self.contains(&tag) && !effects.iter().any(|e| e.tag == Some(tag));
+        if released {
+            self.remove(tag);
+        }
+        released
+    }
 }
 
+/// Effects are kept in insertion order, and removals never reorder the survivors,
+/// so iteration is deterministic for networked simulation.
+/// Up to N effects are stored inline before spilling to the heap.  The plugin inserts the
+/// ActiveTags<M> it processes when the component is added, unless the entity already has one.
 #[derive(Component, Clone, Deref, DerefMut)]
-#[require(ActiveTags)]
-pub struct ActiveEffects<T: StatTrait>(pub(crate) SmallVec<[GameplayEffect<T>; ACTIVE_EFFECTS_SIZE]>);
+pub struct ActiveEffects<T: StatTrait, const N: usize = ACTIVE_EFFECTS_SIZE>(pub(crate) SmallVec<[GameplayEffect<T>; N]>);
 
-impl<T: StatTrait> ActiveEffects<T> {
+impl<T: StatTrait, const N: usize> ActiveEffects<T, N> {
     pub fn new(effects: impl IntoIterator<Item = GameplayEffect<T>>) -> Self {
-        let mut instance = Self(SmallVec::<[GameplayEffect<T>; ACTIVE_EFFECTS_SIZE]>::new());
+        let mut instance = Self(SmallVec::<[GameplayEffect<T>; N]>::new());
         instance.0.extend(effects);
         instance
     }
@@ -112,192 +470,970 @@ impl<T: StatTrait> ActiveEffects<T> {
     pub fn match_effect_type(&mut self, other: TagId) -> impl Iterator<Item = &mut GameplayEffect<T>> {
         self.0.iter_mut().filter(move |e| e.tag == Some(other))
     }
+
+    /// Remove every effect the predicate rejects, keeping the order of the rest, and return the removed effects.
+    /// Stats are not recalculated, so trigger RetainEffects instead on entities managed by the plugin.
+    pub fn retain_effects(&mut self, predicate: impl Fn(&GameplayEffect<T>) -> bool) -> SmallVec<[GameplayEffect<T>; 4]> {
+        let mut removed = SmallVec::new();
+        let mut i = 0;
+        while i < self.0.len() {
+            if predicate(&self.0[i]) {
+                i += 1;
+            } else {
+                removed.push(self.0.remove(i));
+            }
+        }
+        removed
+    }
+
+    /// Effects which were added with this source entity
+    pub fn from_source(&self, source: Entity) -> impl Iterator<Item = &GameplayEffect<T>> {
+        self.0.iter().filter(move |e| e.source_entity == Some(source))
+    }
+
+    /// How much the persistent Additive and Multiplicative effects with this tag add to the stat's
+    /// modified base, e.g. for a "Blessing: +30 Strength" tooltip.  The additive part is the tag's
+    /// additive amount under every multiplier, the multiplicative part is what the tag's multipliers
+    /// add on top of the base and the other additive effects.  Magnitudes are read from stats, so
+    /// stats of other entities and weighted tables are only known once cached, and outgoing
+    /// multipliers and bounds are not applied.
+    pub fn contribution(&self, tag: TagId, stat: T, stats: &GameplayStats<T>) -> f32 {
+        let Some(base) = stats.try_get(stat).map(|s| s.base_value) else { return 0. };
+        let (mut additive, mut multiplicative) = (0., 1.);
+        let (mut tag_additive, mut tag_multiplicative) = (0., 1.);
+        let persistent = self.0.iter()
+            .filter(|e| e.stat_target == stat && matches!(e.duration, EffectDuration::Persistent(_)));
+        for effect in persistent {
+            let magnitude = match (&effect.magnitude, effect.magnitude_cache) {
+                (_, Some((_, cached))) => cached,
+                (EffectMagnitude::Fixed(x), _) => *x,
+                (EffectMagnitude::StackTable(table), _) => stack_value(table, effect.stack_count),
+                (EffectMagnitude::LocalStat(scaling_stat, f), _) => stats.try_get(*scaling_stat).map_or(0., |s| f.apply(s.current_value)),
+                (EffectMagnitude::Piecewise { stat: piecewise_stat, reference, threshold, below, above }, _) => {
+                    match (stats.try_get(*piecewise_stat), stats.try_get(*reference)) {
+                        (Some(s), Some(r)) if s.current_value < r.current_value * threshold => *below,
+                        _ => *above,
+                    }
+                },
+                _ => continue,
+            };
+            let tagged = effect.tag == Some(tag);
+            match effect.calculation {
+                EffectCalculation::Additive if tagged => tag_additive += effect.resisted(magnitude),
+                EffectCalculation::Additive => additive += effect.resisted(magnitude),
+                EffectCalculation::Multiplicative if tagged => tag_multiplicative *= effect.resisted(magnitude),
+                EffectCalculation::Multiplicative => multiplicative *= effect.resisted(magnitude),
+                _ => { }
+            }
+        }
+        let additive_part = tag_additive * multiplicative * tag_multiplicative;
+        let multiplicative_part = (base + additive) * multiplicative * (tag_multiplicative - 1.);
+        additive_part + multiplicative_part
+    }
+}
+
+/// Effects waiting out their warmup, with the seconds left before each is added
+#[derive(Resource)]
+pub struct PendingEffects<T: StatTrait>(pub(crate) Vec<(f32, AddEffectData<T>)>);
+
+impl<T: StatTrait> Default for PendingEffects<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
 }
 
-pub(crate) fn add_effect<T: StatTrait>(
+impl<T: StatTrait> PendingEffects<T> {
+    /// Tags and remaining warmup of the effects pending on the entity
+    pub fn on_entity(&self, entity: Entity) -> impl Iterator<Item = (Option<TagId>, f32)> {
+        self.0.iter()
+            .filter(move |(_, data)| data.target_entity == entity)
+            .map(|(remaining, data)| (data.effect.tag, *remaining))
+    }
+}
+
+/// Lightweight description of an active effect, e.g. for buff UI
+#[derive(Clone, PartialEq)]
+pub struct EffectSummary<T: StatTrait> {
+    pub tag: Option<TagId>,
+    pub stat_target: T,
+    pub calculation: EffectCalculation,
+    pub remaining: Option<f32>,
+    pub net_id: Option<NetEffectId>,
+}
+
+impl<T: StatTrait> From<&GameplayEffect<T>> for EffectSummary<T> {
+    fn from(effect: &GameplayEffect<T>) -> Self {
+        Self {
+            tag: effect.tag,
+            stat_target: effect.stat_target,
+            calculation: effect.calculation.clone(),
+            remaining: effect.remaining_duration(),
+            net_id: effect.net_id,
+        }
+    }
+}
+
+/// Every effect across all entities added by the given source, paired with the entity it is on.
+/// Covers the default ActiveEffects capacity; use ActiveEffects::from_source in a query otherwise.
+pub fn effects_from_source<T: StatTrait>(world: &mut World, source: Entity) -> Vec<(Entity, EffectSummary<T>)> {
+    let mut query = world.query::<(Entity, &ActiveEffects<T>)>();
+    query.iter(world)
+        .flat_map(|(entity, effects)| effects.from_source(source).map(move |effect| (entity, effect.into())))
+        .collect()
+}
+
+/// Ids handed out by with_temporary_effect, counting down from the top so they stay clear of game ids
+static TEMPORARY_NET_ID: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Add the effect to the entity, run the closure and then remove the effect again, recalculating its stat.
+/// Meant for persistent effects, e.g. previewing damage with a buff applied.  Any warmup is skipped, and
+/// immediate effects cannot be reverted.  Covers the default ActiveEffects capacity.
+pub fn with_temporary_effect<T: StatTrait, R>(
+    world: &mut World,
+    entity: Entity,
+    mut effect: GameplayEffect<T>,
+    f: impl FnOnce(&mut World) -> R,
+) -> R {
+    let net_id = *effect.net_id.get_or_insert_with(|| NetEffectId(TEMPORARY_NET_ID.fetch_sub(1, Ordering::Relaxed)));
+    effect.warmup = None;
+    world.trigger(AddEffect(AddEffectData::new(entity, effect, None)));
+    let result = f(world);
+    world.trigger(RemoveEffectByNetId { target_entity: entity, net_id });
+    result
+}
+
+/// Clock process_active_effects steps effects by, shared with add_effect so aligned ticks land on its grid
+#[derive(Resource)]
+pub(crate) struct EffectClock<T: StatTrait>(pub(crate) FixedStepClock, PhantomData<T>);
+
+impl<T: StatTrait> Default for EffectClock<T> {
+    fn default() -> Self {
+        Self(FixedStepClock::default(), PhantomData)
+    }
+}
+
+/// Resources process_active_effects reads besides the effects and stats themselves
+#[derive(SystemParam)]
+pub(crate) struct ProcessingState<'w, 's, T: StatTrait, const N: usize> {
+    time: Res<'w, Time>,
+    pending: ResMut<'w, PendingEffects<T>>,
+    entities: &'w Entities,
+    suppressions: Res<'w, Suppressions>,
+    tag_registry: Option<Res<'w, TagRegistry>>,
+    budget: ResMut<'w, ProcessingBudget<T>>,
+    despawned: RemovedComponents<'w, 's, ActiveEffects<T, N>>,
+    clock: ResMut<'w, EffectClock<T>>,
+}
+
+/// Message writers shared by the systems and observers which add, process and remove effects
+#[derive(SystemParam)]
+pub(crate) struct EffectWriters<'w, T: StatTrait> {
+    pub(crate) added: MessageWriter<'w, OnEffectAdded>,
+    pub(crate) removed: MessageWriter<'w, OnEffectRemoved>,
+    pub(crate) breached: MessageWriter<'w, OnBoundsBreached<T>>,
+    pub(crate) payload: MessageWriter<'w, OnEffectPayload>,
+    pub(crate) repeating: MessageWriter<'w, OnRepeatingEffectTriggered>,
+    pub(crate) batched: MessageWriter<'w, OnStatChangedBatched<T>>,
+    pub(crate) expiring: MessageWriter<'w, OnEffectExpiring>,
+}
+
+/// Configuration and per-entity rules add_effect checks before storing an effect
+#[derive(SystemParam)]
+pub(crate) struct AddEffectRules<'w, 's, T: StatTrait> {
+    stacking: Res<'w, StackingBehaviors>,
+    tag_registry: Option<Res<'w, TagRegistry>>,
+    mutex_groups: Res<'w, MutexGroups>,
+    resistances: Query<'w, 's, &'static Resistances>,
+    time: Res<'w, Time>,
+    clock: Res<'w, EffectClock<T>>,
+    pending: ResMut<'w, PendingEffects<T>>,
+    vetoes: Query<'w, 's, &'static EffectVetoes<T>>,
+    redirects: Query<'w, 's, &'static RedirectTo>,
+}
+
+pub(crate) fn add_effect<T: StatTrait, const N: usize, const M: usize>(
     trigger: On<AddEffect<T>>,
     mut stats_query: Query<&mut GameplayStats<T>>,
-    mut active_effects: Query<(Entity, &mut ActiveEffects<T>, &mut ActiveTags)>,
-    mut added_writer: MessageWriter<OnEffectAdded>,
-    mut breached_writer: MessageWriter<OnBoundsBreached<T>>,
-    stacking_behaviors: Res<StackingBehaviors>,
+    mut active_effects: Query<(Entity, &mut ActiveEffects<T, N>, &mut ActiveTags<M>)>,
+    mut writers: EffectWriters<T>,
+    mut rules: AddEffectRules<T>,
+    mut ctx: EffectContext<T>,
 ) {
     let event = trigger.event();
-    let AddEffectData::<T> { effect, target_entity, source_entity} = &event.0;
+    let redirected;
+    let data = match redirect_target(&event.0, &rules.redirects, &active_effects) {
+        Some(target_entity) => {
+            redirected = AddEffectData { target_entity, ..event.0.clone() };
+            &redirected
+        },
+        None => &event.0,
+    };
+    let AddEffectData::<T> { effect, target_entity, source_entity, outcome_sender, resumed, stats_snapshot } = data;
 
-    if let Ok((entity, mut effects, mut tags)) = active_effects.get_mut(*target_entity) {
-        let source = get_effect_source_stats(effect, entity, &mut stats_query);
-        let amount = get_effect_amount(effect, source);
+    if rules.vetoes.get(*target_entity).is_ok_and(|vetoes| !vetoes.allows(data)) {
+        if let Some(sender) = outcome_sender {
+            sender.send(EffectOutcome::blocked(EffectBlockedReason::Vetoed));
+        }
+        return;
+    }
+
+    // The outcome is sent once the warmup completes and the effect is added again
+    if let Some(warmup) = effect.warmup.filter(|&w| w > 0.) {
+        let mut data = data.clone();
+        data.effect.warmup = None;
+        rules.pending.0.push((warmup, data));
+        return;
+    }
+
+    let tag_count = match &effect.magnitude {
+        EffectMagnitude::TagCount { tag, candidates, .. } => candidates.iter()
+            .filter(|&&candidate| active_effects.get(candidate).is_ok_and(|(_, _, tags)| tags.contains(tag)))
+            .count(),
+        _ => 0,
+    };
+
+    let outcome = 'add: {
+        let Ok((entity, mut effects, mut tags)) = active_effects.get_mut(*target_entity) else {
+            break 'add EffectOutcome::blocked(EffectBlockedReason::MissingActiveEffects);
+        };
+        if !stats_query.contains(entity) {
+            warn!("Skipping effect on entity {} which has no GameplayStats component", entity);
+            break 'add EffectOutcome::blocked(EffectBlockedReason::MissingStats);
+        }
+        if effect.cooldown.is_some_and(|(tag, _)| tags.contains(&tag)) {
+            break 'add EffectOutcome::blocked(EffectBlockedReason::OnCooldown);
+        }
+        let mut effect = effect.clone();
+        effect.source_entity = *source_entity;
+        if effect.align_ticks {
+            if let EffectDuration::Repeating(timer, _) = &mut effect.duration {
+                timer.align(&rules.clock.0, &rules.time, ctx.settings.fixed_step);
+            }
+        }
+        let resistance = effect.tag.zip(rules.resistances.get(entity).ok())
+            .and_then(|(tag, resistances)| resistances.get(tag, rules.tag_registry.as_deref()));
+        if let Some(resistance) = resistance {
+            if let Some(remaining) = effect.get_duration_timer().map(|timer| timer.remaining).filter(|_| !resumed) {
+                effect.set_duration(remaining * (1. - resistance.duration)).ok();
+            }
+            effect.magnitude_factor = 1. - resistance.magnitude;
+        }
+        if effect.random_target && matches!(effect.duration, EffectDuration::Immediate | EffectDuration::Persistent(_)) {
+            if let Some(stat) = ctx.rng.pick(T::variants()) {
+                effect.stat_target = stat;
+            }
+        }
+        if ctx.immutable.rejects(entity, effect.stat_target) {
+            break 'add EffectOutcome::blocked(EffectBlockedReason::ImmutableStat);
+        }
+        // Roll the table once up front, so persistent effects revert exactly and bounds and
+        // recalculations never draw from the RNG
+        if let EffectMagnitude::WeightedTable(table) = &effect.magnitude {
+            effect.magnitude = EffectMagnitude::Fixed(sample_weighted(table, &mut ctx.rng));
+        }
+        if let EffectMagnitude::TagCount { base, per_entity, .. } = effect.magnitude {
+            effect.magnitude = EffectMagnitude::Fixed(base + per_entity * tag_count as f32);
+        }
+        // Lag compensated effects read the target's stats from the snapshot, so they are fixed from then on
+        let local = matches!(effect.magnitude, EffectMagnitude::LocalStat(..) | EffectMagnitude::Piecewise { .. } | EffectMagnitude::Blend { source: None, .. })
+            || effect.magnitude.source_entity() == Some(entity);
+        if let Some(snapshot) = stats_snapshot.as_deref().filter(|_| local) {
+            effect.magnitude = EffectMagnitude::Fixed(get_base_magnitude(&effect, Some(snapshot), &mut ctx));
+        }
+        let source = get_effect_source_stats(&effect, entity, &stats_query);
+        let amount = get_effect_amount(&effect, source, &stats_query, &mut ctx);
+        if effect.bound_mode == BoundMode::Snapshot
+            && matches!(effect.calculation, EffectCalculation::LowerBound | EffectCalculation::UpperBound) {
+            effect.magnitude = EffectMagnitude::Fixed(amount);
+        }
+        let effect = &effect;
+        // A timed effect added with no time left is a single-tick burst of its full magnitude and is never
+        // stored.  A persistent or tracked one would be reverted before doing anything, so it is rejected instead.
+        let expired = effect.get_duration_timer().is_some_and(|timer| timer.finished());
+        if expired && matches!(effect.duration, EffectDuration::Persistent(_) | EffectDuration::Tracked(_)) {
+            break 'add EffectOutcome::blocked(EffectBlockedReason::Expired);
+        }
             
-        if !matches!(effect.duration, EffectDuration::Immediate) {
+        let mut restacked = SmallVec::<[T; 2]>::new();
+        if !matches!(effect.duration, EffectDuration::Immediate) && !expired {
+            let stacking = match effect.tag {
+                Some(tag) => Some(rules.stacking.get(tag, rules.tag_registry.as_deref())),
+                None => rules.stacking.untagged_policy(),
+            };
+            let mut weaker = SmallVec::new();
+            if matches!(stacking, Some(StackingPolicy::KeepStrongest)) {
+                let outclassed = effects.0.iter().filter(|other| in_stack(other, effect)).any(|other| {
+                    let source = get_effect_source_stats(other, entity, &stats_query);
+                    other.calculation.strength(get_effect_amount(other, source, &stats_query, &mut ctx)) >= effect.calculation.strength(amount)
+                });
+                if outclassed {
+                    break 'add EffectOutcome::blocked(EffectBlockedReason::Outclassed);
+                }
+                weaker = effects.retain_effects(|other| !in_stack(other, effect));
+            }
+            // Resolve stacking first so a blocked effect neither evicts rival tags nor grants its own
+            let store = match stack_effect(&mut effects.0, effect, stacking) {
+                Ok(store) => store,
+                Err(reason) => break 'add EffectOutcome::blocked(reason),
+            };
             if let Some(tag) = effect.tag {
-                tags.add(tag);
-                let stacking = stacking_behaviors.0[*tag as usize]
-                    .unwrap_or_default();
-
-                match stacking {
-                    StackingPolicy::NoStacking => {
-                        if effects.match_effect_type(tag).count() == 0 {
-                            effects.0.push(effect.clone());
-                        } else { return; }
-                    },
-                    StackingPolicy::NoStackingResetDuration => {
-                        if effects.match_effect_type(tag).count() == 0 {
-                            effects.0.push(effect.clone());
-                        } else {
-                            if let Some(timer) = effect.get_duration_timer() {
-                                for other in effects.match_effect_type(tag) {
-                                    other.set_duration(timer.clone()).ok();
-                                }
-                            }
-                            return;
-                        }
+                if let Some(group) = rules.mutex_groups.get(tag) {
+                    let exclusive: SmallVec<[TagId; 4]> = tags.iter()
+                        .copied()
+                        .filter(|&t| t != tag && rules.mutex_groups.get(t) == Some(group))
+                        .collect();
+                    for other in exclusive {
+                        remove_effects_with_tag(
+                            entity, Some(other), &mut effects, &mut tags,
+                            &mut stats_query, &mut writers.breached, &mut writers.removed, &mut ctx,
+                        );
                     }
-                    StackingPolicy::MultipleEffects(max) => {
-                        if effects.match_effect_type(tag).count() < max as usize {
-                            effects.0.push(effect.clone());
-                        } else { return; }
-                    },
-                    StackingPolicy::MultipleEffectsResetDurations(max) => {
-                        if let Some(timer) = effect.get_duration_timer() {
-                            for other in effects.match_effect_type(tag) {
-                                other.set_duration(timer.clone()).ok();
-                            }
-                        }
-                        if effects.match_effect_type(tag).count() < max as usize {
-                            effects.0.push(effect.clone());
-                        } else { return; }
-                    },
                 }
-            } else {
+                if !tags.contains(&tag) {
+                    tags.add(tag);
+                    ctx.tag_added_writer.write(OnTagAdded { entity, tag });
+                }
+            }
+            if store {
                 effects.0.push(effect.clone());
             }
+            // Finished once the new effect is stored, so the shared tag is not released in between
+            if !weaker.is_empty() {
+                finish_removal::<T, N, M>(entity, &mut effects.0, &mut tags, weaker, &mut stats_query, &mut writers.breached, &mut writers.removed, &mut ctx);
+            }
+            restacked = refresh_stack_counts(&mut effects.0, effect.tag);
+            grant_source_tag::<T, M>(effect, &mut ctx.commands);
         }
         // Check for bounds breach
         match &effect.duration {
+            _ if expired => {
+                if let Some(e) = apply_immediate(entity, effect, &mut stats_query, amount, &effects, &mut ctx) {
+                    writers.breached.write(e);
+                }
+            },
             EffectDuration::Immediate => {
-                if let Some(e) = apply_immediate(entity, effect, &mut stats_query, amount, &effects) {
-                    breached_writer.write(e);
+                if let Some(e) = apply_immediate(entity, effect, &mut stats_query, amount, &effects, &mut ctx) {
+                    writers.breached.write(e);
+                }
+            },
+            EffectDuration::Tracked(_) => {
+                let current = |stats_query: &Query<&mut GameplayStats<T>>| stats_query.get(entity).ok()
+                    .and_then(|stats| stats.try_get(effect.stat_target)).map_or(0., |stat| stat.current_value);
+                let before = current(&stats_query);
+                if let Some(e) = apply_immediate(entity, effect, &mut stats_query, amount, &effects, &mut ctx) {
+                    writers.breached.write(e);
+                }
+                let applied = current(&stats_query) - before;
+                if let Some(stored) = effects.0.iter_mut().rev().find(|other| in_stack(other, effect)) {
+                    stored.applied += applied;
                 }
             },
             EffectDuration::Persistent(_) => {
-                if let Some(e) = recalculate_stats(entity, &effects, effect.stat_target, &mut stats_query) {
-                    breached_writer.write(e);
+                restacked.push(effect.stat_target);
+                for e in recalculate_stats_multi(entity, &effects, &restacked, std::slice::from_ref(effect), &mut stats_query, &mut ctx) {
+                    writers.breached.write(e);
                 }
             },
             _ => { }
         }
-        added_writer.write(OnEffectAdded(EffectMetadata::new(event.0.target_entity, effect.tag, *source_entity)));
+        if let Some((tag, secs)) = effect.cooldown {
+            effects.0.push(GameplayEffect::tag_effect(tag, Some(secs)));
+            if !tags.contains(&tag) {
+                tags.add(tag);
+                ctx.tag_added_writer.write(OnTagAdded { entity, tag });
+            }
+        }
+        writers.added.write(OnEffectAdded(
+            EffectMetadata::new(*target_entity, effect.tag, *source_entity).with_net_id(effect.net_id)
+        ));
+        if let Some(payload) = &effect.payload {
+            writers.payload.write(OnEffectPayload {
+                metadata: EffectMetadata::new(*target_entity, effect.tag, *source_entity).with_net_id(effect.net_id),
+                payload: payload.clone(),
+            });
+        }
+        EffectOutcome::applied(amount)
+    };
+
+    if let Some(sender) = outcome_sender {
+        sender.send(outcome);
+    }
+}
+
+/// Take a removed Tracked effect's one-off change back out of its stat
+pub(crate) fn revert_tracked<T: StatTrait>(
+    entity: Entity,
+    effect: &GameplayEffect<T>,
+    stats_query: &mut Query<&mut GameplayStats<T>>,
+    ctx: &mut EffectContext<T>,
+) {
+    if !matches!(effect.duration, EffectDuration::Tracked(_)) || effect.applied == 0. { return }
+    let Ok(mut stats) = stats_query.get_mut(entity) else { return };
+    let Some(stat) = stats.try_get_mut(effect.stat_target) else { return };
+    let old = stat.current_value;
+    stat.current_value -= effect.applied;
+    if let Some(audit) = &ctx.audit {
+        audit.record(StatMutation {
+            entity, stat: effect.stat_target, old, new: stat.current_value, tag: effect.tag, source: effect.source_entity, cause: MutationCause::Recalculation,
+        });
+    }
+}
+
+/// Give a stored effect's source its source tag.  Deferred since the source may be the target whose
+/// tags are already borrowed, and skipped if the source no longer exists.
+pub(crate) fn grant_source_tag<T: StatTrait, const M: usize>(effect: &GameplayEffect<T>, commands: &mut Commands) {
+    let (Some(tag), Some(source)) = (effect.source_tag, effect.source_entity) else { return };
+    commands.queue(move |world: &mut World| {
+        {
+            let Some(mut tags) = world.get_mut::<ActiveTags<M>>(source) else { return };
+            if tags.contains(&tag) { return }
+            tags.add(tag);
+        }
+        world.write_message(OnTagAdded { entity: source, tag });
+    });
+}
+
+/// Take a removed effect's source tag back off its source, unless another effect from the same source
+/// still grants it or the source carries the tag from an effect of its own
+pub(crate) fn release_source_tag<T: StatTrait, const N: usize, const M: usize>(effect: &GameplayEffect<T>, commands: &mut Commands) {
+    let (Some(tag), Some(source)) = (effect.source_tag, effect.source_entity) else { return };
+    commands.queue(move |world: &mut World| {
+        let mut query = world.query::<&ActiveEffects<T, N>>();
+        let granted = query.iter(world)
+            .flat_map(|effects| effects.0.iter())
+            .any(|e| e.source_entity == Some(source) && e.source_tag == Some(tag));
+        let own = world.get::<ActiveEffects<T, N>>(source).is_some_and(|effects| effects.0.iter().any(|e| e.tag == Some(tag)));
+        if granted || own { return }
+        {
+            let Some(mut tags) = world.get_mut::<ActiveTags<M>>(source) else { return };
+            if !tags.contains(&tag) { return }
+            tags.remove(tag);
+        }
+        world.write_message(OnTagRemoved { entity: source, tag });
+    });
+}
+
+/// Update the stack count of the effects with this tag, returning the stats of the persistent ones
+/// whose StackTable magnitude changed with it
+pub(crate) fn refresh_stack_counts<T: StatTrait>(effects: &mut [GameplayEffect<T>], tag: Option<TagId>) -> SmallVec<[T; 2]> {
+    let mut affected = SmallVec::new();
+    let Some(tag) = tag else { return affected };
+    let count = effects.iter().filter(|e| e.tag == Some(tag)).count() as u32;
+    for effect in effects.iter_mut().filter(|e| e.tag == Some(tag) && e.stack_count != count) {
+        effect.stack_count = count;
+        if matches!((&effect.magnitude, &effect.duration), (EffectMagnitude::StackTable(_), EffectDuration::Persistent(_))) {
+            affected.push(effect.stat_target);
+        }
     }
+    affected
 }
 
-pub(crate) fn remove_effect<T: StatTrait>(
+/// Final target after following RedirectTo from the effect's target, None when it is not redirected
+fn redirect_target<T: StatTrait, const N: usize, const M: usize>(
+    data: &AddEffectData<T>,
+    redirects: &Query<&RedirectTo>,
+    active_effects: &Query<(Entity, &mut ActiveEffects<T, N>, &mut ActiveTags<M>)>,
+) -> Option<Entity> {
+    let mut target = data.target_entity;
+    let mut visited = SmallVec::<[Entity; 4]>::new();
+    while let Ok(redirect) = redirects.get(target) {
+        visited.push(target);
+        if !redirect.applies(&data.effect) || visited.contains(&redirect.target) || !active_effects.contains(redirect.target) { break }
+        target = redirect.target;
+    }
+    (target != data.target_entity).then_some(target)
+}
+
+pub(crate) fn apply_instant<T: StatTrait, const N: usize>(
+    trigger: On<ApplyInstant<T>>,
+    mut stats_query: Query<&mut GameplayStats<T>>,
+    effects_query: Query<&ActiveEffects<T, N>>,
+    mut breached_writer: MessageWriter<OnBoundsBreached<T>>,
+    mut changed_writer: MessageWriter<OnStatChanged<T>>,
+    mut ctx: EffectContext<T>,
+) {
+    let event = trigger.event();
+    let Some(previous) = stats_query.get(event.target_entity).ok()
+        .and_then(|stats| stats.try_get(event.stat))
+        .map(|stat| stat.current_value) else { return };
+
+    let mut effect = GameplayEffect::new(
+        None,
+        event.stat,
+        EffectMagnitude::Fixed(event.amount),
+        EffectCalculation::Additive,
+        EffectDuration::Immediate,
+    );
+    effect.source_entity = event.source_entity;
+    let amount = get_effect_amount(&effect, None, &stats_query, &mut ctx);
+    // Bounds from stored effects still apply
+    let effects = effects_query.get(event.target_entity).map_or(&[][..], |effects| &effects.0[..]);
+    if let Some(e) = apply_immediate(event.target_entity, &effect, &mut stats_query, amount, effects, &mut ctx) {
+        breached_writer.write(e);
+    }
+
+    let current = stats_query.get(event.target_entity).ok()
+        .and_then(|stats| stats.try_get(event.stat))
+        .map_or(previous, |stat| stat.current_value);
+    if current != previous {
+        changed_writer.write(OnStatChanged { entity: event.target_entity, stat: event.stat, delta: current - previous });
+    }
+}
+
+pub(crate) fn remove_effect<T: StatTrait, const N: usize, const M: usize>(
     trigger: Trigger<RemoveEffect>,
     mut breached_writer: MessageWriter<OnBoundsBreached<T>>,
     mut removed_writer: MessageWriter<OnEffectRemoved>,
-    mut effects_entities_query: Query<(&mut ActiveEffects<T>, &mut ActiveTags)>,
+    mut effects_entities_query: Query<(&mut ActiveEffects<T, N>, &mut ActiveTags<M>)>,
     mut stats_query: Query<&mut GameplayStats<T>>,
+    mut ctx: EffectContext<T>,
 ) {
-    let EffectMetadata{ tag, target_entity, source_entity } = trigger.event().0;
+    let EffectMetadata{ tag, target_entity, .. } = trigger.event().0;
     let Ok((mut effects, mut tags)) = effects_entities_query.get_mut(target_entity) else { return };
-    if let Some(tag) = tag {
-        tags.remove(tag);
-    }
+    remove_effects_with_tag(
+        target_entity, tag, &mut effects, &mut tags,
+        &mut stats_query, &mut breached_writer, &mut removed_writer, &mut ctx,
+    );
+}
+
+/// Remove every active effect matching the tag, recalculating the stats they modified
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn remove_effects_with_tag<T: StatTrait, const N: usize, const M: usize>(
+    target_entity: Entity,
+    tag: Option<TagId>,
+    effects: &mut Mut<ActiveEffects<T, N>>,
+    tags: &mut ActiveTags<M>,
+    stats_query: &mut Query<&mut GameplayStats<T>>,
+    breached_writer: &mut MessageWriter<OnBoundsBreached<T>>,
+    removed_writer: &mut MessageWriter<OnEffectRemoved>,
+    ctx: &mut EffectContext<T>,
+) {
     let mut to_remove = SmallVec::<[usize; 8]>::new();
 
     for (index, current_effect) in effects.0.iter().enumerate() {
-        if tag == current_effect.tag {
+        if tag == current_effect.tag && !current_effect.undispellable {
             to_remove.push(index);
         }
     }
 
+    let mut removed = SmallVec::<[GameplayEffect<T>; 4]>::new();
     for &i in to_remove.iter().rev() {
         let effect = effects.0.remove(i);
-        if let Some(e) = recalculate_stats(target_entity, &effects, effect.stat_target, &mut stats_query) {
+        revert_tracked(target_entity, &effect, stats_query, ctx);
+        release_source_tag::<T, N, M>(&effect, &mut ctx.commands);
+        if let Some(e) = recalculate_stats(target_entity, effects, effect.stat_target, std::slice::from_ref(&effect), stats_query, ctx) {
             breached_writer.write(e);
         }
-        removed_writer.write(OnEffectRemoved(EffectMetadata::new(target_entity, effect.tag, source_entity)));
+        removed_writer.write(OnEffectRemoved(
+            EffectMetadata::new(target_entity, effect.tag, effect.source_entity).with_net_id(effect.net_id)
+                .with_removal_reason(RemovalReason::Removed)
+        ));
+        removed.push(effect);
+    }
+    // Undispellable effects keep their tag and stacks
+    let restacked = refresh_stack_counts(&mut effects.0, tag);
+    for e in recalculate_stats_multi(target_entity, effects, &restacked, &removed, stats_query, ctx) {
+        breached_writer.write(e);
+    }
+    if let Some(tag) = tag.filter(|&tag| tags.release(tag, &effects.0)) {
+        ctx.tag_removed_writer.write(OnTagRemoved { entity: target_entity, tag });
+    }
+}
+
+pub(crate) fn remove_effect_by_net_id<T: StatTrait, const N: usize, const M: usize>(
+    trigger: On<RemoveEffectByNetId>,
+    mut breached_writer: MessageWriter<OnBoundsBreached<T>>,
+    mut removed_writer: MessageWriter<OnEffectRemoved>,
+    mut effects_entities_query: Query<(&mut ActiveEffects<T, N>, &mut ActiveTags<M>)>,
+    mut stats_query: Query<&mut GameplayStats<T>>,
+    mut ctx: EffectContext<T>,
+) {
+    let RemoveEffectByNetId { target_entity, net_id } = *trigger.event();
+    let Ok((mut effects, mut tags)) = effects_entities_query.get_mut(target_entity) else { return };
+    let Some(index) = effects.0.iter().position(|e| e.net_id == Some(net_id)) else { return };
+
+    let effect = effects.0.remove(index);
+    revert_tracked(target_entity, &effect, &mut stats_query, &mut ctx);
+    release_source_tag::<T, N, M>(&effect, &mut ctx.commands);
+    if let Some(tag) = effect.tag.filter(|&tag| tags.release(tag, &effects.0)) {
+        ctx.tag_removed_writer.write(OnTagRemoved { entity: target_entity, tag });
     }
+    let mut affected = refresh_stack_counts(&mut effects.0, effect.tag);
+    affected.push(effect.stat_target);
+    for e in recalculate_stats_multi(target_entity, &effects, &affected, std::slice::from_ref(&effect), &mut stats_query, &mut ctx) {
+        breached_writer.write(e);
+    }
+    removed_writer.write(OnEffectRemoved(
+        EffectMetadata::new(target_entity, effect.tag, effect.source_entity).with_net_id(effect.net_id)
+            .with_removal_reason(RemovalReason::Removed)
+    ));
 }
 
-pub(crate) fn process_active_effects<T: StatTrait>(
-    time: Res<Time>,
+pub(crate) fn remove_effect_group<T: StatTrait, const N: usize, const M: usize>(
+    trigger: On<RemoveEffectGroup>,
+    mut breached_writer: MessageWriter<OnBoundsBreached<T>>,
+    mut removed_writer: MessageWriter<OnEffectRemoved>,
+    mut effects_entities_query: Query<(&mut ActiveEffects<T, N>, &mut ActiveTags<M>)>,
     mut stats_query: Query<&mut GameplayStats<T>>,
-    mut entity_effects_query: Query<(Entity, &mut ActiveEffects<T>, &mut ActiveTags)>,
-    mut periodic_event_writer: MessageWriter<OnRepeatingEffectTriggered>,
+    mut ctx: EffectContext<T>,
+) {
+    let RemoveEffectGroup(target_entity, group_id) = *trigger.event();
+    let Ok((mut effects, mut tags)) = effects_entities_query.get_mut(target_entity) else { return };
+
+    let removed = effects.retain_effects(|effect| effect.group_id != Some(group_id));
+    finish_removal::<T, N, M>(target_entity, &mut effects.0, &mut tags, removed, &mut stats_query, &mut breached_writer, &mut removed_writer, &mut ctx);
+}
+
+pub(crate) fn retain_effects<T: StatTrait, const N: usize, const M: usize>(
+    trigger: On<RetainEffects<T>>,
     mut breached_writer: MessageWriter<OnBoundsBreached<T>>,
     mut removed_writer: MessageWriter<OnEffectRemoved>,
+    mut effects_entities_query: Query<(&mut ActiveEffects<T, N>, &mut ActiveTags<M>)>,
+    mut stats_query: Query<&mut GameplayStats<T>>,
+    mut ctx: EffectContext<T>,
 ) {
-    entity_effects_query.iter_mut().for_each(|(entity, mut effects, mut tags)| {
-
-        // Tick all the timers
-        for effect in effects.0.iter_mut() {
-            match &mut effect.duration {
-                EffectDuration::Continuous(Some(timer)) => { timer.tick(time.delta_secs()); },
-                EffectDuration::Persistent(Some(timer)) => { timer.tick(time.delta_secs()); },
-                EffectDuration::Repeating(period, timer) => {
-                    period.tick(time.delta_secs());
-                    if let Some(timer) = timer {
-                        timer.tick(time.delta_secs());
-                    }
-                },
-                _ => {}
-            }
+    let event = trigger.event();
+    let Ok((mut effects, mut tags)) = effects_entities_query.get_mut(event.target_entity) else { return };
+    let removed = effects.retain_effects(|effect| effect.undispellable || (event.predicate)(effect));
+    finish_removal::<T, N, M>(event.target_entity, &mut effects.0, &mut tags, removed, &mut stats_query, &mut breached_writer, &mut removed_writer, &mut ctx);
+}
+
+/// Drop the warmup effects with the tag queued for the entity, announcing each as interrupted
+pub(crate) fn cancel_pending<T: StatTrait>(
+    trigger: On<CancelPending>,
+    mut pending: ResMut<PendingEffects<T>>,
+    mut interrupted_writer: MessageWriter<OnEffectInterrupted>,
+) {
+    let CancelPending(entity, tag) = *trigger.event();
+    pending.0.retain(|(_, data)| {
+        if data.target_entity != entity || data.effect.tag != Some(tag) { return true }
+        interrupted_writer.write(OnEffectInterrupted(
+            EffectMetadata::new(entity, data.effect.tag, data.source_entity).with_net_id(data.effect.net_id)
+        ));
+        if let Some(sender) = &data.outcome_sender {
+            sender.send(EffectOutcome::blocked(EffectBlockedReason::Interrupted));
         }
-        
-        let mut removed = SmallVec::<[usize; 8]>::new();
+        false
+    });
+}
+
+/// Give an entity gaining ActiveEffects the ActiveTags<M> of the plugin's tag capacity
+pub(crate) fn insert_active_tags<T: StatTrait, const N: usize, const M: usize>(
+    trigger: On<Add, ActiveEffects<T, N>>,
+    mut commands: Commands,
+) {
+    commands.entity(trigger.entity).insert_if_new(ActiveTags::<M>::default());
+}
+
+/// Emit OnEffectRemoved for every effect left on an entity which is despawned or loses its ActiveEffects
+pub(crate) fn remove_effects_on_despawn<T: StatTrait, const N: usize, const M: usize>(
+    trigger: On<Remove, ActiveEffects<T, N>>,
+    effects_query: Query<&ActiveEffects<T, N>>,
+    mut removed_writer: MessageWriter<OnEffectRemoved>,
+    mut commands: Commands,
+) {
+    let Ok(effects) = effects_query.get(trigger.entity) else { return };
+    for effect in effects.0.iter() {
+        release_source_tag::<T, N, M>(effect, &mut commands);
+        removed_writer.write(OnEffectRemoved(
+            EffectMetadata::new(trigger.entity, effect.tag, effect.source_entity).with_net_id(effect.net_id)
+                .with_removal_reason(RemovalReason::Despawned)
+        ));
+    }
+}
+
+/// Drop tags no other effect carries, recalculate each affected stat once and announce the removals
+#[allow(clippy::too_many_arguments)]
+fn finish_removal<T: StatTrait, const N: usize, const M: usize>(
+    target_entity: Entity,
+    effects: &mut [GameplayEffect<T>],
+    tags: &mut ActiveTags<M>,
+    removed: SmallVec<[GameplayEffect<T>; 4]>,
+    stats_query: &mut Query<&mut GameplayStats<T>>,
+    breached_writer: &mut MessageWriter<OnBoundsBreached<T>>,
+    removed_writer: &mut MessageWriter<OnEffectRemoved>,
+    ctx: &mut EffectContext<T>,
+) {
+    let mut affected = SmallVec::<[T; 4]>::new();
+    for effect in removed.iter() {
+        if let Some(tag) = effect.tag.filter(|&tag| tags.release(tag, effects)) {
+            ctx.tag_removed_writer.write(OnTagRemoved { entity: target_entity, tag });
+        }
+        revert_tracked(target_entity, effect, stats_query, ctx);
+        release_source_tag::<T, N, M>(effect, &mut ctx.commands);
+        affected.push(effect.stat_target);
+        affected.extend(refresh_stack_counts(effects, effect.tag));
+    }
+    for e in recalculate_stats_multi(target_entity, effects, &affected, &removed, stats_query, ctx) {
+        breached_writer.write(e);
+    }
+    for effect in removed {
+        removed_writer.write(OnEffectRemoved(
+            EffectMetadata::new(target_entity, effect.tag, effect.source_entity).with_net_id(effect.net_id)
+                .with_removal_reason(RemovalReason::Removed)
+        ));
+    }
+}
 
-        // Now apply effects for this frame
+/// Run condition of evaluate_effect_conditions, which as an exclusive system would otherwise block
+/// the schedule every frame even when no active effect has a condition
+pub(crate) fn has_effect_conditions<T: StatTrait, const N: usize>(effects_query: Query<&ActiveEffects<T, N>>) -> bool {
+    effects_query.iter().any(|effects| effects.0.iter().any(|effect| effect.condition.is_some()))
+}
+
+/// Conditions need the whole World, so evaluate them up front and cache the result on each effect
+pub(crate) fn evaluate_effect_conditions<T: StatTrait, const N: usize>(world: &mut World) {
+    let mut conditions = Vec::new();
+    let mut query = world.query::<(Entity, &ActiveEffects<T, N>)>();
+    for (entity, effects) in query.iter(world) {
         for (idx, effect) in effects.0.iter().enumerate() {
-            // Get effect magnitude
-            let source = get_effect_source_stats(effect, entity, &mut stats_query);
-            if matches!(effect.magnitude, EffectMagnitude::NonlocalStat(..)) && source.is_none() { // Source entity gone
-                removed.push(idx); 
+            if let Some(condition) = &effect.condition {
+                conditions.push((entity, idx, condition.clone()));
             }
-            let mut amount = get_effect_amount(effect, source);
-            if matches!(effect.duration, EffectDuration::Continuous(_)) {
-                amount *= time.delta_secs();
-                // TODO check effect saturation so framerate spikes don't cause a huge effect
+        }
+    }
+    if conditions.is_empty() { return; }
+
+    let results: Vec<bool> = conditions.iter()
+        .map(|(entity, _, condition)| (condition.0)(*entity, &*world))
+        .collect();
+    for ((entity, idx, _), met) in conditions.into_iter().zip(results) {
+        if let Some(mut effects) = world.get_mut::<ActiveEffects<T, N>>(entity) {
+            effects.0[idx].condition_met = met;
+        }
+    }
+}
+
+pub(crate) fn process_active_effects<T: StatTrait, const N: usize, const M: usize>(
+    mut stats_query: Query<&mut GameplayStats<T>>,
+    mut entity_effects_query: Query<(Entity, &mut ActiveEffects<T, N>, &mut ActiveTags<M>)>,
+    mut writers: EffectWriters<T>,
+    mut state: ProcessingState<T, N>,
+    mut ctx: EffectContext<T>,
+) {
+    for entity in state.despawned.read() {
+        state.budget.last_processed.remove(&entity);
+    }
+    // With a fixed step everything below runs once per whole step that elapsed, on a quantized clock
+    for (delta, elapsed) in state.clock.0.steps(&state.time, ctx.settings.fixed_step) {
+        state.pending.0.retain_mut(|(remaining, data)| {
+            *remaining -= delta;
+            if *remaining > 0. { return true }
+            ctx.commands.trigger(AddEffect(data.clone()));
+            false
+        });
+
+        let snapshot = ctx.settings.snapshot_sources.then(|| {
+            let mut snapshot = HashMap::new();
+            for (_, effects, _) in entity_effects_query.iter() {
+                for effect in effects.0.iter() {
+                    let Some(source_entity) = effect.magnitude.source_entity() else { continue };
+                    if let Ok(stats) = stats_query.get(source_entity) {
+                        snapshot.entry(source_entity).or_insert_with(|| stats.clone());
+                    }
+                }
             }
+            snapshot
+        });
+
+        let window = state.budget.next_window(entity_effects_query.iter().map(|(entity, _, _)| entity).collect());
+        entity_effects_query.iter_mut().for_each(|(entity, mut effects, mut tags)| {
+            // Outside the budget's window only continuous effects are processed, by the frame time
+            let in_window = window.as_ref().is_none_or(|window| window.binary_search(&entity).is_ok());
+            let continuous = |effect: &GameplayEffect<T>| matches!(effect.duration, EffectDuration::Continuous(_));
+            if !in_window && !effects.0.iter().any(continuous) { return; }
+            let budget_delta = if in_window { state.budget.elapsed_since_processed(entity, elapsed, delta) } else { 0. };
+            if !stats_query.contains(entity) { return; }
+            let stats_before = if ctx.settings.batch_stat_changes {
+                stats_query.get(entity).ok().cloned()
+            } else { None };
 
-            // Check for expiration timers
-            if let Some(timer) = effect.get_duration_timer() {
-                if timer.finished() {
-                    removed.push(idx);
+            let suppressed: SmallVec<[Option<SuppressionMode>; ACTIVE_EFFECTS_SIZE]> = effects.0.iter()
+                .map(|effect| effect.tag.and_then(|tag| state.suppressions.get(tag, &tags, state.tag_registry.as_deref())))
+                .collect();
+
+            // Tick all the timers
+            let mut scheduled_fires = SmallVec::<[(usize, usize); 4]>::new();
+            for (idx, effect) in effects.0.iter_mut().enumerate() {
+                if suppressed[idx] == Some(SuppressionMode::Freeze) { continue }
+                let delta = match continuous(effect) {
+                    true => delta,
+                    false if in_window => budget_delta,
+                    false => continue,
+                };
+                match &mut effect.duration {
+                    EffectDuration::Continuous(Some(timer)) => { timer.tick(delta); },
+                    EffectDuration::Persistent(Some(timer)) | EffectDuration::Tracked(Some(timer)) => { timer.tick(delta); },
+                    EffectDuration::Repeating(period, timer) => {
+                        period.tick(delta);
+                        if let Some(timer) = timer {
+                            timer.tick(delta);
+                        }
+                    },
+                    EffectDuration::Scheduled(times) => {
+                        // A long frame can pass several scheduled points at once
+                        let pending = times.len();
+                        times.retain(|&t| t > elapsed);
+                        if times.len() < pending {
+                            scheduled_fires.push((idx, pending - times.len()));
+                        }
+                    },
+                    _ => {}
+                }
+                if let (Some(warn_before), Some(remaining)) = (effect.warn_before, effect.remaining_duration()) {
+                    if !effect.warned && remaining <= warn_before {
+                        effect.warned = true;
+                        writers.expiring.write(OnEffectExpiring(
+                            EffectMetadata::new(entity, effect.tag, effect.source_entity).with_net_id(effect.net_id)
+                        ));
+                    }
                 }
             }
+        
+            // Refresh cached magnitudes whose source stats changed since they were computed
+            for effect in effects.0.iter_mut().filter(|e| e.cache_magnitude) {
+                let EffectMagnitude::NonlocalStat(_, _, source_entity) = effect.magnitude else { continue };
+                let Ok(source) = stats_query.get_mut(source_entity) else {
+                    effect.magnitude_cache = None;
+                    continue;
+                };
+                let changed = source.last_changed();
+                if effect.magnitude_cache.is_some_and(|(tick, _)| tick == changed) { continue }
+                effect.magnitude_cache = Some((changed, get_base_magnitude(effect, Some(&source), &mut ctx)));
+            }
 
-            // Persistent and immediate effects are already applied
-            let apply = match effect.duration {
-                EffectDuration::Repeating(period, _) => {
-                    if period.just_triggered() {
-                        periodic_event_writer.write(OnRepeatingEffectTriggered(EffectMetadata::new(
-                            entity, effect.tag, None
-                        )));
-                        true
-                    } else { false }
-                },
-                EffectDuration::Continuous(_) => { true },
-                _ => { false }
-            };
-            if apply {
-                if let Some(event) = apply_immediate(entity, effect, &mut stats_query, amount, &effects) {
-                    breached_writer.write(event);
+            let mut removed = SmallVec::<[(usize, RemovalReason); 8]>::new();
+            let mut budgets = SmallVec::<[(usize, f32); 4]>::new();
+
+            // Now apply effects for this frame, in calculation phase order so the result
+            // does not depend on insertion order.  The sort is stable within a phase.
+            let mut order: SmallVec<[usize; ACTIVE_EFFECTS_SIZE]> = (0..effects.0.len()).collect();
+            order.sort_by_key(|&i| effects.0[i].calculation.phase());
+            for idx in order {
+                let effect = &effects.0[idx];
+                if !in_window && !continuous(effect) { continue }
+                // Get effect magnitude
+                let source = match (effect.magnitude.source_entity(), &snapshot) {
+                    (Some(source_entity), Some(snapshot)) if source_entity != entity => snapshot.get(&source_entity),
+                    _ => get_effect_source_stats(effect, entity, &stats_query),
+                };
+                if let (Some(source_entity), None) = (effect.magnitude.source_entity(), source) {
+                    let reason = if state.entities.contains(source_entity) { RemovalReason::SourceInvalid } else { RemovalReason::SourceDespawned };
+                    removed.push((idx, reason));
+                    continue;
+                }
+                let mut amount = match effect.magnitude_cache {
+                    Some((_, magnitude)) if source.is_some() => scale_effect_amount(effect, magnitude, &stats_query, &mut ctx),
+                    _ => get_effect_amount(effect, source, &stats_query, &mut ctx),
+                };
+                if matches!(effect.duration, EffectDuration::Continuous(_)) {
+                    amount *= delta;
+                    // TODO check effect saturation so framerate spikes don't cause a huge effect
+                }
+
+                // Check for expiration timers
+                if let Some(timer) = effect.get_duration_timer() {
+                    if timer.finished() {
+                        removed.push((idx, RemovalReason::Expired));
+                    }
+                }
+                if let EffectDuration::Scheduled(times) = &effect.duration {
+                    if times.is_empty() {
+                        removed.push((idx, RemovalReason::Expired));
+                    }
+                }
+
+                // Persistent and immediate effects are already applied
+                let applications = match &effect.duration {
+                    _ if !effect.condition_met || suppressed[idx].is_some() => 0,
+                    EffectDuration::Repeating(period, _) => {
+                        if period.just_triggered() {
+                            writers.repeating.write(OnRepeatingEffectTriggered(
+                                EffectMetadata::new(entity, effect.tag, None).with_net_id(effect.net_id)
+                            ));
+                            1
+                        } else { 0 }
+                    },
+                    EffectDuration::Scheduled(_) => {
+                        let fired = scheduled_fires.iter()
+                            .find(|(i, _)| *i == idx)
+                            .map_or(0, |(_, n)| *n);
+                        for _ in 0..fired {
+                            writers.repeating.write(OnRepeatingEffectTriggered(
+                                EffectMetadata::new(entity, effect.tag, None).with_net_id(effect.net_id)
+                            ));
+                        }
+                        fired
+                    },
+                    EffectDuration::Continuous(_) => { 1 },
+                    _ => { 0 }
+                };
+                let mut budget = effect.budget.filter(|_| effect.calculation == EffectCalculation::Additive);
+                for _ in 0..applications {
+                    let retargeted = effect.random_target.then(|| {
+                        let mut retargeted = effect.clone();
+                        retargeted.stat_target = ctx.rng.pick(T::variants()).unwrap_or(T::NONE);
+                        retargeted
+                    });
+                    let effect = retargeted.as_ref().unwrap_or(effect);
+                    if let Some(remaining) = &mut budget {
+                        // Charge what lands on the stat, after the target's incoming multiplier
+                        let multiplier = stats_query.get(entity).map_or(1., |stats| ctx.incoming.multiplier(stats, effect, amount));
+                        let landed = (amount * multiplier).abs();
+                        let charged = landed.min(*remaining);
+                        if landed > 0. {
+                            amount *= charged / landed;
+                        }
+                        *remaining -= charged;
+                    }
+                    if let Some(event) = apply_immediate(entity, effect, &mut stats_query, amount, &effects, &mut ctx) {
+                        writers.breached.write(event);
+                    }
+                }
+                if let Some(remaining) = budget.filter(|_| applications > 0) {
+                    budgets.push((idx, remaining));
+                }
+            }
+            for (idx, remaining) in budgets {
+                effects.0[idx].budget = Some(remaining);
+                if remaining <= 0. {
+                    removed.push((idx, RemovalReason::BudgetExhausted));
                 }
             }
-        }
 
-        for &i in removed.iter().rev() {
-            let effect = effects.0.remove(i);
-            if matches!(effect.duration, EffectDuration::Persistent(_)) {
-                if let Some(e) = recalculate_stats(entity, &effects, effect.stat_target, &mut stats_query) {
-                    breached_writer.write(e);
+            // An effect can expire for several reasons in one frame, only remove it once with the first reason.
+            // remove() shifts the tail down so surviving effects keep their relative order.
+            removed.sort_by_key(|&(i, _)| i);
+            removed.dedup_by_key(|&mut (i, _)| i);
+            for &(i, reason) in removed.iter().rev() {
+                let effect = effects.0.remove(i);
+                revert_tracked(entity, &effect, &mut stats_query, &mut ctx);
+                release_source_tag::<T, N, M>(&effect, &mut ctx.commands);
+                let mut affected = refresh_stack_counts(&mut effects.0, effect.tag);
+                if matches!(effect.duration, EffectDuration::Persistent(_) | EffectDuration::Tracked(_)) {
+                    affected.push(effect.stat_target);
+                }
+                for e in recalculate_stats_multi(entity, &effects, &affected, std::slice::from_ref(&effect), &mut stats_query, &mut ctx) {
+                    writers.breached.write(e);
+                }
+                if let Some(tag) = effect.tag.filter(|&tag| tags.release(tag, &effects.0)) {
+                    ctx.tag_removed_writer.write(OnTagRemoved { entity, tag });
                 }
+                writers.removed.write(OnEffectRemoved(
+                    EffectMetadata::new(entity, effect.tag, effect.source_entity).with_net_id(effect.net_id).with_removal_reason(reason)
+                ));
             }
-            if let Some(tag) = effect.tag {
-                tags.remove(tag);
+
+            if let Some(before) = stats_before {
+                if let Ok(after) = stats_query.get(entity) {
+                    for &stat in T::variants() {
+                        let total_delta = after.get(stat).current_value - before.get(stat).current_value;
+                        if total_delta != 0. {
+                            writers.batched.write(OnStatChangedBatched { entity, stat, total_delta });
+                        }
+                    }
+                }
             }
-            removed_writer.write(OnEffectRemoved(EffectMetadata::new(entity, effect.tag, None)));
-        }
-    });
-}
\ No newline at end of file
+        });
+    }
+}
@@ -1,11 +1,12 @@
 #[macro_export]
 macro_rules! stats {
     ($name:ident { $($variant:ident),* $(,)? }) => {
-        #[repr(u8)]
-        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        // usize::MAX keeps None clear of every real variant, and Into<u8> still maps it to u8::MAX
+        #[repr(usize)]
+        #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
         pub enum $name {
             $($variant,)*
-            None = 255
+            None = usize::MAX
         }
 
         impl From<$name> for u8 {
@@ -14,6 +15,12 @@ macro_rules! stats {
             }
         }
 
+        impl From<$name> for usize {
+            fn from(e: $name) -> Self {
+                e as usize
+            }
+        }
+
         impl $name {
             pub fn from_u8(value: u8) -> Self {
                 match value {
@@ -29,9 +36,11 @@ macro_rules! stats {
             fn variants() -> &'static [Self] {
                 &[$(Self::$variant),*]
             }
-        }
 
-        // Array holding all variants
-        //pub const $name_VARIANTS: &[$name] = &[$($name::$variant),*];
+            // Macro discriminants are contiguous from 0 so skip the variants() lookup
+            fn slot(self) -> Option<usize> {
+                if self == $name::None { None } else { Some(self as usize) }
+            }
+        }
     };
 }
@@ -1,29 +1,93 @@
-use bevy::prelude::*;
+use std::sync::Arc;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_hierarchical_tags::TagId;
-use crate::prelude::*;
+use crate::{prelude::*, outcome::{outcome_channel, EffectOutcomeSender}};
 
 #[derive(Clone)]
 pub struct AddEffectData<T: StatTrait> {
     pub target_entity: Entity,
     pub effect: GameplayEffect<T>,
     pub source_entity: Option<Entity>,
+    pub(crate) outcome_sender: Option<EffectOutcomeSender>,
+    pub(crate) resumed: bool,
+    pub(crate) stats_snapshot: Option<Box<GameplayStats<T>>>,
 }
 
 impl<T: StatTrait> AddEffectData<T> {
     pub fn new(target_entity: Entity, effect: GameplayEffect<T>, source_entity: Option<Entity>) -> Self {
-        Self { effect, target_entity, source_entity }
+        Self { effect, target_entity, source_entity, outcome_sender: None, resumed: false, stats_snapshot: None }
+    }
+
+    /// Effect an entity applies to itself, e.g. a self-buff.  The entity is both target and source,
+    /// and NonlocalStat magnitudes reading from it become LocalStat.
+    pub fn self_effect(entity: Entity, effect: GameplayEffect<T>) -> Self {
+        Self::new(entity, effect.localize(entity), Some(entity))
+    }
+
+    /// Attach a one-shot channel which receives the outcome once the effect is processed
+    pub fn with_outcome(mut self) -> (Self, EffectOutcomeReceiver) {
+        let (sender, receiver) = outcome_channel();
+        self.outcome_sender = Some(sender);
+        (self, receiver)
+    }
+
+    /// Resume an effect partway through its duration, e.g. when loading a save.  The duration timer
+    /// keeps its full length but starts with this much remaining, and resistances do not shorten it again.
+    pub fn with_remaining(mut self, remaining: f32) -> Self {
+        if let Some(timer) = self.effect.get_duration_timer_mut() {
+            *timer = timer.clone().with_remaining(remaining);
+        }
+        self.resumed = true;
+        self
+    }
+
+    /// Resolve the magnitude against past stats of the target, e.g. from StatSnapshots::stats_at for lag
+    /// compensation.  Magnitudes reading the target's own stats are fixed to the snapshot's values.
+    pub fn with_stats_snapshot(mut self, stats: GameplayStats<T>) -> Self {
+        self.stats_snapshot = Some(Box::new(stats));
+        self
+    }
+
+    /// Assign a replication id so this specific effect instance can be referenced later
+    pub fn with_net_id(mut self, net_id: NetEffectId) -> Self {
+        self.effect.net_id = Some(net_id);
+        self
     }
 }
 
+/// Why an effect was removed, carried by OnEffectRemoved
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RemovalReason {
+    Removed, // by RemoveEffect, RemoveEffectByNetId, RemoveEffectGroup, RetainEffects or a mutex group
+    Expired,
+    BudgetExhausted, // the effect applied its whole budget
+    SourceDespawned, // a NonlocalStat effect's source entity no longer exists
+    SourceInvalid, // a NonlocalStat effect's source entity exists but lost its GameplayStats
+    Despawned, // the target entity was despawned or lost its ActiveEffects
+}
+
 pub struct EffectMetadata {
     pub target_entity: Entity,
     pub tag: Option<TagId>,
     pub source_entity: Option<Entity>,
+    pub net_id: Option<NetEffectId>,
+    /// Only set on OnEffectRemoved
+    pub removal_reason: Option<RemovalReason>,
 }
 
 impl EffectMetadata {
     pub fn new(target_entity: Entity, tag: Option<TagId>, source_entity: Option<Entity>) -> Self {
-        Self { source_entity, target_entity, tag }
+        Self { source_entity, target_entity, tag, net_id: None, removal_reason: None }
+    }
+
+    pub fn with_net_id(mut self, net_id: Option<NetEffectId>) -> Self {
+        self.net_id = net_id;
+        self
+    }
+
+    pub fn with_removal_reason(mut self, reason: RemovalReason) -> Self {
+        self.removal_reason = Some(reason);
+        self
     }
 }
 
@@ -31,11 +95,13 @@ pub struct BoundsBreachedMetadata<T> {
     pub target_entity: Entity,
     pub stat: T,
     pub bound: EffectCalculation,
+    /// How far past the bound the value was before it was clamped, e.g. excess damage
+    pub overshoot: f32,
 }
 
 impl<T: StatTrait> BoundsBreachedMetadata<T> {
-    pub fn new(entity: Entity, stat: T, bound: EffectCalculation) -> Self {
-        Self { target_entity: entity, stat, bound }
+    pub fn new(entity: Entity, stat: T, bound: EffectCalculation, overshoot: f32) -> Self {
+        Self { target_entity: entity, stat, bound, overshoot }
     }
 }
 
@@ -45,14 +111,180 @@ pub struct AddEffect<T: StatTrait>(pub AddEffectData<T>);
 #[derive(Event, Deref)]
 pub struct RemoveEffect(pub EffectMetadata);
 
+/// Remove the single active effect carrying this id
+#[derive(Event, Clone, Copy)]
+pub struct RemoveEffectByNetId {
+    pub target_entity: Entity,
+    pub net_id: NetEffectId,
+}
+
+/// Remove every effect on the entity sharing this group id
+#[derive(Event, Clone, Copy)]
+pub struct RemoveEffectGroup(pub Entity, pub u64);
+
+/// Abort the effects with this tag still warming up on the entity
+#[derive(Event, Clone, Copy)]
+pub struct CancelPending(pub Entity, pub TagId);
+
+/// Remove every effect on the entity the predicate rejects, except undispellable ones, then recalculate the affected stats
+#[derive(Event, Clone)]
+pub struct RetainEffects<T: StatTrait> {
+    pub target_entity: Entity,
+    pub predicate: Arc<dyn Fn(&GameplayEffect<T>) -> bool + Send + Sync>,
+}
+
+impl<T: StatTrait> RetainEffects<T> {
+    pub fn new(target_entity: Entity, predicate: impl Fn(&GameplayEffect<T>) -> bool + Send + Sync + 'static) -> Self {
+        Self { target_entity, predicate: Arc::new(predicate) }
+    }
+}
+
+/// Fast path for one-shot additive changes such as damage.  Applies directly
+/// to the stat, skipping effect storage, tags and stacking.
+#[derive(Event, Clone, Copy)]
+pub struct ApplyInstant<T: StatTrait> {
+    pub target_entity: Entity,
+    pub stat: T,
+    pub amount: f32,
+    pub source_entity: Option<Entity>,
+}
+
+/// Shorthand for triggering ApplyInstant
+pub trait InstantEffectCommandsExt {
+    fn apply_instant<T: StatTrait>(&mut self, entity: Entity, stat: T, amount: f32, source: Option<Entity>);
+
+    /// Subtract amount from the stat
+    fn damage<T: StatTrait>(&mut self, entity: Entity, stat: T, amount: f32) {
+        self.apply_instant(entity, stat, -amount, None);
+    }
+}
+
+impl InstantEffectCommandsExt for Commands<'_, '_> {
+    fn apply_instant<T: StatTrait>(&mut self, entity: Entity, stat: T, amount: f32, source: Option<Entity>) {
+        self.trigger(ApplyInstant { target_entity: entity, stat, amount, source_entity: source });
+    }
+}
+
+/// Immediate mode helpers for tests and scripts working on the World directly
+pub trait EffectWorldExt {
+    /// Trigger AddEffect and return the target stat's current value right after, None if the entity
+    /// has no such stat.  Effects with a warmup are only queued, so the value is unchanged.
+    fn apply_effect_now<T: StatTrait>(&mut self, entity: Entity, effect: GameplayEffect<T>) -> Option<f32>;
+}
+
+impl EffectWorldExt for World {
+    fn apply_effect_now<T: StatTrait>(&mut self, entity: Entity, effect: GameplayEffect<T>) -> Option<f32> {
+        let stat = effect.stat_target;
+        let source = effect.source_entity;
+        self.trigger(AddEffect(AddEffectData::new(entity, effect, source)));
+        self.get::<GameplayStats<T>>(entity)
+            .and_then(|stats| stats.try_get(stat))
+            .map(|stat| stat.current_value)
+    }
+}
+
 #[derive(Message, Deref)]
 pub struct OnEffectAdded(pub EffectMetadata);
 
 #[derive(Message, Deref)]
 pub struct OnEffectRemoved(pub EffectMetadata);
 
+/// A tag became active on an entity because an effect carrying it was added
+#[derive(Message, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OnTagAdded {
+    pub entity: Entity,
+    pub tag: TagId,
+}
+
+/// The last effect carrying a tag was removed or expired, so the tag is no longer active
+#[derive(Message, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OnTagRemoved {
+    pub entity: Entity,
+    pub tag: TagId,
+}
+
+/// An effect carrying a payload was applied, for user systems to interpret, e.g. as a knockback
+#[derive(Message)]
+pub struct OnEffectPayload {
+    pub metadata: EffectMetadata,
+    pub payload: EffectPayload,
+}
+
+/// An effect's remaining duration dropped below its expiry warning threshold
+#[derive(Message, Deref)]
+pub struct OnEffectExpiring(pub EffectMetadata);
+
+#[derive(Message, Deref)]
+pub struct OnEffectInterrupted(pub EffectMetadata);
+
 #[derive(Message, Deref)]
 pub struct OnRepeatingEffectTriggered(pub EffectMetadata);
 
 #[derive(Message, Deref)]
-pub struct OnBoundsBreached<T: StatTrait>(pub BoundsBreachedMetadata<T>);
\ No newline at end of file
+pub struct OnBoundsBreached<T: StatTrait>(pub BoundsBreachedMetadata<T>);
+
+/// Sum of all changes to one stat on one entity during a frame of effect processing
+#[derive(Message)]
+pub struct OnStatChangedBatched<T: StatTrait> {
+    pub entity: Entity,
+    pub stat: T,
+    pub total_delta: f32,
+}
+
+/// Change to a stat made through ApplyInstant
+#[derive(Message)]
+pub struct OnStatChanged<T: StatTrait> {
+    pub entity: Entity,
+    pub stat: T,
+    pub delta: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CrossingDirection {
+    Upward,
+    Downward,
+}
+
+/// A stat moved across one of the StatThresholds, threshold being its fraction of the reference stat
+#[derive(Message)]
+pub struct OnThresholdCrossed<T: StatTrait> {
+    pub entity: Entity,
+    pub stat: T,
+    pub threshold: f32,
+    pub direction: CrossingDirection,
+}
+
+/// A DespawnOnBound entity breached its bound and will be despawned after the component's delay
+#[derive(Message)]
+pub struct OnDeath<T: StatTrait> {
+    pub entity: Entity,
+    pub stat: T,
+}
+
+/// Readers for the common effect messages bundled into one system parameter, instead of a
+/// MessageReader per message.  Each method drains the messages it has not returned yet.
+#[derive(SystemParam)]
+pub struct EffectEvents<'w, 's, T: StatTrait> {
+    added: MessageReader<'w, 's, OnEffectAdded>,
+    removed: MessageReader<'w, 's, OnEffectRemoved>,
+    repeating: MessageReader<'w, 's, OnRepeatingEffectTriggered>,
+    breached: MessageReader<'w, 's, OnBoundsBreached<T>>,
+}
+
+impl<T: StatTrait> EffectEvents<'_, '_, T> {
+    pub fn added(&mut self) -> impl Iterator<Item = &EffectMetadata> {
+        self.added.read().map(|e| &e.0)
+    }
+
+    pub fn removed(&mut self) -> impl Iterator<Item = &EffectMetadata> {
+        self.removed.read().map(|e| &e.0)
+    }
+
+    pub fn repeating(&mut self) -> impl Iterator<Item = &EffectMetadata> {
+        self.repeating.read().map(|e| &e.0)
+    }
+
+    pub fn breached(&mut self) -> impl Iterator<Item = &BoundsBreachedMetadata<T>> {
+        self.breached.read().map(|e| &e.0)
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+use bevy::prelude::*;
+use crate::{prelude::*, calculation::{recalculate_stats, EffectContext}};
+
+type Formula<T> = Arc<dyn Fn(&GameplayStats<T>) -> f32 + Send + Sync>;
+
+/// Derived stats whose base value is computed from other stats on the same entity,
+/// e.g. AttackPower = Strength * 2 + Level.  Formulas are kept in dependency order,
+/// so a formula reading another derived stat always sees its updated value.
+#[derive(Resource, Clone)]
+pub struct StatFormulas<T: StatTrait>(Vec<(T, Vec<T>, Formula<T>)>);
+
+impl<T: StatTrait> Default for StatFormulas<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: StatTrait> StatFormulas<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compute stat's base value from the current values of the inputs.  Panics if the formulas form a cycle.
+    pub fn formula(mut self, stat: T, inputs: &[T], f: impl Fn(&GameplayStats<T>) -> f32 + Send + Sync + 'static) -> Self {
+        self.0.push((stat, inputs.to_vec(), Arc::new(f)));
+        self.sort();
+        self
+    }
+
+    /// Stable topological sort, each formula placed after the formulas producing its inputs
+    fn sort(&mut self) {
+        let mut unsorted = std::mem::take(&mut self.0);
+        while !unsorted.is_empty() {
+            let ready = unsorted.iter().position(|(stat, inputs, _)| {
+                !inputs.iter().any(|input| input != stat && unsorted.iter().any(|(other, _, _)| other == input))
+            }).expect("Stat formulas must not depend on each other cyclically");
+            self.0.push(unsorted.remove(ready));
+        }
+    }
+}
+
+/// Re-evaluates the formulas on entities whose stats changed, recalculating each derived stat whose base moved
+pub(crate) fn apply_stat_formulas<T: StatTrait, const N: usize>(
+    formulas: Res<StatFormulas<T>>,
+    effects_query: Query<(Entity, &ActiveEffects<T, N>)>,
+    mut stats_query: Query<&mut GameplayStats<T>>,
+    mut breached_writer: MessageWriter<OnBoundsBreached<T>>,
+    mut ctx: EffectContext<T>,
+) {
+    if formulas.0.is_empty() {
+        return;
+    }
+    for (entity, effects) in effects_query.iter() {
+        if !stats_query.get_mut(entity).is_ok_and(|stats| stats.is_changed()) { continue }
+        for (stat, _, formula) in formulas.0.iter() {
+            let Ok(stats) = stats_query.get(entity) else { break };
+            let base = formula(stats);
+            if stats.try_get(*stat).is_none_or(|s| s.base_value == base) { continue }
+            if let Some(s) = stats_query.get_mut(entity).ok().as_mut().and_then(|stats| stats.try_get_mut(*stat)) {
+                s.base_value = base;
+            }
+            if let Some(e) = recalculate_stats(entity, &effects.0, *stat, &[], &mut stats_query, &mut ctx) {
+                breached_writer.write(e);
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use std::marker::PhantomData;
-use bevy::prelude::Component;
+use std::{collections::HashMap, hash::Hash, marker::PhantomData};
+use bevy::prelude::*;
+use crate::calculation::EffectCalculation;
 
 pub(crate) const STAT_LIMIT: usize = 16;
 
@@ -8,17 +9,55 @@ pub struct GameplayStat {
     pub current_value: f32,
     pub base_value: f32,
     pub(crate) modified_base: f32,
+    pub(crate) at_bound: Option<Bound>,
+    pub(crate) last_delta: f32,
+    pub(crate) previous_value: f32,
+}
+
+/// Bound a stat was clamped to, kept apart from EffectCalculation so GameplayStat stays Copy
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum Bound {
+    Lower,
+    Upper,
+}
+
+impl Bound {
+    fn calculation(self) -> EffectCalculation {
+        match self {
+            Bound::Lower => EffectCalculation::LowerBound,
+            Bound::Upper => EffectCalculation::UpperBound,
+        }
+    }
 }
 
 impl GameplayStat {
     pub fn new(base_value: f32, current_value: f32) -> Self {
-        Self { base_value, current_value, modified_base: base_value }
+        Self { base_value, current_value, modified_base: base_value, at_bound: None, last_delta: 0., previous_value: current_value }
+    }
+
+    /// The bound the current value was clamped to the last time an effect changed it
+    pub fn at_bound(&self) -> Option<EffectCalculation> {
+        self.at_bound.map(Bound::calculation)
+    }
+
+    /// Net change of the current value over the last update, including changes made between updates
+    pub fn last_delta(&self) -> f32 {
+        self.last_delta
     }
 }
 
-pub trait StatTrait: Copy + Eq + Into<u8> + Send + Sync + 'static {
+/// Into<usize> gives each variant a stable numeric id, which need not fit in a u8.  Discriminants
+/// may be large, but GameplayStats holds at most 16 stats, so an enum may have at most 16 real
+/// variants and GameplayStats::new panics if it has more.
+pub trait StatTrait: Copy + Eq + Into<usize> + Send + Sync + 'static {
     const NONE: Self;
     fn variants() -> &'static [Self]; // all real variants, not including NONE
+
+    /// Position of this variant in the GameplayStats array, None for NONE.
+    /// Derived from variants() so discriminants need not be contiguous.
+    fn slot(self) -> Option<usize> {
+        Self::variants().iter().position(|&v| v == self)
+    }
 }
 
 #[derive(Component, Clone)]
@@ -27,31 +66,69 @@ pub struct GameplayStats<T: StatTrait>([GameplayStat; STAT_LIMIT], PhantomData<T
 impl<T: StatTrait> GameplayStats<T> {
     pub fn new(init: impl Fn(T) -> f32) -> Self {
         let variants = T::variants();
-        assert!(variants.len() <= 16, "Max number of stat variants is 16");
-
         let mut instance = Self([GameplayStat::default(); STAT_LIMIT], PhantomData);
 
-        for &variant in variants {
+        // Checks the slots actually indexed, i.e. the macro's discriminants or the positions in variants(),
+        // which also catches an enum with too many variants
+        for (position, &variant) in variants.iter().enumerate() {
             let initial: f32 = init(variant);
-            let index = variant.into() as usize;
+            let index = variant.slot().expect("Stat variant missing from StatTrait::variants");
+            assert!(
+                index < STAT_LIMIT,
+                "Stat variant {} of {} in variants() has slot {}, but GameplayStats holds at most {} stats",
+                position, variants.len(), index, STAT_LIMIT,
+            );
             instance.0[index] = GameplayStat::new(initial, initial);
         }
 
         instance
     }
 
+    /// Initialize from data loaded at runtime, stats missing from the map start at 0
+    pub fn from_map(map: &HashMap<T, f32>) -> Self where T: Hash {
+        Self::new(|stat| map.get(&stat).copied().unwrap_or(0.))
+    }
+
+    /// Like from_map for a list of (stat, value) pairs, the last pair for a stat wins
+    pub fn from_pairs(pairs: &[(T, f32)]) -> Self {
+        Self::new(|stat| pairs.iter().rev().find(|(s, _)| *s == stat).map_or(0., |&(_, value)| value))
+    }
 
     pub fn get(&self, stat_variant: T) -> &GameplayStat {
-        &self.0[stat_variant.into() as usize]
+        &self.0[stat_variant.slot().expect("No stat slot for this variant")]
     }
 
     pub fn get_mut(&mut self, stat_variant: T) -> &mut GameplayStat {
-        &mut self.0[stat_variant.into() as usize]
+        &mut self.0[stat_variant.slot().expect("No stat slot for this variant")]
+    }
+
+    /// Like get, but returns None for the None sentinel instead of panicking
+    pub fn try_get(&self, stat_variant: T) -> Option<&GameplayStat> {
+        self.0.get(stat_variant.slot()?)
+    }
+
+    /// Whether the stat currently sits at an UpperBound or LowerBound, e.g. to grey out healing at full health
+    pub fn is_at_bound(&self, stat_variant: T) -> Option<EffectCalculation> {
+        self.try_get(stat_variant)?.at_bound()
+    }
+
+    pub fn try_get_mut(&mut self, stat_variant: T) -> Option<&mut GameplayStat> {
+        self.0.get_mut(stat_variant.slot()?)
     }
 
     // TODO need to trigger recalculate effects after setting...
     pub fn set(&mut self, stat_variant: T, stat: GameplayStat) {
-        self.0[stat_variant.into() as usize] = stat;
+        self.0[stat_variant.slot().expect("No stat slot for this variant")] = stat;
     }
 }
 
+/// Records each stat's change since the previous update.  Bypasses change detection so
+/// the bookkeeping alone does not mark every entity's stats as changed.
+pub(crate) fn track_stat_deltas<T: StatTrait>(mut stats_query: Query<&mut GameplayStats<T>>) {
+    for mut stats in stats_query.iter_mut() {
+        for stat in stats.bypass_change_detection().0.iter_mut() {
+            stat.last_delta = stat.current_value - stat.previous_value;
+            stat.previous_value = stat.current_value;
+        }
+    }
+}
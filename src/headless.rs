@@ -0,0 +1,493 @@
+use std::sync::Arc;
+use bevy::prelude::*;
+use bevy_hierarchical_tags::TagId;
+use smallvec::SmallVec;
+use crate::{prelude::*, calculation::{sample_weighted, clamp_current}, effects::refresh_stack_counts};
+
+/// Magnitude before duration scaling, resistances and multipliers.  Source holds the stats LocalStat,
+/// Piecewise and Blend magnitudes read, i.e. the target's, or for NonlocalStat the other entity's.
+/// Without a source, magnitudes read from stats count as zero and Piecewise takes its above value.
+pub fn base_magnitude<T: StatTrait>(effect: &GameplayEffect<T>, source: Option<&GameplayStats<T>>, rng: &mut EffectRng) -> f32 {
+    let read = |stat: T| source.and_then(|stats| stats.try_get(stat)).map(|s| s.current_value);
+    match &effect.magnitude {
+        EffectMagnitude::None => 0.,
+        EffectMagnitude::Fixed(x) => *x,
+        EffectMagnitude::LocalStat(stat, f) | EffectMagnitude::NonlocalStat(stat, f, _) => read(*stat).map_or(0., |value| f.apply(value)),
+        EffectMagnitude::WeightedTable(table) => sample_weighted(table, rng),
+        EffectMagnitude::Piecewise { stat, reference, threshold, below, above } => {
+            match (read(*stat), read(*reference)) {
+                (Some(s), Some(r)) if s < r * threshold => *below,
+                _ => *above,
+            }
+        },
+        // Resolved to Fixed when the effect is added, where the candidates' tags are known
+        EffectMagnitude::TagCount { base, .. } => *base,
+        EffectMagnitude::StackTable(table) => stack_value(table, effect.stack_count),
+        EffectMagnitude::Blend { a, b, scaling, .. } => {
+            let weighted = |(stat, weight): (T, f32)| read(stat).map_or(0., |value| value * weight);
+            scaling.apply(weighted(*a) + weighted(*b))
+        },
+        EffectMagnitude::Falloff { base, distance, curve } => base * curve.factor(*distance),
+    }
+}
+
+/// Entry of a StackTable for the given stack count, zero for an empty table
+pub(crate) fn stack_value(table: &[f32], stack_count: u32) -> f32 {
+    let index = (stack_count.max(1) as usize - 1).min(table.len().saturating_sub(1));
+    table.get(index).copied().unwrap_or(0.)
+}
+
+/// Magnitude after duration scaling and resistances.  Outgoing multipliers
+/// read the source entity's stats, so the systems apply them on top.
+pub fn scaled_amount<T: StatTrait>(effect: &GameplayEffect<T>, magnitude: f32) -> f32 {
+    let mut amount = magnitude;
+    if let Some(scaling) = &effect.duration_scaling {
+        if matches!(effect.duration, EffectDuration::Continuous(_) | EffectDuration::Repeating(..)) {
+            if let Some(timer) = effect.get_duration_timer() {
+                amount *= scaling.apply(timer.fraction_remaining());
+            }
+        }
+    }
+    effect.resisted(amount)
+}
+
+/// Amount an effect applies with: scaled_amount, then for Additive effects the SchoolModifiers
+/// multiplier of its school and outgoing, the current value of the source's OutgoingMultiplier stat
+pub fn final_amount<T: StatTrait>(effect: &GameplayEffect<T>, magnitude: f32, schools: &SchoolModifiers, outgoing: Option<f32>) -> f32 {
+    let amount = scaled_amount(effect, magnitude);
+    if effect.calculation != EffectCalculation::Additive {
+        return amount;
+    }
+    let amount = amount * effect.school.map_or(1., |school| schools.get(school));
+    match outgoing {
+        Some(multiplier) => amount * multiplier,
+        None => amount,
+    }
+}
+
+/// Change the current value of the effect's stat by an amount, scaling damage, i.e. negative Additive
+/// amounts, by the target's IncomingMultipliers stat first.  Unbounded, returns the previous value.
+pub fn apply_amount<T: StatTrait>(
+    stats: &mut GameplayStats<T>,
+    effect: &GameplayEffect<T>,
+    amount: f32,
+    incoming: &IncomingMultipliers<T>,
+) -> Option<f32> {
+    let amount = amount * incoming.multiplier(stats, effect, amount);
+    let stat = stats.try_get_mut(effect.stat_target)?;
+    let previous = stat.current_value;
+    match &effect.calculation {
+        EffectCalculation::Additive => { stat.current_value += amount },
+        EffectCalculation::Multiplicative => { stat.current_value *= amount },
+        EffectCalculation::SetValue => { stat.current_value = amount },
+        EffectCalculation::Custom(calculation) => { stat.current_value = calculation.apply(stat.current_value, amount) },
+        _ => { }
+    }
+    Some(previous)
+}
+
+/// Combined (upper, lower) bounds from a StatCaps cap and the amounts of bound effects.  The tightest
+/// bound of each kind wins, and if they overlap the upper bound wins.
+pub fn combine_bounds(cap: Option<f32>, bounds: impl IntoIterator<Item = (EffectCalculation, f32)>) -> (f32, f32) {
+    let mut ub = cap.unwrap_or(f32::MAX);
+    let mut lb = f32::MIN;
+    for (calculation, amount) in bounds {
+        match calculation {
+            EffectCalculation::LowerBound => lb = f32::max(lb, amount),
+            EffectCalculation::UpperBound => ub = f32::min(ub, amount),
+            _ => { }
+        }
+    }
+    (ub, f32::min(lb, ub))
+}
+
+/// Modified base of a stat from its base value and the amounts of its persistent effects: additive,
+/// then multiplicative, then custom effects in order, with a SetValue overriding them all.  Unbounded.
+pub fn modified_base(base_value: f32, amounts: impl IntoIterator<Item = (EffectCalculation, f32)>) -> f32 {
+    let mut additive: f32 = 0.;
+    let mut multiplicative: f32 = 1.;
+    let mut set_value: Option<f32> = None;
+    let mut custom = SmallVec::<[(Arc<dyn CalculationFn>, f32); 2]>::new();
+    for (calculation, amount) in amounts {
+        match calculation {
+            EffectCalculation::Additive => { additive += amount },
+            EffectCalculation::Multiplicative => { multiplicative *= amount },
+            EffectCalculation::SetValue => { set_value = Some(amount) },
+            EffectCalculation::Custom(calculation) if calculation.affects_base() => { custom.push((calculation, amount)) },
+            _ => { }
+        }
+    }
+    set_value.unwrap_or_else(|| custom.iter()
+        .fold((base_value + additive) * multiplicative, |base, (calculation, amount)| calculation.apply(base, *amount)))
+}
+
+/// Set a stat's modified base from its persistent effect amounts within the bounds, keeping the current
+/// value the same fraction of the modified base it was before
+pub fn rebase(stat: &mut GameplayStat, amounts: impl IntoIterator<Item = (EffectCalculation, f32)>, upper_bound: f32, lower_bound: f32) {
+    let fraction = if stat.modified_base != 0. { stat.current_value / stat.modified_base } else { 1. };
+    stat.modified_base = f32::max(lower_bound, f32::min(upper_bound, modified_base(stat.base_value, amounts)));
+    stat.current_value = stat.modified_base * fraction;
+}
+
+/// Whether a stored effect stacks with a new one: the same tag, or for an untagged effect
+/// an untagged effect on the same stat with the same calculation
+pub fn in_stack<T: StatTrait>(stored: &GameplayEffect<T>, effect: &GameplayEffect<T>) -> bool {
+    match effect.tag {
+        Some(_) => stored.tag == effect.tag,
+        None => stored.tag.is_none() && stored.stat_target == effect.stat_target && stored.calculation == effect.calculation,
+    }
+}
+
+/// Resolve a new effect against the stored ones under a stacking policy, refreshing or merging into them
+/// as the policy says.  Ok(true) when the effect should be stored, Ok(false) when it was merged.
+pub fn stack_effect<T: StatTrait>(
+    stored: &mut [GameplayEffect<T>],
+    effect: &GameplayEffect<T>,
+    policy: Option<StackingPolicy>,
+) -> Result<bool, EffectBlockedReason> {
+    let Some(policy) = policy else { return Ok(true) };
+    let count = stored.iter().filter(|other| in_stack(other, effect)).count();
+    let refresh = |stored: &mut [GameplayEffect<T>]| {
+        if let Some(timer) = effect.get_duration_timer() {
+            for other in stored.iter_mut().filter(|other| in_stack(other, effect)) {
+                other.set_duration(timer.clone()).ok();
+            }
+        }
+    };
+    match policy {
+        StackingPolicy::NoStacking if count == 0 => Ok(true),
+        StackingPolicy::NoStacking => Err(EffectBlockedReason::StackLimit),
+        StackingPolicy::NoStackingResetDuration if count == 0 => Ok(true),
+        StackingPolicy::NoStackingResetDuration => {
+            refresh(stored);
+            Err(EffectBlockedReason::Refreshed)
+        },
+        StackingPolicy::MultipleEffects(max) if count < max as usize => Ok(true),
+        StackingPolicy::MultipleEffects(_) => Err(EffectBlockedReason::StackLimit),
+        StackingPolicy::Merge => Ok(!stored.iter_mut().filter(|other| in_stack(other, effect)).any(|other| other.merge(effect))),
+        // The caller compares amounts and removes the weaker effects first
+        StackingPolicy::KeepStrongest => Ok(true),
+        StackingPolicy::CappedDurationStacks { max_stacks, max_total_secs } => {
+            // Untimed effects take up no time, so only the count cap applies to them
+            let Some(timer) = effect.get_duration_timer() else {
+                return if count < max_stacks as usize { Ok(true) } else { Err(EffectBlockedReason::StackLimit) };
+            };
+            let remaining = |e: &GameplayEffect<T>| e.get_duration_timer().map_or(0., |timer| timer.remaining);
+            let mut headroom = max_total_secs - stored.iter().filter(|other| in_stack(other, effect)).map(remaining).sum::<f32>();
+            if count < max_stacks as usize && timer.remaining <= headroom {
+                return Ok(true);
+            }
+            let mut refreshed = false;
+            for other in stored.iter_mut().filter(|other| in_stack(other, effect)) {
+                let Some(current) = other.get_duration_timer().map(|timer| timer.remaining) else { continue };
+                let topped = f32::min(timer.duration, current + headroom.max(0.));
+                if topped <= current { continue }
+                headroom -= topped - current;
+                other.set_duration(timer.clone().with_remaining(topped)).ok();
+                refreshed = true;
+            }
+            Err(if refreshed { EffectBlockedReason::Refreshed } else { EffectBlockedReason::StackLimit })
+        },
+        StackingPolicy::MultipleEffectsResetDurations(max) => {
+            refresh(stored);
+            if count < max as usize { Ok(true) } else { Err(EffectBlockedReason::Refreshed) }
+        },
+    }
+}
+
+/// One entity's stats and effects stepped by hand, e.g. in a headless server tick loop without an App.
+/// Covers durations, warmups, stacking, bounds and the stat math through the same functions as the systems,
+/// with the plugin's config resources as fields, always recalculating in full.  The entity has no source
+/// entities to read, so NonlocalStat magnitudes count as zero and outgoing multipliers are not applied,
+/// and there is no ledger.  Audit records name Entity::PLACEHOLDER.
+pub struct HeadlessEntity<T: StatTrait> {
+    pub stats: GameplayStats<T>,
+    pub effects: Vec<GameplayEffect<T>>,
+    pub stacking: StackingBehaviors,
+    pub rng: EffectRng,
+    pub bound_epsilon: f32,
+    pub incoming: IncomingMultipliers<T>,
+    pub caps: StatCaps<T>,
+    pub immutable: ImmutableStats<T>,
+    pub schools: SchoolModifiers,
+    pub audit: Option<AuditSink<T>>,
+    pending: Vec<(f32, GameplayEffect<T>)>,
+    elapsed: f32,
+}
+
+impl<T: StatTrait> HeadlessEntity<T> {
+    pub fn new(stats: GameplayStats<T>) -> Self {
+        Self {
+            stats,
+            effects: Vec::new(),
+            stacking: StackingBehaviors::new(),
+            rng: EffectRng::default(),
+            bound_epsilon: 0.,
+            incoming: IncomingMultipliers::new(),
+            caps: StatCaps::new(),
+            immutable: ImmutableStats::new(),
+            schools: SchoolModifiers::new(),
+            audit: None,
+            pending: Vec::new(),
+            elapsed: 0.,
+        }
+    }
+
+    pub fn with_stacking(mut self, stacking: StackingBehaviors) -> Self {
+        self.stacking = stacking;
+        self
+    }
+
+    pub fn with_incoming(mut self, incoming: IncomingMultipliers<T>) -> Self {
+        self.incoming = incoming;
+        self
+    }
+
+    pub fn with_caps(mut self, caps: StatCaps<T>) -> Self {
+        self.caps = caps;
+        self
+    }
+
+    pub fn with_immutable(mut self, immutable: ImmutableStats<T>) -> Self {
+        self.immutable = immutable;
+        self
+    }
+
+    pub fn with_schools(mut self, schools: SchoolModifiers) -> Self {
+        self.schools = schools;
+        self
+    }
+
+    pub fn with_audit(mut self, audit: AuditSink<T>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Add an effect as AddEffect would, returning the amount it was applied with.  An effect with
+    /// a warmup is queued and added by the tick completing it, returning 0 for now.
+    pub fn add(&mut self, mut effect: GameplayEffect<T>) -> Result<f32, EffectBlockedReason> {
+        if let Some(warmup) = effect.warmup.filter(|&w| w > 0.) {
+            effect.warmup = None;
+            self.pending.push((warmup, effect));
+            return Ok(0.);
+        }
+        if self.immutable.contains(effect.stat_target) {
+            return Err(EffectBlockedReason::ImmutableStat);
+        }
+        if let EffectMagnitude::WeightedTable(table) = &effect.magnitude {
+            effect.magnitude = EffectMagnitude::Fixed(sample_weighted(table, &mut self.rng));
+        }
+        let amount = effect_amount(&effect, &self.stats, &self.schools, &mut self.rng);
+        let expired = effect.get_duration_timer().is_some_and(|timer| timer.finished());
+        if expired && matches!(effect.duration, EffectDuration::Persistent(_) | EffectDuration::Tracked(_)) {
+            return Err(EffectBlockedReason::Expired);
+        }
+        if !matches!(effect.duration, EffectDuration::Immediate) && !expired {
+            let policy = match effect.tag {
+                Some(tag) => Some(self.stacking.get(tag, None)),
+                None => self.stacking.untagged_policy(),
+            };
+            if matches!(policy, Some(StackingPolicy::KeepStrongest)) {
+                let (stats, schools, rng) = (&self.stats, &self.schools, &mut self.rng);
+                if self.effects.iter().filter(|other| in_stack(other, &effect)).any(|other| other.calculation.strength(effect_amount(other, stats, schools, rng)) >= effect.calculation.strength(amount)) {
+                    return Err(EffectBlockedReason::Outclassed);
+                }
+                let (weaker, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.effects).into_iter().partition(|other| in_stack(other, &effect));
+                self.effects = kept;
+                self.recalculate_removed(&weaker);
+            }
+            if stack_effect(&mut self.effects, &effect, policy)? {
+                self.effects.push(effect.clone());
+            }
+            for stat in refresh_stack_counts(&mut self.effects, effect.tag) {
+                self.recalculate(stat, std::slice::from_ref(&effect));
+            }
+        }
+        match effect.duration {
+            _ if expired => self.apply(&effect, amount),
+            EffectDuration::Immediate => self.apply(&effect, amount),
+            EffectDuration::Persistent(_) => self.recalculate(effect.stat_target, std::slice::from_ref(&effect)),
+            EffectDuration::Tracked(_) => {
+                let before = self.stats.try_get(effect.stat_target).map_or(0., |s| s.current_value);
+                self.apply(&effect, amount);
+                let applied = self.stats.try_get(effect.stat_target).map_or(0., |s| s.current_value) - before;
+                if let Some(stored) = self.effects.iter_mut().rev().find(|other| in_stack(other, &effect)) {
+                    stored.applied += applied;
+                }
+            },
+            _ => { }
+        }
+        Ok(amount)
+    }
+
+    /// Remove every effect with the tag, returning how many were removed
+    pub fn remove(&mut self, tag: TagId) -> usize {
+        let (removed, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.effects).into_iter().partition(|e| e.tag == Some(tag));
+        self.effects = kept;
+        self.recalculate_removed(&removed);
+        removed.len()
+    }
+
+    /// Advance the effects by secs as process_active_effects does each frame
+    pub fn tick(&mut self, secs: f32) {
+        self.elapsed += secs;
+        let mut scheduled_fires = SmallVec::<[(usize, usize); 4]>::new();
+        for (idx, effect) in self.effects.iter_mut().enumerate() {
+            match &mut effect.duration {
+                EffectDuration::Continuous(Some(timer)) | EffectDuration::Persistent(Some(timer)) | EffectDuration::Tracked(Some(timer)) => timer.tick(secs),
+                EffectDuration::Repeating(period, timer) => {
+                    period.tick(secs);
+                    if let Some(timer) = timer {
+                        timer.tick(secs);
+                    }
+                },
+                EffectDuration::Scheduled(times) => {
+                    let pending = times.len();
+                    times.retain(|&t| t > self.elapsed);
+                    if times.len() < pending {
+                        scheduled_fires.push((idx, pending - times.len()));
+                    }
+                },
+                _ => { }
+            }
+        }
+
+        let mut expired = SmallVec::<[usize; 8]>::new();
+        let mut order: SmallVec<[usize; 8]> = (0..self.effects.len()).collect();
+        order.sort_by_key(|&i| self.effects[i].calculation.phase());
+        for idx in order {
+            let effect = self.effects[idx].clone();
+            let mut amount = effect_amount(&effect, &self.stats, &self.schools, &mut self.rng);
+            let applications = match &effect.duration {
+                _ if !effect.condition_met => 0,
+                EffectDuration::Continuous(_) => {
+                    amount *= secs;
+                    1
+                },
+                EffectDuration::Repeating(period, _) => period.just_triggered() as usize,
+                EffectDuration::Scheduled(_) => scheduled_fires.iter().find(|(i, _)| *i == idx).map_or(0, |(_, n)| *n),
+                _ => 0,
+            };
+            for _ in 0..applications {
+                self.apply(&effect, amount);
+            }
+            let finished = effect.get_duration_timer().is_some_and(|timer| timer.finished())
+                || matches!(&effect.duration, EffectDuration::Scheduled(times) if times.is_empty());
+            if finished {
+                expired.push(idx);
+            }
+        }
+
+        expired.sort();
+        let removed: Vec<_> = expired.iter().rev().map(|&i| self.effects.remove(i)).collect();
+        self.recalculate_removed(&removed);
+
+        // Completed warmups are added after this tick's effects, as the systems' commands are
+        let mut ready = Vec::new();
+        self.pending.retain_mut(|(remaining, effect)| {
+            *remaining -= secs;
+            if *remaining > 0. { return true }
+            ready.push(effect.clone());
+            false
+        });
+        for effect in ready {
+            self.add(effect).ok();
+        }
+    }
+
+    fn recalculate_removed(&mut self, removed: &[GameplayEffect<T>]) {
+        for effect in removed {
+            for stat in refresh_stack_counts(&mut self.effects, effect.tag) {
+                self.recalculate(stat, std::slice::from_ref(effect));
+            }
+            if let (EffectDuration::Tracked(_), Some(stat)) = (&effect.duration, self.stats.try_get_mut(effect.stat_target)) {
+                let old = stat.current_value;
+                stat.current_value -= effect.applied;
+                if let Some(audit) = self.audit.as_ref().filter(|_| effect.applied != 0.) {
+                    audit.record(StatMutation {
+                        entity: Entity::PLACEHOLDER,
+                        stat: effect.stat_target,
+                        old,
+                        new: stat.current_value,
+                        tag: effect.tag,
+                        source: effect.source_entity,
+                        cause: MutationCause::Recalculation,
+                    });
+                }
+            }
+            if matches!(effect.duration, EffectDuration::Persistent(_) | EffectDuration::Tracked(_)) {
+                self.recalculate(effect.stat_target, std::slice::from_ref(effect));
+            }
+        }
+    }
+
+    fn apply(&mut self, effect: &GameplayEffect<T>, amount: f32) {
+        if effect.stat_target == T::NONE || self.immutable.contains(effect.stat_target) { return }
+        let (upper_bound, lower_bound) = self.bounds(effect.stat_target);
+        let Some(previous) = apply_amount(&mut self.stats, effect, amount, &self.incoming) else { return };
+        let Some(stat) = self.stats.try_get_mut(effect.stat_target) else { return };
+        clamp_current(Entity::PLACEHOLDER, effect.stat_target, stat, upper_bound, lower_bound, self.bound_epsilon);
+        if let Some(audit) = &self.audit {
+            audit.record(StatMutation {
+                entity: Entity::PLACEHOLDER,
+                stat: effect.stat_target,
+                old: previous,
+                new: stat.current_value,
+                tag: effect.tag,
+                source: effect.source_entity,
+                cause: MutationCause::Application,
+            });
+        }
+    }
+
+    /// Recalculate a stat and clamp the stats it caps, as recalculate_stats_multi does
+    fn recalculate(&mut self, stat_target: T, triggers: &[GameplayEffect<T>]) {
+        if stat_target == T::NONE { return }
+        let before = self.audit.as_ref().map(|_| self.stats.clone());
+        self.recalculate_stat(stat_target);
+        for &capped in T::variants() {
+            if self.caps.get(capped) != Some(stat_target) { continue }
+            let (upper_bound, lower_bound) = self.bounds(capped);
+            if let Some(stat) = self.stats.try_get_mut(capped) {
+                clamp_current(Entity::PLACEHOLDER, capped, stat, upper_bound, lower_bound, self.bound_epsilon);
+            }
+        }
+        if let (Some(audit), Some(before)) = (&self.audit, before) {
+            audit.record_changes(Entity::PLACEHOLDER, &before, &self.stats, triggers, MutationCause::Recalculation);
+        }
+    }
+
+    fn recalculate_stat(&mut self, stat_target: T) {
+        let (stats, schools, rng) = (&self.stats, &self.schools, &mut self.rng);
+        let amounts: SmallVec<[(EffectCalculation, f32); 4]> = self.effects.iter()
+            .filter(|e| e.stat_target == stat_target && matches!(e.duration, EffectDuration::Persistent(_)))
+            .map(|e| (e.calculation.clone(), effect_amount(e, stats, schools, rng)))
+            .collect();
+        let (upper_bound, lower_bound) = self.bounds(stat_target);
+        let Some(stat) = self.stats.try_get_mut(stat_target) else { return };
+        // Leave a stat nothing ever modified alone, as recalculate_stats does
+        if !amounts.is_empty() || stat.modified_base != stat.base_value {
+            rebase(stat, amounts, upper_bound, lower_bound);
+        }
+        clamp_current(Entity::PLACEHOLDER, stat_target, stat, upper_bound, lower_bound, self.bound_epsilon);
+    }
+
+    fn bounds(&mut self, stat_target: T) -> (f32, f32) {
+        let cap = self.caps.get(stat_target).and_then(|max_stat| self.stats.try_get(max_stat)).map(|max| max.current_value);
+        let (stats, schools, rng) = (&self.stats, &self.schools, &mut self.rng);
+        let bounds: SmallVec<[(EffectCalculation, f32); 2]> = self.effects.iter()
+            .filter(|e| e.stat_target == stat_target && matches!(e.calculation, EffectCalculation::LowerBound | EffectCalculation::UpperBound))
+            .map(|e| (e.calculation.clone(), effect_amount(e, stats, schools, rng)))
+            .collect();
+        combine_bounds(cap, bounds)
+    }
+}
+
+/// Amount of an effect on its own entity, NonlocalStat magnitudes count as zero without their source
+fn effect_amount<T: StatTrait>(effect: &GameplayEffect<T>, stats: &GameplayStats<T>, schools: &SchoolModifiers, rng: &mut EffectRng) -> f32 {
+    let source = match effect.magnitude.source_entity() {
+        Some(_) => None,
+        None => Some(stats),
+    };
+    final_amount(effect, base_magnitude(effect, source, rng), schools, None)
+}
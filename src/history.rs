@@ -0,0 +1,97 @@
+use std::collections::VecDeque;
+use bevy::prelude::*;
+use crate::prelude::*;
+
+/// Opt-in per entity record of some stats' current values over the last `capacity` frames,
+/// e.g. for graphing during balancing.  Updated after effects are processed each frame.
+#[derive(Component, Clone)]
+pub struct StatHistory<T: StatTrait> {
+    capacity: usize,
+    series: Vec<(T, VecDeque<f32>)>,
+}
+
+impl<T: StatTrait> StatHistory<T> {
+    pub fn new(capacity: usize, stats: &[T]) -> Self {
+        Self {
+            capacity,
+            series: stats.iter().map(|&stat| (stat, VecDeque::with_capacity(capacity))).collect(),
+        }
+    }
+
+    /// Recorded values of a tracked stat, oldest first
+    pub fn series(&self, stat: T) -> impl Iterator<Item = f32> + '_ {
+        self.series.iter()
+            .filter(move |(s, _)| *s == stat)
+            .flat_map(|(_, values)| values.iter().copied())
+    }
+
+    pub fn latest(&self, stat: T) -> Option<f32> {
+        self.series.iter().find(|(s, _)| *s == stat).and_then(|(_, values)| values.back().copied())
+    }
+
+    pub fn clear(&mut self) {
+        for (_, values) in self.series.iter_mut() {
+            values.clear();
+        }
+    }
+
+    fn record(&mut self, stats: &GameplayStats<T>) {
+        if self.capacity == 0 { return }
+        for (stat, values) in self.series.iter_mut() {
+            let Some(value) = stats.try_get(*stat) else { continue };
+            if values.len() == self.capacity {
+                values.pop_front();
+            }
+            values.push_back(value.current_value);
+        }
+    }
+}
+
+/// Opt-in per entity ring buffer of whole GameplayStats snapshots over the last `capacity` frames, for
+/// lag compensation, e.g. resolving a hit against the stats the shooter saw.  Recorded after effects
+/// are processed each frame, pass a snapshot to AddEffectData::with_stats_snapshot to use it.
+#[derive(Component, Clone)]
+pub struct StatSnapshots<T: StatTrait> {
+    capacity: usize,
+    snapshots: VecDeque<GameplayStats<T>>,
+}
+
+impl<T: StatTrait> StatSnapshots<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, snapshots: VecDeque::with_capacity(capacity) }
+    }
+
+    /// Stats as recorded frames_ago frames before the latest snapshot, None if not recorded that far back
+    pub fn stats_at(&self, frames_ago: usize) -> Option<&GameplayStats<T>> {
+        let index = self.snapshots.len().checked_sub(frames_ago + 1)?;
+        self.snapshots.get(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    fn record(&mut self, stats: &GameplayStats<T>) {
+        if self.capacity == 0 { return }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(stats.clone());
+    }
+}
+
+pub(crate) fn record_stat_history<T: StatTrait>(
+    mut query: Query<(&GameplayStats<T>, &mut StatHistory<T>)>,
+) {
+    for (stats, mut history) in query.iter_mut() {
+        history.record(stats);
+    }
+}
+
+pub(crate) fn record_stat_snapshots<T: StatTrait>(
+    mut query: Query<(&GameplayStats<T>, &mut StatSnapshots<T>)>,
+) {
+    for (stats, mut snapshots) in query.iter_mut() {
+        snapshots.record(stats);
+    }
+}
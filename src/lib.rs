@@ -1,7 +1,16 @@
-use std::marker::PhantomData;
+use std::{collections::HashMap, marker::PhantomData};
 use bevy::prelude::*;
-use bevy_hierarchical_tags::TagId;
-use crate::{effects::{add_effect, process_active_effects, remove_effect}, prelude::*};
+use bevy_hierarchical_tags::{TagId, TagRegistry};
+use crate::{
+    effects::{ACTIVE_EFFECTS_SIZE, ACTIVE_TAGS_SIZE, add_effect, apply_instant, evaluate_effect_conditions, has_effect_conditions, process_active_effects, remove_effect, remove_effect_by_net_id, remove_effect_group, retain_effects, cancel_pending, remove_effects_on_despawn, insert_active_tags, EffectClock},
+    prelude::*,
+    thresholds::{check_thresholds, record_initial_thresholds, ThresholdStates},
+    history::{record_stat_history, record_stat_snapshots},
+    regen::apply_stat_regen,
+    formulas::apply_stat_formulas,
+    death::despawn_on_bound,
+    gameplay_stats::track_stat_deltas,
+};
 
 mod gameplay_stats;
 mod effects;
@@ -9,6 +18,18 @@ mod timing;
 mod calculation;
 mod events;
 mod enum_macro;
+mod outcome;
+mod diagnostics;
+mod thresholds;
+mod history;
+mod regen;
+mod formulas;
+mod death;
+mod headless;
+mod audit;
+mod effect_target;
+#[cfg(feature = "debug_ui")]
+mod debug_ui;
 
 pub mod prelude {
     pub use crate::{
@@ -16,57 +37,326 @@ pub mod prelude {
         GameplayEffectsPlugin,
         GameplayEffectsSystemSet,
         StackingBehaviors,
+        MutexGroups,
+        Suppressions,
+        SuppressionMode,
+        GameplayEffectsSettings,
+        ProcessingBudget,
+        diagnostics::GameplayEffectsDiagnosticsPlugin,
         gameplay_stats::{GameplayStat, GameplayStats, StatTrait},
-        effects::{GameplayEffect, ActiveEffects, ActiveTags},
-        timing::EffectDuration,
-        calculation::{EffectCalculation, StackingPolicy, EffectMagnitude, StatScalingParams},
-        events::{AddEffectData, EffectMetadata, AddEffect, RemoveEffect, OnEffectAdded,
-            OnEffectRemoved, OnBoundsBreached, OnRepeatingEffectTriggered, BoundsBreachedMetadata},
+        effects::{GameplayEffect, ActiveEffects, ActiveTags, NetEffectId, EffectCondition, EffectPayload, EffectVetoes, RedirectTo, EffectSummary, PendingEffects, effects_from_source, with_temporary_effect},
+        timing::{EffectDuration, SmallTimer, RepeatingSmallTimer},
+        calculation::{EffectCalculation, CalculationFn, StackingPolicy, EffectMagnitude, StatScalingParams, EffectRng,
+            IncomingMultipliers, StatCaps, ImmutableStats, OutgoingMultiplier, SchoolId, SchoolModifiers, DurationScaling, FalloffCurve, DamageLedger, LedgerEntry, Resistance, Resistances, Leech, BoundMode},
+        events::{AddEffectData, EffectMetadata, RemovalReason, AddEffect, RemoveEffect, RemoveEffectByNetId, RemoveEffectGroup, RetainEffects, CancelPending, OnEffectAdded,
+            OnEffectRemoved, OnEffectInterrupted, OnEffectExpiring, OnEffectPayload, OnTagAdded, OnTagRemoved, OnBoundsBreached, OnRepeatingEffectTriggered, BoundsBreachedMetadata, OnStatChangedBatched,
+            ApplyInstant, InstantEffectCommandsExt, OnStatChanged, OnThresholdCrossed, CrossingDirection, OnDeath, EffectEvents, EffectWorldExt},
+        thresholds::StatThresholds,
+        history::{StatHistory, StatSnapshots},
+        regen::StatRegen,
+        formulas::StatFormulas,
+        death::DespawnOnBound,
+        audit::{AuditSink, StatMutation, MutationCause},
+        headless::{HeadlessEntity, base_magnitude, scaled_amount, final_amount, apply_amount, combine_bounds, modified_base, rebase, in_stack, stack_effect},
+        effect_target::{EffectTarget, EffectTargetPlugin},
+        outcome::{EffectOutcome, EffectBlockedReason, EffectOutcomeReceiver, apply_effect_and_wait},
     };
+    #[cfg(feature = "debug_ui")]
+    pub use crate::debug_ui::{EffectsDebugPlugin, EffectsDebugSelection};
 }
 
-pub struct GameplayEffectsPlugin<T: StatTrait>(StackingBehaviors, PhantomData<T>);
+/// N and M are the inline capacities of the ActiveEffects and ActiveTags components it processes
+pub struct GameplayEffectsPlugin<T: StatTrait, const N: usize = ACTIVE_EFFECTS_SIZE, const M: usize = ACTIVE_TAGS_SIZE>(
+    StackingBehaviors, StatRegen<T>
+);
 
-impl<T: StatTrait> Default for GameplayEffectsPlugin<T> {
+impl<T: StatTrait, const N: usize, const M: usize> Default for GameplayEffectsPlugin<T, N, M> {
     fn default() -> Self {
         Self::new(StackingBehaviors::new())
     }
 }
 
-impl<T: StatTrait> GameplayEffectsPlugin<T> {
+impl<T: StatTrait, const N: usize, const M: usize> GameplayEffectsPlugin<T, N, M> {
     pub fn new(stacking: StackingBehaviors) -> Self {
-        Self(stacking, PhantomData)
+        Self(stacking, StatRegen::new())
+    }
+
+    /// Regenerate stat by the current value of regen_stat each second, up to max_stat
+    pub fn with_regen(mut self, stat: T, regen_stat: T, max_stat: T) -> Self {
+        self.1 = self.1.regen(stat, regen_stat, max_stat);
+        self
     }
 }
 
 #[derive(Resource, Clone)]
-pub struct StackingBehaviors([Option<StackingPolicy>; 1024]);
+pub struct StackingBehaviors([Option<StackingPolicy>; 1024], StackingPolicy, Option<StackingPolicy>);
 
 impl StackingBehaviors {
     pub fn new() -> Self {
-        Self([None; 1024])
+        Self([None; 1024], StackingPolicy::default(), None)
+    }
+
+    /// Policy for effects without a tag, which otherwise always add a new effect.  Untagged effects
+    /// stack with the untagged effects on the same stat with the same calculation.
+    pub fn untagged(mut self, policy: StackingPolicy) -> Self {
+        self.2 = Some(policy);
+        self
+    }
+
+    /// Policy for tags with no policy of their own or on an ancestor, NoStacking unless set
+    pub fn with_default(mut self, policy: StackingPolicy) -> Self {
+        self.1 = policy;
+        self
     }
 
     pub fn stack(mut self, tag: TagId, policy: StackingPolicy) -> Self {
         self.0[*tag as usize] = Some(policy);
         self
     }
+
+    /// Change a tag's policy at runtime through ResMut<StackingBehaviors>, e.g. a talent raising a debuff's stack limit.
+    /// Effects already active keep their stacks and later adds follow the new policy.
+    pub fn set_policy(&mut self, tag: TagId, policy: StackingPolicy) {
+        self.0[*tag as usize] = Some(policy);
+    }
+
+    /// The tag's own policy, without falling back to ancestors or the default
+    pub fn get_policy(&self, tag: TagId) -> Option<StackingPolicy> {
+        self.0[*tag as usize]
+    }
+
+    /// Clear the tag's own policy so it falls back to its ancestors or the default again
+    pub fn remove_policy(&mut self, tag: TagId) -> Option<StackingPolicy> {
+        self.0[*tag as usize].take()
+    }
+
+    /// Policy for untagged effects, None when they always stack
+    pub(crate) fn untagged_policy(&self) -> Option<StackingPolicy> {
+        self.2
+    }
+
+    /// Policy for a tag, falling back to the nearest ancestor tag with a policy,
+    /// e.g. Effect.Status.Burning inherits from Effect.Status
+    pub fn get(&self, tag: TagId, registry: Option<&TagRegistry>) -> StackingPolicy {
+        let mut current = Some(tag);
+        while let Some(tag) = current {
+            if let Some(policy) = self.0[*tag as usize] {
+                return policy;
+            }
+            current = registry.and_then(|r| r.parent(tag));
+        }
+        self.1
+    }
+}
+
+
+/// Groups of tags where only one tag per group may be active at a time.
+/// Adding an effect removes any active effect tagged with another member of its group.
+#[derive(Resource, Clone)]
+pub struct MutexGroups([Option<u16>; 1024]);
+
+impl Default for MutexGroups {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MutexGroups {
+    pub fn new() -> Self {
+        Self([None; 1024])
+    }
+
+    pub fn group(mut self, group: u16, tags: &[TagId]) -> Self {
+        for &tag in tags {
+            self.0[*tag as usize] = Some(group);
+        }
+        self
+    }
+
+    pub(crate) fn get(&self, tag: TagId) -> Option<u16> {
+        self.0[*tag as usize]
+    }
+}
+
+/// Whether suppressed effects keep running down their timers or freeze until the suppression ends
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SuppressionMode {
+    #[default]
+    Tick,
+    Freeze,
+}
+
+/// While a suppressor tag is active on an entity, its continuous, repeating and scheduled effects tagged
+/// with the suppressed tag are not applied, e.g. a stun pausing channeled abilities.  Descendants of the
+/// suppressed tag are covered too when a TagRegistry resource exists.
+#[derive(Resource, Clone, Default)]
+pub struct Suppressions(Vec<(TagId, TagId, SuppressionMode)>);
+
+impl Suppressions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn suppress(mut self, suppressor: TagId, suppressed: TagId, mode: SuppressionMode) -> Self {
+        self.0.push((suppressor, suppressed, mode));
+        self
+    }
+
+    /// How an effect with this tag is suppressed given the entity's active tags, if at all
+    pub(crate) fn get<const M: usize>(&self, tag: TagId, active: &ActiveTags<M>, registry: Option<&TagRegistry>) -> Option<SuppressionMode> {
+        self.0.iter()
+            .filter(|(suppressor, _, _)| active.iter().any(|t| t == suppressor))
+            .find(|(_, suppressed, _)| {
+                let mut current = Some(tag);
+                while let Some(tag) = current {
+                    if tag == *suppressed { return true }
+                    current = registry.and_then(|r| r.parent(tag));
+                }
+                false
+            })
+            .map(|&(_, _, mode)| mode)
+    }
+}
+
+/// Opt-in behaviors of the effect systems
+#[derive(Resource, Clone, Default)]
+pub struct GameplayEffectsSettings {
+    /// Emit one OnStatChangedBatched per entity and stat each frame with the summed change
+    pub batch_stat_changes: bool,
+    /// Rebuild persistent stat values from base_value and the active persistent effects on every
+    /// recalculation instead of rescaling incrementally.  Also honours persistent SetValue effects.
+    pub full_recalculation: bool,
+    /// Values within this distance of a bound count as reaching it and snap exactly onto it
+    pub bound_epsilon: f32,
+    /// Process effects in whole steps of this many seconds, accumulating frame time in between, so
+    /// timers and continuous effects give identical floats regardless of frame pacing, e.g. for lockstep
+    pub fixed_step: Option<f32>,
+    /// NonlocalStat effects read their source's stats as they were at the start of the frame, so
+    /// entities draining each other get the same result whichever is processed first
+    pub snapshot_sources: bool,
+}
+
+/// Spreads effect processing over several frames for large entity counts.  Each frame only the next
+/// entities_per_frame entities, round-robin in Entity order, have their effects ticked and applied, by
+/// the time since they were last processed.  Continuous effects are exempt and still change their stat
+/// every frame.  Totals stay the same but expiry and repeating or scheduled ticks land up to a cycle late,
+/// so only enable it where exact per-frame timing does not matter.
+#[derive(Resource)]
+pub struct ProcessingBudget<T: StatTrait> {
+    /// None processes every entity each frame
+    pub entities_per_frame: Option<usize>,
+    /// Last entity processed, so spawning or despawning others does not shift the round-robin
+    cursor: Option<Entity>,
+    pub(crate) last_processed: HashMap<Entity, f32>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: StatTrait> Default for ProcessingBudget<T> {
+    fn default() -> Self {
+        Self { entities_per_frame: None, cursor: None, last_processed: HashMap::new(), _marker: PhantomData }
+    }
 }
 
+impl<T: StatTrait> ProcessingBudget<T> {
+    pub fn new(entities_per_frame: usize) -> Self {
+        Self { entities_per_frame: Some(entities_per_frame), ..default() }
+    }
+
+    /// Sorted entities to process this frame, None for all of them, advancing the cursor to the last one
+    pub(crate) fn next_window(&mut self, mut entities: Vec<Entity>) -> Option<Vec<Entity>> {
+        let Some(per_frame) = self.entities_per_frame.filter(|&n| n < entities.len()) else {
+            self.last_processed.clear();
+            return None;
+        };
+        entities.sort();
+        let start = self.cursor.map_or(0, |cursor| entities.partition_point(|&entity| entity <= cursor));
+        let mut window: Vec<Entity> = entities.iter().cycle().skip(start).take(per_frame).copied().collect();
+        self.cursor = window.last().copied();
+        window.sort();
+        Some(window)
+    }
+
+    /// Seconds to advance an entity's effects by, the frame time unless the budget is skipping entities
+    pub(crate) fn elapsed_since_processed(&mut self, entity: Entity, now: f32, delta: f32) -> f32 {
+        if self.entities_per_frame.is_none() {
+            return delta;
+        }
+        self.last_processed.insert(entity, now).map_or(delta, |last| now - last)
+    }
+}
 
 #[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct GameplayEffectsSystemSet;
 
-impl<T: StatTrait> Plugin for GameplayEffectsPlugin<T> {
+/// Marks that a GameplayEffectsPlugin for T has already been built into the app
+#[derive(Resource)]
+struct GameplayEffectsRegistered<T: StatTrait>(PhantomData<T>);
+
+impl<T: StatTrait, const N: usize, const M: usize> Plugin for GameplayEffectsPlugin<T, N, M> {
     fn build(&self, app: &mut App) {
+        // Several library plugins may each add the plugin for the same stat enum, only the
+        // first registers the observers and systems so effects are not applied twice
+        if app.world().contains_resource::<GameplayEffectsRegistered<T>>() {
+            warn!("GameplayEffectsPlugin for {} was added more than once, ignoring the duplicate", std::any::type_name::<T>());
+            return;
+        }
+        app.insert_resource(GameplayEffectsRegistered::<T>(PhantomData));
         app.add_message::<OnEffectAdded>();
         app.add_message::<OnEffectRemoved>();
+        app.add_message::<OnEffectInterrupted>();
+        app.add_message::<OnEffectExpiring>();
+        app.add_message::<OnEffectPayload>();
+        app.add_message::<OnTagAdded>();
+        app.add_message::<OnTagRemoved>();
         app.add_message::<OnRepeatingEffectTriggered>();
         app.add_message::<OnBoundsBreached<T>>();
-        app.add_observer(add_effect::<T>);
-        app.add_observer(remove_effect::<T>);
-        app.add_systems(Update, process_active_effects::<T>.in_set(GameplayEffectsSystemSet));
+        app.add_message::<OnStatChangedBatched<T>>();
+        app.add_message::<OnStatChanged<T>>();
+        app.add_message::<OnThresholdCrossed<T>>();
+        app.add_message::<OnDeath<T>>();
+        app.add_observer(add_effect::<T, N, M>);
+        app.add_observer(remove_effect::<T, N, M>);
+        app.add_observer(remove_effect_by_net_id::<T, N, M>);
+        app.add_observer(remove_effect_group::<T, N, M>);
+        app.add_observer(retain_effects::<T, N, M>);
+        app.add_observer(apply_instant::<T, N>);
+        app.add_observer(cancel_pending::<T>);
+        app.add_observer(remove_effects_on_despawn::<T, N, M>);
+        app.add_observer(insert_active_tags::<T, N, M>);
+        app.add_observer(record_initial_thresholds::<T>);
+        app.add_systems(Update, (
+            evaluate_effect_conditions::<T, N>.run_if(has_effect_conditions::<T, N>),
+            process_active_effects::<T, N, M>,
+            apply_stat_regen::<T>,
+            apply_stat_formulas::<T, N>,
+            check_thresholds::<T>,
+            record_stat_history::<T>,
+            record_stat_snapshots::<T>,
+            track_stat_deltas::<T>,
+            despawn_on_bound::<T>,
+        ).chain().in_set(GameplayEffectsSystemSet));
         app.insert_resource(self.0.clone());
+        app.insert_resource(self.1.clone());
+        app.init_resource::<MutexGroups>();
+        app.init_resource::<Suppressions>();
+        app.init_resource::<EffectRng>();
+        app.init_resource::<GameplayEffectsSettings>();
+        app.init_resource::<IncomingMultipliers<T>>();
+        app.init_resource::<OutgoingMultiplier<T>>();
+        app.init_resource::<SchoolModifiers>();
+        app.init_resource::<StatCaps<T>>();
+        app.init_resource::<ImmutableStats<T>>();
+        app.init_resource::<DamageLedger<T>>();
+        app.init_resource::<StatThresholds<T>>();
+        app.init_resource::<ThresholdStates<T>>();
+        app.init_resource::<StatFormulas<T>>();
+        app.init_resource::<ProcessingBudget<T>>();
+        app.init_resource::<PendingEffects<T>>();
+        app.init_resource::<EffectClock<T>>();
+    }
+
+    fn is_unique(&self) -> bool {
+        false
     }
 }
 
@@ -84,6 +374,10 @@ mod tests {
             HealthRegen,
             HealthMax,
             Strength,
+            Vulnerability,
+            SpellPower,
+            Level,
+            AttackPower,
         }
     );
 
@@ -103,12 +397,16 @@ mod tests {
                     MyStats::HealthRegen => { 5.0 },
                     MyStats::HealthMax => { 100.0 },
                     MyStats::Strength => { 10.0 },
+                    MyStats::Vulnerability => { 1.0 },
+                    MyStats::SpellPower => { 1.0 },
+                    MyStats::Level => { 5.0 },
+                    MyStats::AttackPower => { 0. },
                     MyStats::None => { 0. }
                 }
             },
         );
         let active_effects = ActiveEffects::<MyStats>::new(std::iter::empty());
-        let active_tags = ActiveTags::default();
+        let active_tags: ActiveTags = ActiveTags::default();
         let entity = app.world_mut().spawn((
             stats_component,
             active_effects,
@@ -204,6 +502,20 @@ mod tests {
         assert_eq!(event.stat, MyStats::Health);
     }
 
+    #[test]
+    fn test_bounds_breached_overshoot() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        let effect = |amount, calculation, duration| GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(amount), calculation, duration);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect(0., EffectCalculation::LowerBound, EffectDuration::persistent()), None)));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect(-200., EffectCalculation::Additive, EffectDuration::immediate()), None)));
+
+        // 100 health takes 200 damage, so 100 of it went past the floor
+        let events = app.world().resource::<Events<OnBoundsBreached<MyStats>>>();
+        let breaches: Vec<_> = events.get_cursor().read(events).map(|e| (e.bound.clone(), e.overshoot)).collect();
+        assert_eq!(breaches, vec![(EffectCalculation::LowerBound, 100.)]);
+    }
+
     #[test] 
     fn test_set_value() {
         let mut app = setup_app();
@@ -348,7 +660,7 @@ mod tests {
         let mut events = cursor.read(&events);
         assert_eq!(events.len(), 1);
         let event = events.next().unwrap();
-        let EffectMetadata { target_entity, tag, source_entity } = event.0;
+        let EffectMetadata { target_entity, tag, source_entity, .. } = event.0;
         assert_eq!(source_entity, Some(entity2));
         assert_eq!(target_entity, entity1);
         assert_eq!(tag, None);
@@ -636,4 +948,3612 @@ mod tests {
         assert!(!tags.contains(&tag));
         assert_eq!(effects.iter().len(), 0);
     }
+
+    #[test] 
+    fn test_mutex_groups() {
+        let mut app = setup_app();
+        let calm = TagId::from(1);
+        let frenzy = TagId::from(2);
+        app.insert_resource(MutexGroups::new()
+            .group(0, &[calm, frenzy])
+        );
+
+        let (entity, _) = setup_entity(&mut app);
+        let mut query = app.world_mut()
+            .query::<(Entity, &GameplayStats<MyStats>, &ActiveEffects<MyStats>, &ActiveTags)>();
+
+        let calm_buff = GameplayEffect::new(
+            Some(calm),
+            MyStats::Health,
+            EffectMagnitude::Fixed(50.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        let frenzy_buff = GameplayEffect::new(
+            Some(frenzy),
+            MyStats::Health,
+            EffectMagnitude::Fixed(2.),
+            EffectCalculation::Multiplicative,
+            EffectDuration::Persistent(None),
+        );
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, calm_buff, None)));
+        let (_, stats, effects, tags) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 150.);
+        assert_eq!(effects.iter().len(), 1);
+        assert!(tags.contains(&calm));
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, frenzy_buff, None)));
+        let (_, stats, effects, tags) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 200.);
+        assert_eq!(stats.get(MyStats::Health).modified_base, 200.);
+        assert_eq!(effects.iter().len(), 1);
+        assert!(tags.contains(&frenzy));
+        assert!(!tags.contains(&calm));
+
+        let events = app.world_mut().resource_mut::<Events<OnEffectRemoved>>();
+        let mut cursor = events.get_cursor();
+        let event = cursor.read(&events).next().unwrap();
+        assert_eq!(event.tag, Some(calm));
+    }
+
+    #[test] 
+    fn test_weighted_table_magnitude() {
+        let mut app = setup_app();
+        app.insert_resource(EffectRng::seeded(42));
+        let (entity, mut query) = setup_entity(&mut app);
+
+        let roll = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::WeightedTable(vec![(70., 1.), (25., 2.), (5., 3.)]),
+            EffectCalculation::SetValue,
+            EffectDuration::Immediate,
+        );
+
+        let rolls = 10_000;
+        let mut counts = [0usize; 3];
+        for _ in 0..rolls {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, roll.clone(), None)));
+            let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+            let value = stats.get(MyStats::Strength).current_value;
+            counts[value as usize - 1] += 1;
+        }
+        for (count, expected) in counts.iter().zip([0.70, 0.25, 0.05]) {
+            let frequency = *count as f32 / rolls as f32;
+            assert!((frequency - expected).abs() < 0.02, "frequency {frequency} expected {expected}");
+        }
+
+        let empty = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::WeightedTable(vec![]),
+            EffectCalculation::SetValue,
+            EffectDuration::Immediate,
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, empty, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 0.);
+    }
+
+
+    #[test]
+    fn test_weighted_table_rolled_on_add() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let next_roll = |app: &App| app.world().resource::<EffectRng>().clone().next_f32();
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                None,
+                MyStats::Health,
+                EffectMagnitude::WeightedTable(vec![(1., 50.), (1., 60.)]),
+                EffectCalculation::LowerBound,
+                EffectDuration::Persistent(None),
+            ),
+            None,
+        )));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                None,
+                MyStats::Health,
+                EffectMagnitude::Fixed(-1.),
+                EffectCalculation::Additive,
+                EffectDuration::Continuous(None),
+            ),
+            None,
+        )));
+        let (_, _, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert!(effects.iter().all(|e| matches!(e.magnitude, EffectMagnitude::Fixed(_))));
+
+        // Applying the continuous effect reads the bound every frame without rolling again
+        let roll = next_roll(&app);
+        for _ in 0..3 {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+        }
+        assert_eq!(next_roll(&app), roll);
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 97.);
+    }
+
+    #[test] 
+    fn test_scaling_params_non_finite() {
+        // Negative base with fractional exponent is NaN
+        let sqrt = StatScalingParams { exponent: 0.5, ..default() };
+        assert_eq!(sqrt.apply(-4.), 0.);
+        let sqrt_min = StatScalingParams { exponent: 0.5, min: Some(1.), ..default() };
+        assert_eq!(sqrt_min.apply(-4.), 1.);
+        let sqrt_max = StatScalingParams { exponent: 0.5, max: Some(10.), ..default() };
+        assert_eq!(sqrt_max.apply(-4.), 0.);
+
+        // Huge exponents overflow
+        let huge = StatScalingParams { exponent: 100., ..default() };
+        assert_eq!(huge.apply(1000.), f32::MAX);
+        let huge_negative = StatScalingParams { exponent: 101., ..default() };
+        assert_eq!(huge_negative.apply(-1000.), f32::MIN);
+        let huge_clamped = StatScalingParams { exponent: 100., min: Some(-5.), max: Some(50.), ..default() };
+        assert_eq!(huge_clamped.apply(1000.), 50.);
+        let inverted = StatScalingParams { exponent: -100., multiplier: -1., max: Some(0.), ..default() };
+        assert_eq!(inverted.apply(0.), f32::MIN);
+
+        for stat in [-1000., -1., 0., 1., 1000.] {
+            for exponent in [-100., -0.5, 0.5, 3.3, 100.] {
+                let params = StatScalingParams { exponent, min: Some(-10.), max: Some(10.), ..default() };
+                let out = params.apply(stat);
+                assert!(out.is_finite());
+                assert!((-10. ..=10.).contains(&out));
+            }
+        }
+    }
+
+    #[test] 
+    fn test_batched_stat_changes() {
+        let mut app = setup_app();
+        app.insert_resource(GameplayEffectsSettings { batch_stat_changes: true, ..default() });
+        let (entity, mut query) = setup_entity(&mut app);
+
+        for dps in [-1., -2.] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(
+                entity,
+                GameplayEffect::new(
+                    None,
+                    MyStats::Health,
+                    EffectMagnitude::Fixed(dps),
+                    EffectCalculation::Additive,
+                    EffectDuration::Continuous(Some(10.0.into())),
+                ),
+                None,
+            )));
+        }
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 97.);
+
+        let events = app.world_mut().resource_mut::<Events<OnStatChangedBatched<MyStats>>>();
+        let mut cursor = events.get_cursor();
+        let mut events = cursor.read(&events);
+        assert_eq!(events.len(), 1);
+        let event = events.next().unwrap();
+        assert_eq!(event.entity, entity);
+        assert_eq!(event.stat, MyStats::Health);
+        assert_eq!(event.total_delta, -3.);
+    }
+
+    #[test] 
+    fn test_incoming_multiplier() {
+        let mut app = setup_app();
+        app.insert_resource(IncomingMultipliers::new()
+            .scale(MyStats::Health, MyStats::Vulnerability)
+        );
+        let (entity, mut query) = setup_entity(&mut app);
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                None,
+                MyStats::Vulnerability,
+                EffectMagnitude::Fixed(2.),
+                EffectCalculation::SetValue,
+                EffectDuration::Immediate,
+            ),
+            None,
+        )));
+        let damage = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-10.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, damage, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 80.);
+
+        // Healing is not amplified
+        let heal = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(10.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, heal, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 90.);
+    }
+
+    #[test] 
+    fn test_net_effect_id() {
+        let mut app = setup_app();
+        let tag = TagId::from(1);
+        app.insert_resource(StackingBehaviors::new()
+            .stack(tag, StackingPolicy::MultipleEffects(2))
+        );
+        let (entity, _) = setup_entity(&mut app);
+        let mut query = app.world_mut()
+            .query::<(Entity, &GameplayStats<MyStats>, &ActiveEffects<MyStats>, &ActiveTags)>();
+
+        let buff = GameplayEffect::new(
+            Some(tag),
+            MyStats::Health,
+            EffectMagnitude::Fixed(10.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff.clone(), None).with_net_id(NetEffectId(7))));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff, None).with_net_id(NetEffectId(8))));
+
+        let events = app.world_mut().resource_mut::<Events<OnEffectAdded>>();
+        let mut cursor = events.get_cursor();
+        let ids: Vec<_> = cursor.read(&events).map(|e| e.net_id).collect();
+        assert_eq!(ids, vec![Some(NetEffectId(7)), Some(NetEffectId(8))]);
+
+        let (_, stats, effects, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 120.);
+        assert_eq!(effects.iter().len(), 2);
+
+        app.world_mut().trigger(RemoveEffectByNetId { target_entity: entity, net_id: NetEffectId(7) });
+        let (_, stats, effects, tags) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 110.);
+        assert_eq!(effects.iter().len(), 1);
+        assert_eq!(effects[0].net_id, Some(NetEffectId(8)));
+        assert!(tags.contains(&tag));
+
+        let events = app.world_mut().resource_mut::<Events<OnEffectRemoved>>();
+        let mut cursor = events.get_cursor();
+        let event = cursor.read(&events).next().unwrap();
+        assert_eq!(event.net_id, Some(NetEffectId(7)));
+        assert_eq!(event.target_entity, entity);
+    }
+
+    #[test] 
+    fn test_scheduled_effect() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                None,
+                MyStats::Health,
+                EffectMagnitude::Fixed(-10.),
+                EffectCalculation::Additive,
+                EffectDuration::Scheduled(vec![1.5, 2.5, 3.0, 6.0]),
+            ),
+            None,
+        )));
+
+        // (seconds to advance, expected health, effect still active)
+        let steps = [(1, 100., true), (1, 90., true), (2, 70., true), (5, 60., false), (5, 60., false)];
+        for (secs, health, active) in steps {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(secs));
+            app.update();
+            let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, health);
+            assert_eq!(effects.iter().len(), active as usize);
+        }
+    }
+
+    #[test] 
+    fn test_missing_stats_component() {
+        let mut app = setup_app();
+        let entity = app.world_mut().spawn(ActiveEffects::<MyStats>::new(std::iter::empty())).id();
+
+        for duration in [EffectDuration::Immediate, EffectDuration::Persistent(None), EffectDuration::Continuous(None)] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(
+                entity,
+                GameplayEffect::new(
+                    None,
+                    MyStats::Health,
+                    EffectMagnitude::LocalStat(MyStats::Strength, StatScalingParams::default()),
+                    EffectCalculation::Additive,
+                    duration,
+                ),
+                None,
+            )));
+        }
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, None, None)));
+
+        let effects = app.world().get::<ActiveEffects<MyStats>>(entity).unwrap();
+        assert_eq!(effects.iter().len(), 0);
+        let events = app.world().resource::<Events<OnEffectAdded>>();
+        assert!(events.is_empty());
+    }
+
+    #[test] 
+    fn test_none_stat_access() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        let mut query = app.world_mut()
+            .query::<(Entity, &GameplayStats<MyStats>, &ActiveEffects<MyStats>, &ActiveTags)>();
+
+        let tag = TagId::from(1);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::<MyStats>::tag_effect(tag, None),
+            None,
+        )));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                None,
+                MyStats::Health,
+                EffectMagnitude::LocalStat(MyStats::None, StatScalingParams::default()),
+                EffectCalculation::Additive,
+                EffectDuration::Continuous(None),
+            ),
+            None,
+        )));
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+
+        let (_, stats, effects, tags) = query.iter(app.world_mut()).next().unwrap();
+        assert!(stats.try_get(MyStats::None).is_none());
+        assert_eq!(stats.try_get(MyStats::Health).unwrap().current_value, 100.);
+        assert_eq!(effects.iter().len(), 2);
+        assert!(tags.contains(&tag));
+    }
+
+    #[repr(u8)]
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum SparseStats {
+        Armor = 10,
+        Speed = 20,
+        Luck = 200,
+        None = 255,
+    }
+
+    impl From<SparseStats> for usize {
+        fn from(stat: SparseStats) -> Self {
+            stat as usize
+        }
+    }
+
+    impl StatTrait for SparseStats {
+        const NONE: Self = SparseStats::None;
+
+        fn variants() -> &'static [Self] {
+            &[SparseStats::Armor, SparseStats::Speed, SparseStats::Luck]
+        }
+    }
+
+    #[test] 
+    fn test_non_contiguous_stats() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins.build().disable::<TimePlugin>());
+        app.world_mut().insert_resource::<Time>(Time::default());
+        app.add_plugins(GameplayEffectsPlugin::<SparseStats>::default());
+
+        let stats = GameplayStats::<SparseStats>::new(|stat| {
+            match stat {
+                SparseStats::Armor => 5.,
+                SparseStats::Speed => 7.,
+                SparseStats::Luck => 3.,
+                SparseStats::None => unreachable!(),
+            }
+        });
+        assert_eq!(stats.get(SparseStats::Armor).current_value, 5.);
+        assert_eq!(stats.get(SparseStats::Speed).current_value, 7.);
+        assert_eq!(stats.get(SparseStats::Luck).current_value, 3.);
+        assert!(stats.try_get(SparseStats::None).is_none());
+
+        let entity = app.world_mut().spawn((stats, ActiveEffects::<SparseStats>::new(std::iter::empty()))).id();
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                None,
+                SparseStats::Luck,
+                EffectMagnitude::LocalStat(SparseStats::Speed, StatScalingParams::default()),
+                EffectCalculation::Additive,
+                EffectDuration::Persistent(None),
+            ),
+            None,
+        )));
+        let stats = app.world().get::<GameplayStats<SparseStats>>(entity).unwrap();
+        assert_eq!(stats.get(SparseStats::Luck).current_value, 10.);
+        assert_eq!(stats.get(SparseStats::Armor).current_value, 5.);
+        assert_eq!(stats.get(SparseStats::Speed).current_value, 7.);
+    }
+
+    #[test] 
+    fn test_outgoing_multiplier() {
+        let mut app = setup_app();
+        app.insert_resource(OutgoingMultiplier::new(MyStats::SpellPower));
+        let (target, mut query) = setup_entity(&mut app);
+        let (caster, _) = setup_entity(&mut app);
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            caster,
+            GameplayEffect::new(
+                None,
+                MyStats::SpellPower,
+                EffectMagnitude::Fixed(2.),
+                EffectCalculation::SetValue,
+                EffectDuration::Immediate,
+            ),
+            None,
+        )));
+        let fireball = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-10.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, fireball.clone(), Some(caster))));
+        let health = query.get(app.world(), target).unwrap().1.get(MyStats::Health).current_value;
+        assert_eq!(health, 80.);
+
+        // Without a source there is nothing to scale by
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, fireball, None)));
+        let health = query.get(app.world(), target).unwrap().1.get(MyStats::Health).current_value;
+        assert_eq!(health, 70.);
+    }
+
+    #[test] 
+    fn test_remove_effect_group() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+
+        let parts = [
+            (MyStats::Health, 2., EffectCalculation::Multiplicative),
+            (MyStats::Health, 20., EffectCalculation::Additive),
+            (MyStats::Strength, 5., EffectCalculation::Additive),
+        ];
+        for (stat, magnitude, calculation) in parts {
+            let effect = GameplayEffect::new(
+                None,
+                stat,
+                EffectMagnitude::Fixed(magnitude),
+                calculation,
+                EffectDuration::Persistent(None),
+            ).with_group(3);
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect, None)));
+        }
+        let unrelated = GameplayEffect::new(
+            None,
+            MyStats::HealthMax,
+            EffectMagnitude::Fixed(50.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, unrelated, None)));
+
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 240.);
+        assert_eq!(stats.get(MyStats::Strength).current_value, 15.);
+        assert_eq!(effects.iter().len(), 4);
+
+        app.world_mut().trigger(RemoveEffectGroup(entity, 3));
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 100.);
+        assert_eq!(stats.get(MyStats::Strength).current_value, 10.);
+        assert_eq!(stats.get(MyStats::HealthMax).current_value, 150.);
+        assert_eq!(effects.iter().len(), 1);
+
+        let events = app.world_mut().resource_mut::<Events<OnEffectRemoved>>();
+        let mut cursor = events.get_cursor();
+        assert_eq!(cursor.read(&events).len(), 3);
+    }
+
+    #[test] 
+    fn test_stable_effect_order() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+
+        fn order(app: &mut App) -> Vec<TagId> {
+            let mut query = app.world_mut().query::<&ActiveEffects<MyStats>>();
+            let effects = query.iter(app.world()).next().unwrap();
+            effects.iter().map(|e| e.tag.unwrap()).collect()
+        }
+
+        for i in 1..=8 {
+            // Tag 3 and 6 expire after a second, the rest are persistent
+            let timer = if i % 3 == 0 { Some(1.0.into()) } else { None };
+            let effect = GameplayEffect::new(
+                Some(TagId::from(i)),
+                MyStats::Strength,
+                EffectMagnitude::Fixed(1.),
+                EffectCalculation::Additive,
+                EffectDuration::Persistent(timer),
+            ).with_group(i as u64 % 2);
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect, None)));
+        }
+
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(TagId::from(2)), None)));
+        assert_eq!(order(&mut app), [1, 3, 4, 5, 6, 7, 8].map(TagId::from).to_vec());
+
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+        assert_eq!(order(&mut app), [1, 4, 5, 7, 8].map(TagId::from).to_vec());
+
+        app.world_mut().trigger(RemoveEffectGroup(entity, 1));
+        assert_eq!(order(&mut app), [4, 8].map(TagId::from).to_vec());
+    }
+
+    #[test] 
+    fn test_stacking_inherited_from_parent_tag() {
+        let mut app = setup_app();
+        let mut registry = TagRegistry::new();
+        let status = registry.register("Effect.Status");
+        let burning = registry.register("Effect.Status.Burning");
+        let stacking = StackingBehaviors::new()
+            .stack(status, StackingPolicy::MultipleEffects(2));
+        assert!(matches!(stacking.get(burning, Some(&registry)), StackingPolicy::MultipleEffects(2)));
+        assert!(matches!(stacking.get(burning, None), StackingPolicy::NoStacking));
+        app.insert_resource(registry);
+        app.insert_resource(stacking);
+
+        let (entity, mut query) = setup_entity(&mut app);
+        let effect = GameplayEffect::new(
+            Some(burning),
+            MyStats::Health,
+            EffectMagnitude::Fixed(-1.0),
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(Some(5.0.into())),
+        );
+        for _ in 0..3 {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect.clone(), None)));
+        }
+        let (_, _, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(effects.iter().len(), 2);
+    }
+
+    #[test] 
+    fn test_duration_scaling() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+
+        // Heals 10 per second, ramping up to 3x as it expires
+        let effect = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(10.),
+            EffectCalculation::Additive,
+            EffectDuration::Repeating(1.0.into(), Some(10.0.into())),
+        ).with_duration_scaling(DurationScaling { start: 1., end: 3. });
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect, None)));
+
+        let mut previous = 100.;
+        for i in 1..=9 {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+            let health = stats.get(MyStats::Health).current_value;
+            let fraction_remaining = (10 - i) as f32 / 10.;
+            let expected = 10. * (3. - 2. * fraction_remaining);
+            assert!((health - previous - expected).abs() < 1e-3);
+            previous = health;
+        }
+        // 10 * (1.2 + 1.4 + ... + 2.8), the last tick at 10% remaining
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert!((stats.get(MyStats::Health).current_value - 100. - 180.).abs() < 1e-2);
+    }
+
+    #[test] 
+    fn test_damage_ledger() {
+        let mut app = setup_app();
+        let (target, _) = setup_entity(&mut app);
+        let (attacker, _) = setup_entity(&mut app);
+        let (healer, _) = setup_entity(&mut app);
+
+        let change = |amount: f32| GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(amount),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        );
+        for amount in [-10., -25., 5.] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(target, change(amount), Some(attacker))));
+        }
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, change(20.), Some(healer))));
+        // Unattributed changes are not recorded
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, change(-50.), None)));
+
+        let ledger = app.world().resource::<DamageLedger<MyStats>>();
+        assert_eq!(ledger.get(attacker, MyStats::Health), LedgerEntry { positive: 5., negative: -35. });
+        assert_eq!(ledger.get(healer, MyStats::Health), LedgerEntry { positive: 20., negative: 0. });
+        assert_eq!(ledger.get(attacker, MyStats::Strength), LedgerEntry::default());
+        assert_eq!(ledger.iter_source(attacker).count(), 1);
+    }
+
+    #[derive(Resource)]
+    struct InCombat(bool);
+
+    #[test] 
+    fn test_conditional_effect() {
+        let mut app = setup_app();
+        app.insert_resource(InCombat(false));
+        let (entity, mut query) = setup_entity(&mut app);
+
+        // Regenerate only while out of combat
+        let regen = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(1.),
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(Some(10.0.into())),
+        ).with_condition(|_, world| !world.resource::<InCombat>().0);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, regen, None)));
+
+        let mut expected = 100.;
+        for in_combat in [false, true, true, false, true, false] {
+            app.world_mut().resource_mut::<InCombat>().0 = in_combat;
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            if !in_combat {
+                expected += 1.;
+            }
+            let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, expected);
+            assert_eq!(effects.iter().len(), 1);
+        }
+    }
+
+
+    #[test]
+    fn test_conditions_evaluated_only_when_present() {
+        use bevy::ecs::system::RunSystemOnce;
+        use crate::effects::{has_effect_conditions, ACTIVE_EFFECTS_SIZE};
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        let has_conditions = |app: &mut App| app.world_mut().run_system_once(has_effect_conditions::<MyStats, ACTIVE_EFFECTS_SIZE>).unwrap();
+        let effect = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(1.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect.clone(), None)));
+        assert!(!has_conditions(&mut app));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect.with_condition(|_, _| true), None)));
+        assert!(has_conditions(&mut app));
+    }
+
+    #[test] 
+    fn test_effect_outcome() {
+        let mut app = setup_app();
+        let tag = TagId::from(1);
+        app.insert_resource(StackingBehaviors::new()
+            .stack(tag, StackingPolicy::NoStacking)
+        );
+        let (entity, _) = setup_entity(&mut app);
+
+        let effect = GameplayEffect::new(
+            Some(tag),
+            MyStats::Health,
+            EffectMagnitude::Fixed(-1.0),
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(Some(3.0.into())),
+        );
+        let (first, second) = {
+            let mut commands = app.world_mut().commands();
+            (
+                apply_effect_and_wait(&mut commands, AddEffectData::new(entity, effect.clone(), None)),
+                apply_effect_and_wait(&mut commands, AddEffectData::new(entity, effect, None)),
+            )
+        };
+        assert!(first.try_recv().is_none());
+        app.world_mut().flush();
+
+        let first = bevy::tasks::block_on(first);
+        assert!(first.applied);
+        assert_eq!(first.amount, -1.);
+        assert_eq!(first.blocked_reason, None);
+
+        let second = bevy::tasks::block_on(second);
+        assert!(!second.applied);
+        assert_eq!(second.blocked_reason, Some(EffectBlockedReason::StackLimit));
+    }
+
+    #[test] 
+    fn test_spilled_effects_diagnostic() {
+        use bevy::diagnostic::DiagnosticsStore;
+
+        let mut app = setup_app();
+        app.add_plugins(GameplayEffectsDiagnosticsPlugin::<MyStats>::default());
+        let (entity, _) = setup_entity(&mut app);
+        setup_entity(&mut app);
+
+        let path = GameplayEffectsDiagnosticsPlugin::<MyStats>::SPILLED_ACTIVE_EFFECTS;
+        let spilled = |app: &App| app.world().resource::<DiagnosticsStore>().get(&path).unwrap().value();
+        app.update();
+        assert_eq!(spilled(&app), Some(0.));
+
+        let effect = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(1.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        for _ in 0..25 {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect.clone(), None)));
+        }
+        app.update();
+        assert_eq!(spilled(&app), Some(1.));
+    }
+
+
+    #[test] 
+    fn test_small_capacity_active_effects() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins.build().disable::<TimePlugin>());
+        app.world_mut().insert_resource::<Time>(Time::default());
+        app.add_plugins(GameplayEffectsPlugin::<MyStats, 2, 4>::default());
+
+        let stats = GameplayStats::<MyStats>::new(|stat| if stat == MyStats::Health { 100. } else { 0. });
+        let entity = app.world_mut().spawn((
+            stats,
+            ActiveEffects::<MyStats, 2>::new(std::iter::empty()),
+            ActiveTags::<4>::default(),
+        )).id();
+
+        let effect = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(10.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        for _ in 0..3 {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect.clone(), None)));
+        }
+        app.update();
+
+        let effects = app.world().get::<ActiveEffects<MyStats, 2>>(entity).unwrap();
+        assert_eq!(effects.len(), 3);
+        assert!(effects.0.spilled());
+        assert_eq!(app.world().get::<GameplayStats<MyStats>>(entity).unwrap().get(MyStats::Health).current_value, 130.);
+    }
+
+
+    #[test]
+    fn test_active_tags_capacity_from_plugin() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins.build().disable::<TimePlugin>());
+        app.world_mut().insert_resource::<Time>(Time::default());
+        app.add_plugins(GameplayEffectsPlugin::<MyStats, 2, 4>::default());
+
+        let stats = GameplayStats::<MyStats>::new(|_| 10.);
+        let entity = app.world_mut().spawn((stats.clone(), ActiveEffects::<MyStats, 2>::new(std::iter::empty()))).id();
+        assert!(app.world().get::<ActiveTags<4>>(entity).is_some());
+        assert!(app.world().get::<ActiveTags>(entity).is_none());
+
+        let tag = TagId::from(1);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                Some(tag),
+                MyStats::Strength,
+                EffectMagnitude::Fixed(5.),
+                EffectCalculation::Additive,
+                EffectDuration::Persistent(None),
+            ),
+            None,
+        )));
+        assert!(app.world().get::<ActiveTags<4>>(entity).unwrap().contains(&tag));
+
+        // Tags spawned with the entity are kept
+        let mut tags = ActiveTags::<4>::default();
+        tags.add(tag);
+        let tagged = app.world_mut().spawn((stats, ActiveEffects::<MyStats, 2>::new(std::iter::empty()), tags)).id();
+        assert!(app.world().get::<ActiveTags<4>>(tagged).unwrap().contains(&tag));
+    }
+
+
+    #[test] 
+    fn test_apply_instant_matches_immediate_effect() {
+        let mut app = setup_app();
+        app.insert_resource(IncomingMultipliers::new().scale(MyStats::Health, MyStats::Vulnerability));
+        let (effect_entity, _) = setup_entity(&mut app);
+        let (instant_entity, _) = setup_entity(&mut app);
+
+        let effect = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-7.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(effect_entity, effect, None)));
+        app.world_mut().commands().damage(instant_entity, MyStats::Health, 7.);
+        app.world_mut().flush();
+
+        let health = |entity| app.world().get::<GameplayStats<MyStats>>(entity).unwrap().get(MyStats::Health).current_value;
+        assert_eq!(health(instant_entity), 93.);
+        assert_eq!(health(instant_entity), health(effect_entity));
+        assert!(app.world().get::<ActiveEffects<MyStats>>(instant_entity).unwrap().is_empty());
+
+        let events = app.world().resource::<Events<OnStatChanged<MyStats>>>();
+        let mut cursor = events.get_cursor();
+        let mut events = cursor.read(events);
+        assert_eq!(events.len(), 1);
+        let event = events.next().unwrap();
+        assert_eq!(event.entity, instant_entity);
+        assert_eq!(event.delta, -7.);
+    }
+
+
+    #[test] 
+    fn test_cc_resistance() {
+        let mut app = setup_app();
+        let mut registry = TagRegistry::new();
+        let cc = registry.register("Effect.CC");
+        let stun = registry.register("Effect.CC.Stun");
+        app.insert_resource(registry);
+
+        let (entity, _) = setup_entity(&mut app);
+        app.world_mut().entity_mut(entity).insert(
+            Resistances::new().resist(cc, Resistance { duration: 0.5, magnitude: 0. })
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, GameplayEffect::<MyStats>::tag_effect(stun, Some(4.)), None)));
+        assert!(app.world().get::<ActiveTags>(entity).unwrap().contains(&stun));
+
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs_f32(1.5));
+        app.update();
+        assert!(app.world().get::<ActiveTags>(entity).unwrap().contains(&stun));
+
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs_f32(1.));
+        app.update();
+        assert!(!app.world().get::<ActiveTags>(entity).unwrap().contains(&stun));
+    }
+
+    #[test]
+    fn test_magnitude_resistance() {
+        let mut app = setup_app();
+        let curse = TagId::from(1);
+        let (entity, mut query) = setup_entity(&mut app);
+        app.world_mut().entity_mut(entity).insert(
+            Resistances::new().resist(curse, Resistance { duration: 0., magnitude: 0.5 })
+        );
+        let cursed = |stat, amount, calculation, duration| GameplayEffect::new(Some(curse), stat, EffectMagnitude::Fixed(amount), calculation, duration);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, cursed(MyStats::Health, -20., EffectCalculation::Additive, EffectDuration::immediate()), None)));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, cursed(MyStats::Strength, 0.5, EffectCalculation::Multiplicative, EffectDuration::persistent()), None)));
+
+        // Half the damage, and a 0.5 weakness resisted to 0.75
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 90.);
+        assert_eq!(stats.get(MyStats::Strength).current_value, 7.5);
+    }
+
+
+    #[test] 
+    fn test_leech() {
+        let mut app = setup_app();
+        let (source, _) = setup_entity(&mut app);
+        let (target, _) = setup_entity(&mut app);
+
+        let lifesteal = |amount| GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(amount),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        ).with_leech(MyStats::Health, source, 0.2);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, lifesteal(-20.), Some(source))));
+        app.world_mut().flush();
+
+        let health = |app: &App, entity| app.world().get::<GameplayStats<MyStats>>(entity).unwrap().get(MyStats::Health).current_value;
+        assert_eq!(health(&app, target), 80.);
+        assert_eq!(health(&app, source), 104.);
+
+        // Healing the target leeches nothing
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, lifesteal(20.), Some(source))));
+        app.world_mut().flush();
+        assert_eq!(health(&app, target), 100.);
+        assert_eq!(health(&app, source), 104.);
+
+        // The leeched gain is attributed to the source, next to the damage and the heal
+        let ledger = app.world().resource::<DamageLedger<MyStats>>();
+        assert_eq!(ledger.get(source, MyStats::Health), LedgerEntry { positive: 24., negative: -20. });
+
+        // A negative fraction is clamped rather than hurting the source
+        let backfire = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-20.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        ).with_leech(MyStats::Health, source, -0.5);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, backfire, Some(source))));
+        app.world_mut().flush();
+        assert_eq!(health(&app, source), 104.);
+    }
+
+
+    #[test] 
+    fn test_effects_from_source() {
+        let mut app = setup_app();
+        let (caster, _) = setup_entity(&mut app);
+        let (ally_a, _) = setup_entity(&mut app);
+        let (ally_b, _) = setup_entity(&mut app);
+
+        let buff = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(5.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(Some(10.0.into())),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(ally_a, buff.clone(), Some(caster))));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(ally_b, buff.clone(), Some(caster))));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(ally_b, buff, None)));
+
+        let mut found = effects_from_source::<MyStats>(app.world_mut(), caster);
+        found.sort_by_key(|(entity, _)| *entity);
+        let mut expected = vec![ally_a, ally_b];
+        expected.sort();
+        assert_eq!(found.iter().map(|(entity, _)| *entity).collect::<Vec<_>>(), expected);
+        assert!(found.iter().all(|(_, summary)| summary.stat_target == MyStats::Strength && summary.remaining == Some(10.)));
+        assert!(effects_from_source::<MyStats>(app.world_mut(), ally_a).is_empty());
+    }
+
+
+    #[test] 
+    fn test_persistent_removal_after_base_change() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let health = |app: &mut App| app.world().get::<GameplayStats<MyStats>>(entity).unwrap().get(MyStats::Health).current_value;
+
+        let tag = TagId::from(1);
+        let buff = GameplayEffect::new(
+            Some(tag),
+            MyStats::Health,
+            EffectMagnitude::Fixed(2.),
+            EffectCalculation::Multiplicative,
+            EffectDuration::Persistent(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff.clone(), None)));
+        assert_eq!(health(&mut app), 200.);
+
+        // Permanent base change while buffed
+        app.world_mut().get_mut::<GameplayStats<MyStats>>(entity).unwrap().get_mut(MyStats::Health).base_value = 150.;
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(tag), None)));
+        assert_eq!(health(&mut app), 150.);
+
+        // Damaged to 75% while buffed, the proportion survives the base change and removal
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff.clone(), None)));
+        assert_eq!(health(&mut app), 300.);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(-75.), EffectCalculation::Additive, EffectDuration::Immediate),
+            None,
+        )));
+        app.world_mut().get_mut::<GameplayStats<MyStats>>(entity).unwrap().get_mut(MyStats::Health).base_value = 200.;
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(tag), None)));
+        assert_eq!(health(&mut app), 150.);
+
+        // Stats with a zero base no longer divide by zero
+        app.world_mut().get_mut::<GameplayStats<MyStats>>(entity).unwrap().set(MyStats::Strength, GameplayStat::new(0., 0.));
+        let strength_buff = GameplayEffect::new(
+            Some(TagId::from(2)),
+            MyStats::Strength,
+            EffectMagnitude::Fixed(10.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, strength_buff, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 10.);
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(TagId::from(2)), None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 0.);
+    }
+
+
+    #[test] 
+    fn test_full_recalculation() {
+        fn run(full_recalculation: bool) -> App {
+            let mut app = setup_app();
+            app.insert_resource(GameplayEffectsSettings { full_recalculation, ..default() });
+            let (entity, _) = setup_entity(&mut app);
+            let persistent = |tag: TagId, calculation, amount| GameplayEffect::new(
+                Some(tag),
+                MyStats::Health,
+                EffectMagnitude::Fixed(amount),
+                calculation,
+                EffectDuration::Persistent(None),
+            );
+            let damage = GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(-50.), EffectCalculation::Additive, EffectDuration::Immediate);
+
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, persistent(TagId::from(1), EffectCalculation::Multiplicative, 2.), None)));
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, damage, None)));
+            app.world_mut().get_mut::<GameplayStats<MyStats>>(entity).unwrap().get_mut(MyStats::Health).base_value = 150.;
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, persistent(TagId::from(2), EffectCalculation::Additive, 20.), None)));
+            app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(TagId::from(1)), None)));
+            app
+        }
+        fn health(app: &mut App) -> GameplayStat {
+            let mut query = app.world_mut().query::<&GameplayStats<MyStats>>();
+            *query.single(app.world()).unwrap().get(MyStats::Health)
+        }
+
+        let mut incremental = run(false);
+        let mut full = run(true);
+        let (a, b) = (health(&mut incremental), health(&mut full));
+        assert_eq!(a.current_value, 127.5);
+        assert_eq!(a.current_value, b.current_value);
+        assert_eq!(a.modified_base, b.modified_base);
+
+        // Only the full mode honours persistent SetValue effects
+        let entity = full.world_mut().query_filtered::<Entity, With<GameplayStats<MyStats>>>().single(full.world()).unwrap();
+        let set = GameplayEffect::new(
+            Some(TagId::from(3)),
+            MyStats::Health,
+            EffectMagnitude::Fixed(50.),
+            EffectCalculation::SetValue,
+            EffectDuration::Persistent(None),
+        );
+        full.world_mut().trigger(AddEffect(AddEffectData::new(entity, set, None)));
+        assert_eq!(health(&mut full).modified_base, 50.);
+        assert_eq!(health(&mut full).current_value, 37.5);
+        full.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(TagId::from(3)), None)));
+        assert_eq!(health(&mut full).current_value, 127.5);
+    }
+
+
+    #[test] 
+    fn test_threshold_crossed() {
+        let mut app = setup_app();
+        app.insert_resource(StatThresholds::new().threshold(MyStats::Health, MyStats::HealthMax, 0.5));
+        let (entity, _) = setup_entity(&mut app);
+
+        for amount in [-60., -5.] {
+            let damage = GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(amount), EffectCalculation::Additive, EffectDuration::Immediate);
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, damage, None)));
+            app.update();
+        }
+
+        let events = app.world().resource::<Events<OnThresholdCrossed<MyStats>>>();
+        let mut cursor = events.get_cursor();
+        let mut events = cursor.read(events);
+        assert_eq!(events.len(), 1);
+        let event = events.next().unwrap();
+        assert_eq!(event.entity, entity);
+        assert_eq!(event.stat, MyStats::Health);
+        assert_eq!(event.threshold, 0.5);
+        assert_eq!(event.direction, CrossingDirection::Downward);
+    }
+
+
+    #[test]
+    fn test_threshold_crossed_before_first_check() {
+        let mut app = setup_app();
+        app.insert_resource(StatThresholds::new().threshold(MyStats::Health, MyStats::HealthMax, 0.5));
+        let entity = app.world_mut().spawn((
+            GameplayStats::<MyStats>::new(|_| 100.),
+            ActiveEffects::<MyStats>::new(std::iter::empty()),
+        )).id();
+
+        // Damaged in the frame it spawned, before check_thresholds ever saw it
+        let damage = GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(-60.), EffectCalculation::Additive, EffectDuration::Immediate);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, damage, None)));
+        app.update();
+
+        let events = app.world().resource::<Events<OnThresholdCrossed<MyStats>>>();
+        let crossings: Vec<_> = events.get_cursor().read(events).map(|e| (e.entity, e.direction)).collect();
+        assert_eq!(crossings, vec![(entity, CrossingDirection::Downward)]);
+    }
+
+
+    #[test] 
+    fn test_aligned_ticks() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        let dot = |tag| GameplayEffect::new(
+            Some(tag),
+            MyStats::Health,
+            EffectMagnitude::Fixed(-1.),
+            EffectCalculation::Additive,
+            EffectDuration::Repeating(1.0.into(), None),
+        ).with_aligned_ticks();
+        let (first, second) = (TagId::from(1), TagId::from(2));
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, dot(first), None)));
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs_f32(0.3));
+        app.update();
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, dot(second), None)));
+
+        let mut frames = (Vec::new(), Vec::new());
+        let mut cursor = app.world().resource::<Events<OnRepeatingEffectTriggered>>().get_cursor();
+        for frame in 0..30 {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs_f32(0.1));
+            app.update();
+            let events = app.world().resource::<Events<OnRepeatingEffectTriggered>>();
+            for event in cursor.read(events) {
+                if event.tag == Some(first) { frames.0.push(frame) } else { frames.1.push(frame) }
+            }
+        }
+        assert!(frames.0.len() >= 2);
+        assert_eq!(frames.0, frames.1);
+    }
+
+    #[test]
+    fn test_aligned_ticks_follow_period() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        let dot = GameplayEffect::new(
+            None, MyStats::Health, EffectMagnitude::Fixed(-1.), EffectCalculation::Additive, EffectDuration::repeating_forever(2.),
+        ).with_aligned_ticks();
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_millis(500));
+        app.update();
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, dot, None)));
+
+        let mut triggered_at = Vec::new();
+        let mut cursor = app.world().resource::<Events<OnRepeatingEffectTriggered>>().get_cursor();
+        for _ in 0..8 {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_millis(500));
+            app.update();
+            let events = app.world().resource::<Events<OnRepeatingEffectTriggered>>();
+            let elapsed = app.world().resource::<Time>().elapsed_secs();
+            triggered_at.extend(cursor.read(events).map(|_| elapsed));
+        }
+        // Added at 0.5s, the 2s grid puts the ticks at 2s and 4s
+        assert_eq!(triggered_at, vec![2., 4.]);
+    }
+
+    #[test]
+    fn test_aligned_ticks_fixed_step() {
+        let mut app = setup_app();
+        app.insert_resource(GameplayEffectsSettings { fixed_step: Some(0.4), ..default() });
+        let (entity, _) = setup_entity(&mut app);
+        let dot = |tag| GameplayEffect::new(
+            Some(tag),
+            MyStats::Health,
+            EffectMagnitude::Fixed(-1.),
+            EffectCalculation::Additive,
+            EffectDuration::repeating_forever(1.),
+        ).with_aligned_ticks();
+        let (first, second) = (TagId::from(1), TagId::from(2));
+
+        // At 1.1s the clock has only run two steps, so the second DoT aligns to 0.8s rather than 1.1s
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, dot(first), None)));
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_millis(1100));
+        app.update();
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, dot(second), None)));
+
+        let mut frames = (Vec::new(), Vec::new());
+        let mut cursor = app.world().resource::<Events<OnRepeatingEffectTriggered>>().get_cursor();
+        for frame in 0..30 {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_millis(100));
+            app.update();
+            let events = app.world().resource::<Events<OnRepeatingEffectTriggered>>();
+            for event in cursor.read(events) {
+                if event.tag == Some(first) { frames.0.push(frame) } else { frames.1.push(frame) }
+            }
+        }
+        assert!(frames.0.len() >= 2);
+        assert_eq!(frames.0, frames.1);
+    }
+
+
+    #[test] 
+    fn test_stat_history() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        app.world_mut().entity_mut(entity).insert(StatHistory::new(3, &[MyStats::Health]));
+
+        let dot = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-5.),
+            EffectCalculation::Additive,
+            EffectDuration::Repeating(1.0.into(), None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, dot, None)));
+        for _ in 0..5 {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+        }
+
+        let history = app.world().get::<StatHistory<MyStats>>(entity).unwrap();
+        assert_eq!(history.series(MyStats::Health).collect::<Vec<_>>(), vec![85., 80., 75.]);
+        assert_eq!(history.latest(MyStats::Health), Some(75.));
+        assert_eq!(history.series(MyStats::Strength).count(), 0);
+    }
+
+
+    #[test] 
+    fn test_bound_modes() {
+        for (mode, expected) in [(BoundMode::Live, 150.), (BoundMode::Snapshot, 100.)] {
+            let mut app = setup_app();
+            let (entity, mut query) = setup_entity(&mut app);
+            let cap = GameplayEffect::new(
+                None,
+                MyStats::Health,
+                EffectMagnitude::LocalStat(MyStats::HealthMax, StatScalingParams::default()),
+                EffectCalculation::UpperBound,
+                EffectDuration::Persistent(None),
+            ).with_bound_mode(mode);
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, cap, None)));
+
+            // HealthMax grows while the cap is active
+            let max_buff = GameplayEffect::new(
+                None,
+                MyStats::HealthMax,
+                EffectMagnitude::Fixed(50.),
+                EffectCalculation::Additive,
+                EffectDuration::Persistent(None),
+            );
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, max_buff, None)));
+
+            let overheal = GameplayEffect::new(
+                None,
+                MyStats::Health,
+                EffectMagnitude::Fixed(200.),
+                EffectCalculation::SetValue,
+                EffectDuration::Immediate,
+            );
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, overheal, None)));
+            let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, expected);
+        }
+    }
+
+
+    #[test] 
+    fn test_retain_effects() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        for (amount, secs) in [(5., 2.), (7., 10.), (11., 20.)] {
+            let buff = GameplayEffect::new(
+                None,
+                MyStats::Strength,
+                EffectMagnitude::Fixed(amount),
+                EffectCalculation::Additive,
+                EffectDuration::Persistent(Some(secs.into())),
+            );
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff, None)));
+        }
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 33.);
+
+        // Keep only the effects with more than 5s remaining
+        app.world_mut().trigger(RetainEffects::<MyStats>::new(entity, |effect| {
+            effect.remaining_duration().is_some_and(|remaining| remaining > 5.)
+        }));
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(effects.len(), 2);
+        assert_eq!(stats.get(MyStats::Strength).current_value, 28.);
+    }
+
+
+    #[test] 
+    fn test_tightest_bound_wins() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let temporary_cap = TagId::from(1);
+        for (tag, cap) in [(None, 100.), (Some(temporary_cap), 80.)] {
+            let bound = GameplayEffect::new(
+                tag,
+                MyStats::Health,
+                EffectMagnitude::Fixed(cap),
+                EffectCalculation::UpperBound,
+                EffectDuration::Persistent(None),
+            );
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, bound, None)));
+        }
+        let heal = GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(50.), EffectCalculation::Additive, EffectDuration::Immediate);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, heal.clone(), None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 80.);
+
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(temporary_cap), None)));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, heal, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 100.);
+    }
+
+
+    #[test] 
+    fn test_is_at_bound() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let cap = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::LocalStat(MyStats::HealthMax, StatScalingParams::default()),
+            EffectCalculation::UpperBound,
+            EffectDuration::Persistent(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, cap, None)));
+        let heal = GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(10.), EffectCalculation::Additive, EffectDuration::Immediate);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, heal, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.is_at_bound(MyStats::Health), Some(EffectCalculation::UpperBound));
+        assert_eq!(stats.is_at_bound(MyStats::Strength), None);
+
+        let damage = GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(-10.), EffectCalculation::Additive, EffectDuration::Immediate);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, damage, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.is_at_bound(MyStats::Health), None);
+    }
+
+
+    // Generated stat sets may use discriminants well beyond u8
+    #[repr(u32)]
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum GeneratedStats {
+        Mana = 1_000,
+        Focus = 70_000,
+        Rage = 4_000_000,
+        None = u32::MAX,
+    }
+
+    impl From<GeneratedStats> for usize {
+        fn from(stat: GeneratedStats) -> Self {
+            stat as usize
+        }
+    }
+
+    impl StatTrait for GeneratedStats {
+        const NONE: Self = GeneratedStats::None;
+
+        fn variants() -> &'static [Self] {
+            &[GeneratedStats::Mana, GeneratedStats::Focus, GeneratedStats::Rage]
+        }
+    }
+
+    #[test] 
+    fn test_large_discriminant_stats() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins.build().disable::<TimePlugin>());
+        app.world_mut().insert_resource::<Time>(Time::default());
+        app.add_plugins(GameplayEffectsPlugin::<GeneratedStats>::default());
+
+        let stats = GameplayStats::<GeneratedStats>::new(|_| 50.);
+        assert_eq!(usize::from(GeneratedStats::Rage), 4_000_000);
+        assert_eq!(GeneratedStats::Rage.slot(), Some(2));
+        assert!(stats.try_get(GeneratedStats::None).is_none());
+
+        let entity = app.world_mut().spawn((stats, ActiveEffects::<GeneratedStats>::new(std::iter::empty()))).id();
+        let effect = GameplayEffect::new(
+            None,
+            GeneratedStats::Rage,
+            EffectMagnitude::Fixed(25.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect, None)));
+        let stats = app.world().get::<GameplayStats<GeneratedStats>>(entity).unwrap();
+        assert_eq!(stats.get(GeneratedStats::Rage).current_value, 75.);
+        assert_eq!(stats.get(GeneratedStats::Mana).current_value, 50.);
+    }
+
+
+    #[test] 
+    fn test_piecewise_magnitude() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let damage = GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(-60.), EffectCalculation::Additive, EffectDuration::Immediate);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, damage, None)));
+
+        // Heal 10/s below 50% health, otherwise 2/s
+        let heal = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Piecewise { stat: MyStats::Health, reference: MyStats::HealthMax, threshold: 0.5, below: 10., above: 2. },
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, heal, None)));
+
+        for expected in [50., 52., 54.] {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, expected);
+        }
+    }
+
+
+    #[test] 
+    fn test_cached_magnitude() {
+        let mut app = setup_app();
+        let (source, _) = setup_entity(&mut app);
+        let (cached, _) = setup_entity(&mut app);
+        let (uncached, _) = setup_entity(&mut app);
+
+        let dot = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::NonlocalStat(MyStats::Strength, StatScalingParams { multiplier: -1., ..default() }, source),
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(cached, dot.clone().with_cached_magnitude(), None)));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(uncached, dot, None)));
+
+        let health = |app: &App, entity| app.world().get::<GameplayStats<MyStats>>(entity).unwrap().get(MyStats::Health).current_value;
+        for frame in 0..6 {
+            if frame == 3 {
+                app.world_mut().get_mut::<GameplayStats<MyStats>>(source).unwrap().get_mut(MyStats::Strength).current_value = 4.;
+            }
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            assert_eq!(health(&app, cached), health(&app, uncached));
+        }
+        // 3 frames at 10 Strength then 3 at 4
+        assert_eq!(health(&app, cached), 58.);
+    }
+
+
+    #[test] 
+    fn test_phase_ordered_application() {
+        let mut app = setup_app();
+        let (first, _) = setup_entity(&mut app);
+        let (second, _) = setup_entity(&mut app);
+        let periodic = |calculation, amount| GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(amount),
+            calculation,
+            EffectDuration::Repeating(1.0.into(), None),
+        );
+        let add = periodic(EffectCalculation::Additive, 10.);
+        let double = periodic(EffectCalculation::Multiplicative, 2.);
+
+        for effect in [add.clone(), double.clone()] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(first, effect, None)));
+        }
+        for effect in [double, add] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(second, effect, None)));
+        }
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+
+        let health = |entity| app.world().get::<GameplayStats<MyStats>>(entity).unwrap().get(MyStats::Health).current_value;
+        assert_eq!(health(first), 220.);
+        assert_eq!(health(second), 220.);
+    }
+
+
+    #[test] 
+    fn test_random_target() {
+        fn drained_stats(seed: u64) -> Vec<MyStats> {
+            let mut app = setup_app();
+            app.insert_resource(EffectRng::seeded(seed));
+            let (entity, _) = setup_entity(&mut app);
+            let drain = GameplayEffect::new(
+                None,
+                MyStats::None,
+                EffectMagnitude::Fixed(-10.),
+                EffectCalculation::Additive,
+                EffectDuration::Immediate,
+            ).with_random_target();
+
+            let mut drained = Vec::new();
+            for _ in 0..8 {
+                let before = app.world().get::<GameplayStats<MyStats>>(entity).unwrap().clone();
+                app.world_mut().trigger(AddEffect(AddEffectData::new(entity, drain.clone(), None)));
+                let after = app.world().get::<GameplayStats<MyStats>>(entity).unwrap();
+                let changed: Vec<MyStats> = MyStats::variants().iter().copied()
+                    .filter(|&stat| after.get(stat).current_value != before.get(stat).current_value)
+                    .collect();
+                assert_eq!(changed.len(), 1);
+                drained.push(changed[0]);
+            }
+            drained
+        }
+
+        let sequence = drained_stats(42);
+        assert_eq!(sequence, drained_stats(42));
+        assert_eq!(sequence, vec![
+            MyStats::Health, MyStats::Health, MyStats::Health, MyStats::SpellPower,
+            MyStats::Vulnerability, MyStats::Strength, MyStats::Vulnerability, MyStats::Health,
+        ]);
+    }
+
+
+    #[test]
+    fn test_heal_to_max_and_drain_to_min() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let max_buff = GameplayEffect::new(
+            None,
+            MyStats::HealthMax,
+            EffectMagnitude::Fixed(50.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, max_buff, None)));
+        app.world_mut().trigger(ApplyInstant { target_entity: entity, stat: MyStats::Health, amount: -60., source_entity: None });
+
+        let heal = GameplayEffect::heal_to_max(MyStats::Health, MyStats::HealthMax);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, heal, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 150.);
+
+        let drain = GameplayEffect::drain_to_min(MyStats::Health);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, drain, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 0.);
+    }
+
+
+    #[test]
+    fn test_bound_epsilon() {
+        for (bound_epsilon, expected, breaches) in [(0., 149.9995, 0), (1e-3, 150., 1)] {
+            let mut app = setup_app();
+            app.insert_resource(GameplayEffectsSettings { bound_epsilon, ..default() });
+            let (entity, mut query) = setup_entity(&mut app);
+            let cap = GameplayEffect::new(
+                None,
+                MyStats::Health,
+                EffectMagnitude::Fixed(150.),
+                EffectCalculation::UpperBound,
+                EffectDuration::Persistent(None),
+            );
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, cap, None)));
+            let almost = GameplayEffect::new(
+                None,
+                MyStats::Health,
+                EffectMagnitude::Fixed(149.9995),
+                EffectCalculation::SetValue,
+                EffectDuration::Immediate,
+            );
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, almost, None)));
+            let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, expected);
+
+            let events = app.world_mut().resource_mut::<Events<OnBoundsBreached<MyStats>>>();
+            let mut cursor = events.get_cursor();
+            assert_eq!(cursor.read(&events).len(), breaches);
+        }
+    }
+
+
+    #[test]
+    fn test_interrupted_warmup() {
+        let mut app = setup_app();
+        let mut registry = TagRegistry::new();
+        let fireball = registry.register("Ability.Fireball");
+        app.insert_resource(registry);
+        let (entity, mut query) = setup_entity(&mut app);
+        let cast = GameplayEffect::new(
+            Some(fireball),
+            MyStats::Health,
+            EffectMagnitude::Fixed(-30.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        ).with_warmup(2.);
+
+        let (data, receiver) = AddEffectData::new(entity, cast.clone(), None).with_outcome();
+        app.world_mut().trigger(AddEffect(data));
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+        assert_eq!(app.world().resource::<PendingEffects<MyStats>>().on_entity(entity).count(), 1);
+        assert_eq!(receiver.try_recv(), None);
+
+        app.world_mut().trigger(CancelPending(entity, fireball));
+        assert_eq!(receiver.try_recv().unwrap().blocked_reason, Some(EffectBlockedReason::Interrupted));
+        let events = app.world().resource::<Events<OnEffectInterrupted>>();
+        assert_eq!(events.get_cursor().read(events).len(), 1);
+        for _ in 0..3 {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+        }
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 100.);
+
+        // An uninterrupted cast lands once the warmup completes
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, cast, None)));
+        for health in [100., 70., 70.] {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, health);
+        }
+    }
+
+
+    #[test]
+    fn test_current_clamped_to_max_stat() {
+        let mut app = setup_app();
+        app.insert_resource(StatCaps::new().cap(MyStats::Health, MyStats::HealthMax));
+        let (entity, mut query) = setup_entity(&mut app);
+        let max_buff = GameplayEffect::new(
+            None,
+            MyStats::HealthMax,
+            EffectMagnitude::Fixed(50.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(Some(5.0.into())),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, max_buff, None)));
+        let heal = GameplayEffect::heal_to_max(MyStats::Health, MyStats::HealthMax);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, heal, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 150.);
+
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(6));
+        app.update();
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(effects.iter().len(), 0);
+        assert_eq!(stats.get(MyStats::HealthMax).current_value, 100.);
+        assert_eq!(stats.get(MyStats::Health).current_value, 100.);
+    }
+
+
+    struct SaturatingAdd {
+        max: f32,
+        affects_base: bool,
+    }
+
+    impl CalculationFn for SaturatingAdd {
+        fn apply(&self, current: f32, amount: f32) -> f32 {
+            f32::min(current + amount, self.max)
+        }
+
+        fn affects_base(&self) -> bool {
+            self.affects_base
+        }
+    }
+
+    #[test]
+    fn test_custom_calculation() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        use std::sync::Arc;
+        // Built at runtime, e.g. from mod data
+        let saturating_heal = EffectCalculation::Custom(Arc::new(SaturatingAdd { max: 120., affects_base: false }));
+        let saturating_buff = EffectCalculation::Custom(Arc::new(SaturatingAdd { max: 12., affects_base: true }));
+        let heal = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(15.),
+            saturating_heal.clone(),
+            EffectDuration::Immediate,
+        );
+        for expected in [115., 120., 120.] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, heal.clone(), None)));
+            let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, expected);
+        }
+
+        let buff = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(5.),
+            saturating_buff.clone(),
+            EffectDuration::Persistent(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 12.);
+        assert_eq!(saturating_heal, saturating_heal.clone());
+        assert_ne!(saturating_heal, saturating_buff);
+    }
+
+
+    #[test]
+    fn test_removal_events_on_despawn() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        for duration in [EffectDuration::Persistent(None), EffectDuration::Continuous(Some(10.0.into()))] {
+            let effect = GameplayEffect::new(
+                None,
+                MyStats::Strength,
+                EffectMagnitude::Fixed(1.),
+                EffectCalculation::Additive,
+                duration,
+            );
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect, None)));
+        }
+        app.world_mut().despawn(entity);
+
+        let events = app.world().resource::<Events<OnEffectRemoved>>();
+        let removed: Vec<_> = events.get_cursor().read(events).map(|e| e.target_entity).collect();
+        assert_eq!(removed, vec![entity, entity]);
+    }
+
+
+    #[test]
+    fn test_stat_regen() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins.build().disable::<TimePlugin>());
+        app.world_mut().insert_resource::<Time>(Time::default());
+        app.add_plugins(GameplayEffectsPlugin::<MyStats>::default()
+            .with_regen(MyStats::Health, MyStats::HealthRegen, MyStats::HealthMax));
+        let (entity, mut query) = setup_entity(&mut app);
+        app.world_mut().trigger(ApplyInstant { target_entity: entity, stat: MyStats::Health, amount: -20., source_entity: None });
+
+        // Filling up to the max stat flags it like a bound
+        let full = Some(EffectCalculation::UpperBound);
+        for (secs, health, at_bound) in [(1, 85., None), (2, 95., None), (2, 100., full.clone()), (1, 100., full)] {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(secs));
+            app.update();
+            let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, health);
+            assert_eq!(stats.is_at_bound(MyStats::Health), at_bound);
+            assert_eq!(effects.iter().len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_stat_regen_fixed_step() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins.build().disable::<TimePlugin>());
+        app.world_mut().insert_resource::<Time>(Time::default());
+        app.add_plugins(GameplayEffectsPlugin::<MyStats>::default()
+            .with_regen(MyStats::Health, MyStats::HealthRegen, MyStats::HealthMax));
+        app.insert_resource(GameplayEffectsSettings { fixed_step: Some(0.5), ..default() });
+        let (entity, mut query) = setup_entity(&mut app);
+        app.world_mut().trigger(ApplyInstant { target_entity: entity, stat: MyStats::Health, amount: -20., source_entity: None });
+
+        // Regen waits for whole half second steps, carrying the rest of the frame time over
+        for (millis, health) in [(750, 82.5), (750, 87.5), (250, 87.5)] {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_millis(millis));
+            app.update();
+            let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, health);
+        }
+    }
+
+
+    #[test]
+    fn test_temporary_effect() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let buff = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(2.),
+            EffectCalculation::Multiplicative,
+            EffectDuration::Persistent(None),
+        );
+        let boosted = with_temporary_effect(app.world_mut(), entity, buff, |world| {
+            world.get::<GameplayStats<MyStats>>(entity).unwrap().get(MyStats::Strength).current_value
+        });
+        assert_eq!(boosted, 20.);
+
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 10.);
+        assert_eq!(effects.iter().len(), 0);
+    }
+
+
+    #[test]
+    fn test_merge_stacking() {
+        let mut app = setup_app();
+        let tag = TagId::from(1);
+        app.insert_resource(StackingBehaviors::new().stack(tag, StackingPolicy::Merge));
+        let (entity, mut query) = setup_entity(&mut app);
+
+        let effect = GameplayEffect::new(
+            Some(tag),
+            MyStats::Strength,
+            EffectMagnitude::Fixed(2.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        for _ in 0..5 {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect.clone(), None)));
+        }
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(effects.0.len(), 1);
+        assert!(effects.0[0].magnitude == EffectMagnitude::Fixed(10.));
+        assert_eq!(stats.get(MyStats::Strength).current_value, 20.);
+
+        // A different duration is stored separately
+        let mut timed = effect.clone();
+        timed.duration = EffectDuration::Persistent(Some(5.0.into()));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, timed, None)));
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(effects.0.len(), 2);
+        assert_eq!(stats.get(MyStats::Strength).current_value, 22.);
+    }
+
+
+    #[test]
+    fn test_source_invalid_removal_reason() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        let source = app.world_mut().spawn(GameplayStats::<MyStats>::new(|_| 5.)).id();
+        let aura = GameplayEffect::new(
+            None,
+            MyStats::HealthRegen,
+            EffectMagnitude::NonlocalStat(MyStats::Strength, StatScalingParams::default(), source),
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, aura, None)));
+        app.world_mut().entity_mut(source).remove::<GameplayStats<MyStats>>();
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+
+        let events = app.world().resource::<Events<OnEffectRemoved>>();
+        let reasons: Vec<_> = events.get_cursor().read(events).map(|e| e.removal_reason).collect();
+        assert_eq!(reasons, vec![Some(RemovalReason::SourceInvalid)]);
+    }
+
+    #[test]
+    fn test_removed_effect_keeps_source() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        let source = app.world_mut().spawn_empty().id();
+        let (expiring, removed) = (TagId::from(1), TagId::from(2));
+        let buff = |tag, duration| GameplayEffect::new(
+            Some(tag),
+            MyStats::Strength,
+            EffectMagnitude::Fixed(1.),
+            EffectCalculation::Additive,
+            duration,
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff(expiring, EffectDuration::persistent_for(1.)), Some(source))));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff(removed, EffectDuration::persistent()), Some(source))));
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+        // The event's own source is ignored, the effect's is reported
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(removed), None)));
+
+        let events = app.world().resource::<Events<OnEffectRemoved>>();
+        let removals: Vec<_> = events.get_cursor().read(events).map(|e| (e.tag, e.source_entity, e.removal_reason)).collect();
+        assert_eq!(removals, vec![
+            (Some(expiring), Some(source), Some(RemovalReason::Expired)),
+            (Some(removed), Some(source), Some(RemovalReason::Removed)),
+        ]);
+    }
+
+
+    #[test]
+    fn test_effect_vetoes() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let silence = TagId::from(1);
+        let haste = TagId::from(2);
+        app.world_mut().entity_mut(entity).insert(
+            EffectVetoes::<MyStats>::new().veto(move |data| data.effect.tag != Some(silence))
+        );
+
+        let (data, receiver) = AddEffectData::new(entity, GameplayEffect::tag_effect(silence, None), None).with_outcome();
+        app.world_mut().trigger(AddEffect(data));
+        assert_eq!(receiver.try_recv().unwrap().blocked_reason, Some(EffectBlockedReason::Vetoed));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, GameplayEffect::tag_effect(haste, None), None)));
+
+        let (_, _, effects) = query.iter(app.world_mut()).next().unwrap();
+        let tags: Vec<_> = effects.iter().map(|e| e.tag).collect();
+        assert!(tags == vec![Some(haste)]);
+    }
+
+
+    #[test]
+    fn test_effect_budget() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let drain = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-30.),
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(None),
+        ).with_budget(50.);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, drain, None)));
+
+        for (health, active) in [(70., 1), (50., 0), (50., 0)] {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, health);
+            assert_eq!(effects.iter().len(), active);
+        }
+        let events = app.world().resource::<Events<OnEffectRemoved>>();
+        let reasons: Vec<_> = events.get_cursor().read(events).map(|e| e.removal_reason).collect();
+        assert_eq!(reasons, vec![Some(RemovalReason::BudgetExhausted)]);
+    }
+
+    #[test]
+    fn test_effect_budget_after_incoming_multiplier() {
+        let mut app = setup_app();
+        app.insert_resource(IncomingMultipliers::new().scale(MyStats::Health, MyStats::Vulnerability));
+        let (entity, mut query) = setup_entity(&mut app);
+        let vulnerable = GameplayEffect::new(
+            None,
+            MyStats::Vulnerability,
+            EffectMagnitude::Fixed(2.),
+            EffectCalculation::SetValue,
+            EffectDuration::Immediate,
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, vulnerable, None)));
+        let drain = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-20.),
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(None),
+        ).with_budget(50.);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, drain, None)));
+
+        // 40 lands the first second, and only the last 10 of the budget the next
+        for (health, active) in [(60., 1), (50., 0), (50., 0)] {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, health);
+            assert_eq!(effects.iter().len(), active);
+        }
+    }
+
+
+    #[test]
+    fn test_last_delta() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let dot = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-10.),
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, dot, None)));
+        app.world_mut().trigger(ApplyInstant { target_entity: entity, stat: MyStats::Health, amount: 4., source_entity: None });
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 94.);
+        assert_eq!(stats.get(MyStats::Health).last_delta(), -6.);
+        assert_eq!(stats.get(MyStats::Strength).last_delta(), 0.);
+
+        // Resets once a frame passes without changes
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, None, None)));
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).last_delta(), 0.);
+    }
+
+
+    #[test]
+    fn test_suppressed_effects() {
+        let stun = TagId::from(1);
+        let dot = TagId::from(2);
+        for (mode, remaining) in [(SuppressionMode::Tick, 7.), (SuppressionMode::Freeze, 9.)] {
+            let mut app = setup_app();
+            app.insert_resource(Suppressions::new().suppress(stun, dot, mode));
+            let (entity, mut query) = setup_entity(&mut app);
+            let bleed = GameplayEffect::new(
+                Some(dot),
+                MyStats::Health,
+                EffectMagnitude::Fixed(-10.),
+                EffectCalculation::Additive,
+                EffectDuration::Continuous(Some(10.0.into())),
+            );
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, bleed, None)));
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, GameplayEffect::tag_effect(stun, Some(2.)), None)));
+
+            for health in [100., 100., 90.] {
+                app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+                app.update();
+                let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+                assert_eq!(stats.get(MyStats::Health).current_value, health);
+            }
+            let (_, _, effects) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(effects.0[0].remaining_duration(), Some(remaining));
+        }
+    }
+
+
+    #[test]
+    fn test_init_covers_macro_variants() {
+        let initialized = std::cell::RefCell::new(Vec::new());
+        let stats = GameplayStats::<MyStats>::new(|stat| {
+            initialized.borrow_mut().push(stat);
+            usize::from(stat) as f32
+        });
+        assert_eq!(initialized.into_inner(), MyStats::variants());
+        for &stat in MyStats::variants() {
+            assert_eq!(stats.get(stat).base_value, usize::from(stat) as f32);
+        }
+        assert!(stats.try_get(MyStats::None).is_none());
+    }
+
+    stats!(
+        WideStats {
+            S0, S1, S2, S3, S4, S5, S6, S7, S8, S9, S10, S11, S12, S13, S14, S15, S16,
+        }
+    );
+
+    #[test]
+    #[should_panic(expected = "Stat variant 16 of 17 in variants() has slot 16, but GameplayStats holds at most 16 stats")]
+    fn test_too_many_stat_variants() {
+        assert_eq!(usize::from(WideStats::None), usize::MAX);
+        assert_eq!(u8::from(WideStats::None), u8::MAX);
+        GameplayStats::<WideStats>::new(|_| 1.);
+    }
+
+
+    #[test]
+    fn test_macro_slots_match_variants() {
+        // The macro overrides slot() with the discriminant, which must agree with the
+        // position in variants() that hand-written StatTrait impls use
+        for (position, &stat) in MyStats::variants().iter().enumerate() {
+            assert_eq!(stat.slot(), Some(position));
+            assert_eq!(MyStats::from_u8(u8::from(stat)), stat);
+        }
+        assert_eq!(MyStats::NONE.slot(), None);
+    }
+
+
+    #[test]
+    fn test_self_effect() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let buff = GameplayEffect::new(
+            None,
+            MyStats::HealthMax,
+            EffectMagnitude::NonlocalStat(MyStats::Strength, StatScalingParams { multiplier: 2., ..default() }, entity),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        let data = AddEffectData::self_effect(entity, buff);
+        assert!(matches!(data.effect.magnitude, EffectMagnitude::LocalStat(MyStats::Strength, _)));
+        assert_eq!(data.source_entity, Some(entity));
+        app.world_mut().trigger(AddEffect(data));
+
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::HealthMax).current_value, 120.);
+        assert_eq!(effects.from_source(entity).count(), 1);
+    }
+
+
+    #[test]
+    fn test_default_stacking_policy() {
+        let mut app = setup_app();
+        app.insert_resource(StackingBehaviors::new().with_default(StackingPolicy::MultipleEffects(3)));
+        let (entity, mut query) = setup_entity(&mut app);
+        let effect = GameplayEffect::new(
+            Some(TagId::from(1)),
+            MyStats::Strength,
+            EffectMagnitude::Fixed(1.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        for _ in 0..5 {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect.clone(), None)));
+        }
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(effects.iter().len(), 3);
+        assert_eq!(stats.get(MyStats::Strength).current_value, 13.);
+    }
+
+
+    #[test]
+    fn test_untagged_stacking() {
+        let mut app = setup_app();
+        app.insert_resource(StackingBehaviors::new().untagged(StackingPolicy::NoStacking));
+        let (entity, mut query) = setup_entity(&mut app);
+        let slow = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-1.),
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(Some(5.0.into())),
+        );
+        let (data, receiver) = AddEffectData::new(entity, slow.clone(), None).with_outcome();
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, slow.clone(), None)));
+        app.world_mut().trigger(AddEffect(data));
+        assert_eq!(receiver.try_recv().unwrap().blocked_reason, Some(EffectBlockedReason::StackLimit));
+
+        // Untagged effects on another stat do not stack with it
+        let mut other = slow;
+        other.stat_target = MyStats::Strength;
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, other, None)));
+        let (_, _, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(effects.iter().len(), 2);
+    }
+
+
+    #[derive(Component)]
+    struct MoveSpeed(f32);
+
+    impl EffectTarget<MyStats> for MoveSpeed {
+        fn stat() -> MyStats {
+            MyStats::SpellPower
+        }
+
+        fn write(&mut self, value: f32) {
+            self.0 = value;
+        }
+    }
+
+    #[test]
+    fn test_effect_target_component() {
+        let mut app = setup_app();
+        app.add_plugins(EffectTargetPlugin::<MyStats, MoveSpeed>::default());
+        let (entity, _) = setup_entity(&mut app);
+        app.world_mut().entity_mut(entity).insert(MoveSpeed(0.));
+        app.update();
+        assert_eq!(app.world().get::<MoveSpeed>(entity).unwrap().0, 1.);
+
+        let slow = GameplayEffect::new(
+            None,
+            MyStats::SpellPower,
+            EffectMagnitude::Fixed(0.5),
+            EffectCalculation::Multiplicative,
+            EffectDuration::Persistent(Some(2.0.into())),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, slow, None)));
+        for (secs, speed) in [(1, 0.5), (2, 1.)] {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(secs));
+            app.update();
+            assert_eq!(app.world().get::<MoveSpeed>(entity).unwrap().0, speed);
+        }
+    }
+
+
+    #[test]
+    fn test_expiry_warning() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        let buff = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(5.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(Some(5.0.into())),
+        ).with_expiry_warning(1.);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff, None)));
+
+        let mut cursor = app.world().resource::<Events<OnEffectExpiring>>().get_cursor();
+        let mut warned_at = Vec::new();
+        for elapsed in 1..=6 {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            let events = app.world().resource::<Events<OnEffectExpiring>>();
+            warned_at.extend(cursor.read(events).map(|_| elapsed));
+        }
+        assert_eq!(warned_at, vec![4]);
+    }
+
+
+    #[test]
+    fn test_resume_with_remaining() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let buff = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(5.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(Some(10.0.into())),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff, None).with_remaining(3.)));
+        let (_, _, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(effects.0[0].remaining_duration(), Some(3.));
+
+        for (strength, active) in [(15., 1), (15., 1), (10., 0)] {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Strength).current_value, strength);
+            assert_eq!(effects.iter().len(), active);
+        }
+    }
+
+
+    #[test]
+    fn test_stat_formula() {
+        let mut app = setup_app();
+        app.insert_resource(StatFormulas::new()
+            .formula(MyStats::AttackPower, &[MyStats::Strength, MyStats::Level], |stats| {
+                stats.get(MyStats::Strength).current_value * 2. + stats.get(MyStats::Level).current_value
+            }));
+        let (entity, mut query) = setup_entity(&mut app);
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::AttackPower).current_value, 25.);
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                None,
+                MyStats::Strength,
+                EffectMagnitude::Fixed(5.),
+                EffectCalculation::Additive,
+                EffectDuration::Persistent(None),
+            ),
+            None,
+        )));
+        app.update();
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::AttackPower).current_value, 35.);
+    }
+
+
+    #[test]
+    fn test_tag_contribution() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let (blessing, rage) = (TagId::from(1), TagId::from(2));
+        for (tag, magnitude, calculation) in [
+            (blessing, 5., EffectCalculation::Additive),
+            (rage, 2., EffectCalculation::Multiplicative),
+        ] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(
+                entity,
+                GameplayEffect::new(
+                    Some(tag),
+                    MyStats::Strength,
+                    EffectMagnitude::Fixed(magnitude),
+                    calculation,
+                    EffectDuration::Persistent(None),
+                ),
+                None,
+            )));
+        }
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 30.);
+        assert_eq!(effects.contribution(blessing, MyStats::Strength, stats), 10.);
+        assert_eq!(effects.contribution(rage, MyStats::Strength, stats), 15.);
+        assert_eq!(effects.contribution(TagId::from(3), MyStats::Strength, stats), 0.);
+    }
+
+
+    #[test]
+    fn test_despawn_on_bound() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        app.world_mut().entity_mut(entity)
+            .insert(DespawnOnBound::new(MyStats::Health, EffectCalculation::LowerBound).with_delay(1.));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                None,
+                MyStats::Health,
+                EffectMagnitude::Fixed(0.),
+                EffectCalculation::LowerBound,
+                EffectDuration::Persistent(None),
+            ),
+            None,
+        )));
+        app.world_mut().trigger(ApplyInstant { target_entity: entity, stat: MyStats::Health, amount: -200., source_entity: None });
+        app.update();
+
+        let events = app.world().resource::<Events<OnDeath<MyStats>>>();
+        let deaths: Vec<_> = events.get_cursor().read(events).map(|e| e.entity).collect();
+        assert_eq!(deaths, vec![entity]);
+        assert_eq!(app.world().get::<DespawnOnBound<MyStats>>(entity).unwrap().remaining(), Some(1.));
+
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_millis(500));
+        app.update();
+        assert!(app.world().get_entity(entity).is_ok());
+
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_millis(500));
+        app.update();
+        assert!(app.world().get_entity(entity).is_err());
+    }
+
+
+    #[test]
+    fn test_spawn_batch_requires_active_tags() {
+        let mut app = setup_app();
+        let stats = GameplayStats::<MyStats>::new(|_| 10.);
+        let entities: Vec<Entity> = app.world_mut()
+            .spawn_batch((0..3).map(move |_| (stats.clone(), ActiveEffects::<MyStats>::new(std::iter::empty()))))
+            .collect();
+        app.update();
+
+        let tag = TagId::from(1);
+        for &entity in entities.iter() {
+            assert!(app.world().get::<ActiveTags>(entity).is_some());
+            app.world_mut().trigger(AddEffect(AddEffectData::new(
+                entity,
+                GameplayEffect::new(
+                    Some(tag),
+                    MyStats::Strength,
+                    EffectMagnitude::Fixed(5.),
+                    EffectCalculation::Additive,
+                    EffectDuration::Persistent(None),
+                ),
+                None,
+            )));
+            assert!(app.world().get::<ActiveTags>(entity).unwrap().contains(&tag));
+            assert_eq!(app.world().get::<GameplayStats<MyStats>>(entity).unwrap().get(MyStats::Strength).current_value, 15.);
+        }
+    }
+
+
+    #[test]
+    fn test_tag_count_magnitude() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let ally_tag = TagId::from(1);
+        let allies: Vec<Entity> = (0..4).map(|i| {
+            let mut tags: ActiveTags = ActiveTags::default();
+            if i < 3 {
+                tags.add(ally_tag);
+            }
+            app.world_mut().spawn((ActiveEffects::<MyStats>::new(std::iter::empty()), tags)).id()
+        }).collect();
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                Some(TagId::from(2)),
+                MyStats::Strength,
+                EffectMagnitude::TagCount { tag: ally_tag, candidates: allies, base: 1., per_entity: 2. },
+                EffectCalculation::Additive,
+                EffectDuration::Persistent(None),
+            ),
+            None,
+        )));
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 17.);
+        assert!(effects.iter().all(|e| e.magnitude == EffectMagnitude::Fixed(7.)));
+    }
+
+
+    #[test]
+    fn test_headless_lifecycle() {
+        let stats = GameplayStats::<MyStats>::new(|stat| match stat {
+            MyStats::Health | MyStats::HealthMax => 100.,
+            MyStats::Strength => 10.,
+            _ => 0.,
+        });
+        let mut entity = HeadlessEntity::new(stats);
+        let buff_tag = TagId::from(1);
+        let buff = GameplayEffect::new(
+            Some(buff_tag),
+            MyStats::Strength,
+            EffectMagnitude::Fixed(5.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(Some(2.0.into())),
+        );
+        assert_eq!(entity.add(buff.clone()), Ok(5.));
+        assert_eq!(entity.add(buff), Err(EffectBlockedReason::StackLimit));
+        assert_eq!(entity.stats.get(MyStats::Strength).current_value, 15.);
+
+        entity.add(GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(0.),
+            EffectCalculation::LowerBound,
+            EffectDuration::Persistent(None),
+        )).unwrap();
+        entity.add(GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-10.),
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(Some(1.0.into())),
+        )).unwrap();
+        for health in [95., 90.] {
+            entity.tick(0.5);
+            assert_eq!(entity.stats.get(MyStats::Health).current_value, health);
+        }
+        assert_eq!(entity.effects.len(), 2);
+
+        entity.tick(1.);
+        assert_eq!(entity.stats.get(MyStats::Strength).current_value, 10.);
+        assert_eq!(entity.effects.len(), 1);
+
+        entity.add(GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-200.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        )).unwrap();
+        assert_eq!(entity.stats.get(MyStats::Health).current_value, 0.);
+        assert_eq!(entity.stats.is_at_bound(MyStats::Health), Some(EffectCalculation::LowerBound));
+        assert_eq!(entity.remove(buff_tag), 0);
+    }
+
+
+    #[test]
+    fn test_headless_shares_config() {
+        use std::sync::{Arc, Mutex};
+        let stats = GameplayStats::<MyStats>::new(|stat| match stat {
+            MyStats::Health | MyStats::HealthMax => 100.,
+            MyStats::Strength => 10.,
+            MyStats::Vulnerability => 2.,
+            _ => 0.,
+        });
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink = records.clone();
+        let fire = SchoolId(1);
+        let mut entity = HeadlessEntity::new(stats)
+            .with_caps(StatCaps::new().cap(MyStats::Health, MyStats::HealthMax))
+            .with_incoming(IncomingMultipliers::new().scale(MyStats::Health, MyStats::Vulnerability))
+            .with_schools(SchoolModifiers::new().multiplier(fire, 1.5))
+            .with_immutable(ImmutableStats::new().immutable(MyStats::Level))
+            .with_audit(AuditSink::new(move |mutation| sink.lock().unwrap().push(*mutation)));
+        let hit = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-10.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        ).with_school(fire);
+        assert_eq!(entity.add(hit), Ok(-15.));
+        assert_eq!(entity.stats.get(MyStats::Health).current_value, 70.);
+
+        // Lowering the max stat clamps the capped stat
+        let curse = TagId::from(2);
+        entity.add(GameplayEffect::new(
+            Some(curse),
+            MyStats::HealthMax,
+            EffectMagnitude::Fixed(0.5),
+            EffectCalculation::Multiplicative,
+            EffectDuration::Persistent(None),
+        )).unwrap();
+        assert_eq!(entity.stats.get(MyStats::Health).current_value, 50.);
+        assert_eq!(entity.add(GameplayEffect::new(
+            None,
+            MyStats::Level,
+            EffectMagnitude::Fixed(1.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        )), Err(EffectBlockedReason::ImmutableStat));
+
+        let buff = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(5.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        ).with_warmup(1.);
+        assert_eq!(entity.add(buff), Ok(0.));
+        for strength in [10., 15.] {
+            entity.tick(0.5);
+            assert_eq!(entity.stats.get(MyStats::Strength).current_value, strength);
+        }
+
+        let records = records.lock().unwrap();
+        let entity = Entity::PLACEHOLDER;
+        assert_eq!(*records, vec![
+            StatMutation { entity, stat: MyStats::Health, old: 100., new: 70., tag: None, source: None, cause: MutationCause::Application },
+            StatMutation { entity, stat: MyStats::Health, old: 70., new: 50., tag: Some(curse), source: None, cause: MutationCause::Recalculation },
+            StatMutation { entity, stat: MyStats::HealthMax, old: 100., new: 50., tag: Some(curse), source: None, cause: MutationCause::Recalculation },
+            StatMutation { entity, stat: MyStats::Strength, old: 10., new: 15., tag: None, source: None, cause: MutationCause::Application },
+        ]);
+
+        // Magnitudes read from stats are zero without any stats to read
+        let scaled = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::percent_of(MyStats::HealthMax, 0.3),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        );
+        assert_eq!(base_magnitude(&scaled, None, &mut EffectRng::default()), 0.);
+    }
+
+
+    #[test]
+    fn test_change_stacking_policy() {
+        let mut app = setup_app();
+        let tag = TagId::from(1);
+        let (entity, mut query) = setup_entity(&mut app);
+        let debuff = GameplayEffect::new(
+            Some(tag),
+            MyStats::Strength,
+            EffectMagnitude::Fixed(-1.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        let mut add_debuffs = |app: &mut App| {
+            for _ in 0..3 {
+                app.world_mut().trigger(AddEffect(AddEffectData::new(entity, debuff.clone(), None)));
+            }
+            let (_, _, effects) = query.iter(app.world_mut()).next().unwrap();
+            effects.iter().len()
+        };
+        assert_eq!(add_debuffs(&mut app), 1);
+
+        let mut stacking = app.world_mut().resource_mut::<StackingBehaviors>();
+        assert!(stacking.get_policy(tag).is_none());
+        stacking.set_policy(tag, StackingPolicy::MultipleEffects(3));
+        assert!(matches!(stacking.get_policy(tag), Some(StackingPolicy::MultipleEffects(3))));
+        assert_eq!(add_debuffs(&mut app), 3);
+
+        let mut stacking = app.world_mut().resource_mut::<StackingBehaviors>();
+        assert!(matches!(stacking.remove_policy(tag), Some(StackingPolicy::MultipleEffects(3))));
+        assert!(matches!(stacking.get(tag, None), StackingPolicy::NoStacking));
+    }
+
+
+    #[test]
+    fn test_zero_duration_effects() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                None,
+                MyStats::Health,
+                EffectMagnitude::Fixed(-10.),
+                EffectCalculation::Additive,
+                EffectDuration::Continuous(Some(0.0.into())),
+            ),
+            None,
+        )));
+        for _ in 0..2 {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Health).current_value, 90.);
+            assert_eq!(effects.iter().len(), 0);
+        }
+
+        let (data, receiver) = AddEffectData::new(entity, GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(5.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(Some((-1.0).into())),
+        ), None).with_outcome();
+        app.world_mut().trigger(AddEffect(data));
+        assert_eq!(receiver.try_recv().unwrap().blocked_reason, Some(EffectBlockedReason::Expired));
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 10.);
+        assert_eq!(effects.iter().len(), 0);
+    }
+
+
+    #[test]
+    fn test_audit_sink() {
+        use std::sync::{Arc, Mutex};
+        let mut app = setup_app();
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink = records.clone();
+        app.insert_resource(AuditSink::<MyStats>::new(move |mutation| sink.lock().unwrap().push(*mutation)));
+        let (entity, _) = setup_entity(&mut app);
+        let (source, tag) = (app.world_mut().spawn_empty().id(), TagId::from(1));
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                None,
+                MyStats::Health,
+                EffectMagnitude::Fixed(-30.),
+                EffectCalculation::Additive,
+                EffectDuration::Immediate,
+            ),
+            Some(source),
+        )));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                Some(tag),
+                MyStats::Strength,
+                EffectMagnitude::Fixed(2.),
+                EffectCalculation::Multiplicative,
+                EffectDuration::Persistent(None),
+            ),
+            Some(source),
+        )));
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(tag), None)));
+
+        let records = records.lock().unwrap();
+        assert_eq!(*records, vec![
+            StatMutation { entity, stat: MyStats::Health, old: 100., new: 70., tag: None, source: Some(source), cause: MutationCause::Application },
+            StatMutation { entity, stat: MyStats::Strength, old: 10., new: 20., tag: Some(tag), source: Some(source), cause: MutationCause::Recalculation },
+            StatMutation { entity, stat: MyStats::Strength, old: 20., new: 10., tag: Some(tag), source: Some(source), cause: MutationCause::Recalculation },
+        ]);
+    }
+
+
+    #[test]
+    fn test_processing_budget() {
+        let mut app = setup_app();
+        app.insert_resource(ProcessingBudget::<MyStats>::new(2));
+        let poison = GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(-10.),
+            EffectCalculation::Additive,
+            EffectDuration::Continuous(None),
+        );
+        let buff = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(2.),
+            EffectCalculation::Multiplicative,
+            EffectDuration::Persistent(Some(1.5.into())),
+        );
+        let entities: Vec<Entity> = (0..4)
+            .map(|_| app.world_mut().spawn((GameplayStats::<MyStats>::new(|_| 100.), ActiveEffects::<MyStats>::new([poison.clone()]))).id())
+            .collect();
+        for &entity in entities.iter() {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff.clone(), None)));
+        }
+        let mut query = app.world_mut().query::<&GameplayStats<MyStats>>();
+        let mut step = |app: &mut App| {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            let mut values: Vec<(f32, f32)> = query.iter(app.world())
+                .map(|stats| (stats.get(MyStats::Health).current_value, stats.get(MyStats::Strength).current_value))
+                .collect();
+            values.sort_by(|a, b| a.1.total_cmp(&b.1));
+            values
+        };
+
+        // Half the entities per frame, each advanced by the time since it was last processed, except for
+        // the continuous poison which ticks everywhere every frame
+        assert_eq!(step(&mut app), vec![(90., 200.); 4]);
+        assert_eq!(step(&mut app), vec![(80., 200.); 4]);
+        assert_eq!(step(&mut app), vec![(70., 100.), (70., 100.), (70., 200.), (70., 200.)]);
+
+        // The round-robin follows entities, not their position in the query, so a despawn skips nobody
+        app.world_mut().despawn(entities[0]);
+        assert_eq!(step(&mut app), vec![(60., 100.); 3]);
+    }
+
+
+    #[test]
+    fn test_redirect_to_guardian() {
+        let mut app = setup_app();
+        let (protected, _) = setup_entity(&mut app);
+        let (guardian, _) = setup_entity(&mut app);
+        let health = |app: &App, entity| app.world().get::<GameplayStats<MyStats>>(entity).unwrap().get(MyStats::Health).current_value;
+        let hit = |amount| GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Fixed(amount),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        );
+        app.world_mut().entity_mut(protected).insert(RedirectTo::new(guardian).harmful_only());
+        // A cycle back to the protected entity stops at the guardian
+        app.world_mut().entity_mut(guardian).insert(RedirectTo::new(protected));
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(protected, hit(-30.), None)));
+        assert_eq!((health(&app, protected), health(&app, guardian)), (100., 70.));
+
+        // Healing is not harmful so it stays on the protected entity
+        app.world_mut().trigger(AddEffect(AddEffectData::new(protected, hit(10.), None)));
+        assert_eq!((health(&app, protected), health(&app, guardian)), (110., 70.));
+
+        // Without its guardian the protected entity takes the damage itself
+        app.world_mut().despawn(guardian);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(protected, hit(-30.), None)));
+        assert_eq!(health(&app, protected), 80.);
+    }
+
+
+    #[test]
+    fn test_recalculate_stats_multi() {
+        use bevy::ecs::system::RunSystemOnce;
+        use crate::calculation::{recalculate_stats_multi, EffectContext};
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let buff = |stat| GameplayEffect::new(
+            None,
+            stat,
+            EffectMagnitude::Fixed(2.),
+            EffectCalculation::Multiplicative,
+            EffectDuration::Persistent(None),
+        );
+        app.world_mut().entity_mut(entity).insert(ActiveEffects::<MyStats>::new([buff(MyStats::Strength), buff(MyStats::SpellPower)]));
+
+        let breaches = app.world_mut().run_system_once(move |
+            mut stats_query: Query<&mut GameplayStats<MyStats>>,
+            effects_query: Query<&ActiveEffects<MyStats>>,
+            mut ctx: EffectContext<MyStats>,
+        | {
+            let effects = effects_query.get(entity).unwrap();
+            let stats = [MyStats::Strength, MyStats::SpellPower, MyStats::Strength];
+            recalculate_stats_multi(entity, effects, &stats, &[], &mut stats_query, &mut ctx).len()
+        }).unwrap();
+        assert_eq!(breaches, 0);
+
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 20.);
+        assert_eq!(stats.get(MyStats::SpellPower).current_value, 2.);
+        assert_eq!(stats.get(MyStats::Health).current_value, 100.);
+    }
+
+
+    #[test]
+    fn test_fixed_step_determinism() {
+        let simulate = |frames_ms: &[u64]| {
+            let mut app = setup_app();
+            app.insert_resource(GameplayEffectsSettings { fixed_step: Some(0.125), ..default() });
+            let (entity, mut query) = setup_entity(&mut app);
+            for effect in [
+                GameplayEffect::new(
+                    None,
+                    MyStats::Health,
+                    EffectMagnitude::Fixed(-7.3),
+                    EffectCalculation::Additive,
+                    EffectDuration::Continuous(Some(1.7.into())),
+                ),
+                GameplayEffect::new(
+                    None,
+                    MyStats::Strength,
+                    EffectMagnitude::Fixed(1.1),
+                    EffectCalculation::Multiplicative,
+                    EffectDuration::Persistent(Some(0.9.into())),
+                ),
+            ] {
+                app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect, None)));
+            }
+            for &ms in frames_ms {
+                app.world_mut().resource_mut::<Time>().advance_by(Duration::from_millis(ms));
+                app.update();
+            }
+            let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+            (stats.get(MyStats::Health).current_value, stats.get(MyStats::Strength).current_value, effects.iter().len())
+        };
+
+        // Two clients covering the same 2 seconds with different frame pacing
+        let steady = simulate(&[16; 125]);
+        let uneven = simulate(&[100, 400, 33, 7, 700, 260, 500]);
+        assert_eq!(steady, uneven);
+        assert_eq!(steady.2, 0);
+    }
+
+
+    #[test]
+    fn test_stack_table_magnitude() {
+        let mut app = setup_app();
+        let tag = TagId::from(1);
+        app.insert_resource(StackingBehaviors::new()
+            .stack(tag, StackingPolicy::MultipleEffects(3))
+        );
+        let (entity, mut query) = setup_entity(&mut app);
+
+        // Each stack is worth less the more of them there are
+        for duration in [1_f32, 2., 3.] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(
+                entity,
+                GameplayEffect::new(
+                    Some(tag),
+                    MyStats::Strength,
+                    EffectMagnitude::StackTable(vec![10., 8., 6.]),
+                    EffectCalculation::Additive,
+                    EffectDuration::Persistent(Some(duration.into())),
+                ),
+                None,
+            )));
+        }
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 28.);
+
+        for (strength, stacks) in [(26., 2), (20., 1), (10., 0)] {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Strength).current_value, strength);
+            assert_eq!(effects.iter().len(), stacks);
+        }
+    }
+
+
+    #[test]
+    fn test_percent_of_magnitude() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let magnitude = EffectMagnitude::percent_of(MyStats::HealthMax, 0.3);
+        assert!(magnitude == EffectMagnitude::LocalStat(MyStats::HealthMax, StatScalingParams { multiplier: 0.3, ..default() }));
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(None, MyStats::AttackPower, magnitude, EffectCalculation::Additive, EffectDuration::Immediate),
+            None,
+        )));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::AttackPower).current_value, 30.);
+    }
+
+
+    #[test]
+    fn test_immutable_stat() {
+        let mut app = setup_app();
+        app.insert_resource(ImmutableStats::<MyStats>::new().immutable(MyStats::Level));
+        let (entity, mut query) = setup_entity(&mut app);
+
+        for duration in [EffectDuration::Immediate, EffectDuration::Persistent(None)] {
+            let (data, receiver) = AddEffectData::new(entity, GameplayEffect::new(
+                None,
+                MyStats::Level,
+                EffectMagnitude::Fixed(10.),
+                EffectCalculation::Additive,
+                duration,
+            ), None).with_outcome();
+            app.world_mut().trigger(AddEffect(data));
+            assert_eq!(receiver.try_recv().unwrap().blocked_reason, Some(EffectBlockedReason::ImmutableStat));
+        }
+        app.world_mut().trigger(ApplyInstant { target_entity: entity, stat: MyStats::Level, amount: 10., source_entity: None });
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Level).current_value, 5.);
+        assert_eq!(effects.iter().len(), 0);
+    }
+
+
+    #[test]
+    fn test_immutable_stat_formula() {
+        let mut app = setup_app();
+        app.insert_resource(ImmutableStats::<MyStats>::new().immutable(MyStats::AttackPower));
+        app.insert_resource(StatFormulas::new()
+            .formula(MyStats::AttackPower, &[MyStats::Strength, MyStats::Level], |stats| {
+                stats.get(MyStats::Strength).current_value * 2. + stats.get(MyStats::Level).current_value
+            }));
+        let (entity, mut query) = setup_entity(&mut app);
+
+        // Effects cannot change the stat, but its formula still derives it
+        app.world_mut().trigger(AddEffect(AddEffectData::new(
+            entity,
+            GameplayEffect::new(
+                None,
+                MyStats::Strength,
+                EffectMagnitude::Fixed(5.),
+                EffectCalculation::Additive,
+                EffectDuration::Persistent(None),
+            ),
+            None,
+        )));
+        app.update();
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::AttackPower).current_value, 35.);
+    }
+
+
+    #[test]
+    fn test_effect_payload() {
+        #[derive(Reflect, PartialEq, Debug)]
+        struct Knockback(Vec3);
+
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let fireball = GameplayEffect::new(
+            Some(TagId::from(1)),
+            MyStats::Health,
+            EffectMagnitude::Fixed(-10.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        ).with_payload(Knockback(Vec3::X * 5.));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, fireball, None)));
+
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 90.);
+        let events = app.world().resource::<Events<OnEffectPayload>>();
+        let payloads: Vec<_> = events.get_cursor().read(events).collect();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0].metadata.target_entity, entity);
+        assert_eq!(payloads[0].metadata.tag, Some(TagId::from(1)));
+        assert_eq!(payloads[0].payload.downcast_ref::<Knockback>(), Some(&Knockback(Vec3::X * 5.)));
+    }
+
+
+    #[test]
+    fn test_shared_tag_persists_until_last_effect() {
+        let mut app = setup_app();
+        let tag = TagId::from(1);
+        app.insert_resource(StackingBehaviors::new()
+            .stack(tag, StackingPolicy::MultipleEffects(2))
+        );
+        let (entity, _) = setup_entity(&mut app);
+        for duration in [1_f32, 3.] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(
+                entity,
+                GameplayEffect::new(
+                    Some(tag),
+                    MyStats::Strength,
+                    EffectMagnitude::Fixed(1.),
+                    EffectCalculation::Additive,
+                    EffectDuration::Persistent(Some(duration.into())),
+                ),
+                None,
+            )));
+        }
+
+        for active in [true, true, false] {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            assert_eq!(app.world().get::<ActiveTags>(entity).unwrap().contains(&tag), active);
+        }
+    }
+
+
+    #[test]
+    fn test_tag_events() {
+        let mut app = setup_app();
+        let tag = TagId::from(1);
+        app.insert_resource(StackingBehaviors::new()
+            .stack(tag, StackingPolicy::MultipleEffects(2))
+        );
+        let (entity, _) = setup_entity(&mut app);
+        for duration in [1_f32, 2.] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(
+                entity,
+                GameplayEffect::new(
+                    Some(tag),
+                    MyStats::Strength,
+                    EffectMagnitude::Fixed(1.),
+                    EffectCalculation::Additive,
+                    EffectDuration::Persistent(Some(duration.into())),
+                ),
+                None,
+            )));
+        }
+
+        let mut added_cursor = app.world().resource::<Events<OnTagAdded>>().get_cursor();
+        let mut removed_cursor = app.world().resource::<Events<OnTagRemoved>>().get_cursor();
+        let (mut added, mut removed) = (Vec::new(), Vec::new());
+        for _ in 0..3 {
+            added.extend(added_cursor.read(app.world().resource::<Events<OnTagAdded>>()).copied());
+            removed.extend(removed_cursor.read(app.world().resource::<Events<OnTagRemoved>>()).copied());
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+        }
+        assert_eq!(added, vec![OnTagAdded { entity, tag }]);
+        assert_eq!(removed, vec![OnTagRemoved { entity, tag }]);
+    }
+
+    #[test]
+    fn test_blocked_add_keeps_tags() {
+        let mut app = setup_app();
+        let (calm, frenzy) = (TagId::from(1), TagId::from(2));
+        app.insert_resource(MutexGroups::new().group(0, &[calm, frenzy]));
+        app.insert_resource(StackingBehaviors::new().stack(frenzy, StackingPolicy::MultipleEffects(0)));
+        let (entity, _) = setup_entity(&mut app);
+        let buff = |tag| GameplayEffect::new(
+            Some(tag),
+            MyStats::Strength,
+            EffectMagnitude::Fixed(1.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, buff(calm), None)));
+        let mut added_cursor = app.world().resource::<Events<OnTagAdded>>().get_cursor();
+        let mut removed_cursor = app.world().resource::<Events<OnTagRemoved>>().get_cursor();
+        added_cursor.read(app.world().resource::<Events<OnTagAdded>>()).count();
+
+        // Frenzy can never stack, so it is blocked before it evicts calm or grants its tag
+        let (data, receiver) = AddEffectData::new(entity, buff(frenzy), None).with_outcome();
+        app.world_mut().trigger(AddEffect(data));
+        assert_eq!(receiver.try_recv().unwrap().blocked_reason, Some(EffectBlockedReason::StackLimit));
+        let tags = app.world().get::<ActiveTags>(entity).unwrap();
+        assert!(tags.contains(&calm));
+        assert!(!tags.contains(&frenzy));
+        assert_eq!(app.world().get::<ActiveEffects<MyStats>>(entity).unwrap().iter().len(), 1);
+        assert_eq!(added_cursor.read(app.world().resource::<Events<OnTagAdded>>()).count(), 0);
+        assert_eq!(removed_cursor.read(app.world().resource::<Events<OnTagRemoved>>()).count(), 0);
+        assert_eq!(app.world().get::<GameplayStats<MyStats>>(entity).unwrap().get(MyStats::Strength).current_value, 11.);
+    }
+
+
+    #[test]
+    fn test_effect_cooldown() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let cooldown = TagId::from(1);
+        let potion = GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(1.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        ).with_cooldown(cooldown, 2.);
+        let mut drink = |app: &mut App| {
+            let (data, receiver) = AddEffectData::new(entity, potion.clone(), None).with_outcome();
+            app.world_mut().trigger(AddEffect(data));
+            receiver.try_recv().unwrap().blocked_reason
+        };
+
+        assert_eq!(drink(&mut app), None);
+        assert_eq!(drink(&mut app), Some(EffectBlockedReason::OnCooldown));
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+        assert_eq!(drink(&mut app), Some(EffectBlockedReason::OnCooldown));
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+        assert_eq!(drink(&mut app), None);
+
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 12.);
+    }
+
+
+    #[test]
+    fn test_stats_from_table() {
+        let map = std::collections::HashMap::from([(MyStats::Health, 80.), (MyStats::Strength, 12.)]);
+        let stats = GameplayStats::from_map(&map);
+        assert_eq!(stats.get(MyStats::Health).current_value, 80.);
+        assert_eq!(stats.get(MyStats::Health).base_value, 80.);
+        assert_eq!(stats.get(MyStats::Strength).current_value, 12.);
+        assert_eq!(stats.get(MyStats::HealthMax).current_value, 0.);
+        assert_eq!(stats.get(MyStats::Level).current_value, 0.);
+
+        let stats = GameplayStats::from_pairs(&[(MyStats::Level, 3.), (MyStats::Level, 7.)]);
+        assert_eq!(stats.get(MyStats::Level).current_value, 7.);
+        assert_eq!(stats.get(MyStats::Health).current_value, 0.);
+    }
+
+
+    #[test]
+    fn test_snapshot_sources() {
+        let drain = |snapshot_sources: bool| {
+            let mut app = setup_app();
+            app.world_mut().resource_mut::<GameplayEffectsSettings>().snapshot_sources = snapshot_sources;
+            let (first, mut query) = setup_entity(&mut app);
+            let (second, _) = setup_entity(&mut app);
+            for (target, source) in [(first, second), (second, first)] {
+                app.world_mut().trigger(AddEffect(AddEffectData::new(
+                    target,
+                    GameplayEffect::new(
+                        None,
+                        MyStats::Health,
+                        EffectMagnitude::NonlocalStat(MyStats::Health, StatScalingParams { multiplier: -0.1, ..default() }, source),
+                        EffectCalculation::Additive,
+                        EffectDuration::Continuous(Some(1.0.into())),
+                    ),
+                    Some(source),
+                )));
+            }
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+            let mut health = |entity| query.get(app.world(), entity).unwrap().1.get(MyStats::Health).current_value;
+            (health(first), health(second))
+        };
+
+        // Processed one after the other, the second entity reads the first's already drained health
+        let (first, second) = drain(false);
+        assert_ne!(first, second);
+        assert_eq!(drain(true), (90., 90.));
+    }
+
+
+    #[test]
+    fn test_undispellable_effect() {
+        let mut app = setup_app();
+        let curse = TagId::from(1);
+        app.insert_resource(StackingBehaviors::new()
+            .stack(curse, StackingPolicy::MultipleEffects(2))
+        );
+        let (entity, _) = setup_entity(&mut app);
+        let mut query = app.world_mut()
+            .query::<(&GameplayStats<MyStats>, &ActiveEffects<MyStats>, &ActiveTags)>();
+        let debuff = GameplayEffect::new(
+            Some(curse),
+            MyStats::Strength,
+            EffectMagnitude::Fixed(-2.),
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, debuff.clone().undispellable(), None).with_net_id(NetEffectId(1))));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, debuff, None)));
+
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(curse), None)));
+        app.world_mut().trigger(RetainEffects::<MyStats>::new(entity, |_| false));
+        let (stats, effects, tags) = query.single(app.world()).unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 8.);
+        assert_eq!(effects.iter().len(), 1);
+        assert!(tags.contains(&curse));
+
+        app.world_mut().trigger(RemoveEffectByNetId { target_entity: entity, net_id: NetEffectId(1) });
+        let (stats, effects, tags) = query.single(app.world()).unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 10.);
+        assert_eq!(effects.iter().len(), 0);
+        assert!(!tags.contains(&curse));
+    }
+
+
+    #[test]
+    fn test_blend_magnitude() {
+        let mut app = setup_app();
+        // SpellPower stands in for Intelligence
+        let spawn = |app: &mut App, strength: f32, intelligence: f32| app.world_mut().spawn((
+            GameplayStats::from_pairs(&[(MyStats::Strength, strength), (MyStats::SpellPower, intelligence)]),
+            ActiveEffects::<MyStats>::new(std::iter::empty()),
+        )).id();
+        let target = spawn(&mut app, 10., 20.);
+        let caster = spawn(&mut app, 30., 10.);
+        let hybrid = |source| GameplayEffect::new(
+            None,
+            MyStats::AttackPower,
+            EffectMagnitude::Blend { a: (MyStats::Strength, 0.5), b: (MyStats::SpellPower, 0.5), scaling: default(), source },
+            EffectCalculation::Additive,
+            EffectDuration::Persistent(None),
+        );
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, hybrid(None), None)));
+        assert_eq!(app.world().get::<GameplayStats<MyStats>>(target).unwrap().get(MyStats::AttackPower).current_value, 15.);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, hybrid(Some(caster)), Some(caster))));
+        assert_eq!(app.world().get::<GameplayStats<MyStats>>(target).unwrap().get(MyStats::AttackPower).current_value, 35.);
+    }
+
+
+    #[test]
+    fn test_stat_snapshots() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        app.world_mut().entity_mut(entity).insert(StatSnapshots::<MyStats>::new(4));
+        app.update();
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, GameplayEffect::new(
+            None,
+            MyStats::Strength,
+            EffectMagnitude::Fixed(10.),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        ), None)));
+        app.update();
+
+        let snapshots = app.world().get::<StatSnapshots<MyStats>>(entity).unwrap();
+        assert_eq!(snapshots.stats_at(0).unwrap().get(MyStats::Strength).current_value, 20.);
+        assert!(snapshots.stats_at(2).is_none());
+        let past = snapshots.stats_at(1).unwrap().clone();
+        assert_eq!(past.get(MyStats::Strength).current_value, 10.);
+
+        // Scales with Strength as it was a frame ago rather than the live 20
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, GameplayEffect::new(
+            None,
+            MyStats::AttackPower,
+            EffectMagnitude::LocalStat(MyStats::Strength, StatScalingParams::default()),
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        ), None).with_stats_snapshot(past)));
+        assert_eq!(app.world().get::<GameplayStats<MyStats>>(entity).unwrap().get(MyStats::AttackPower).current_value, 10.);
+    }
+
+
+    #[test]
+    fn test_tracked_effect() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let tag = TagId::from(1);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, GameplayEffect::new(
+            Some(tag),
+            MyStats::Strength,
+            EffectMagnitude::Fixed(20.),
+            EffectCalculation::Additive,
+            EffectDuration::Tracked(None),
+        ), None)));
+
+        for _ in 0..3 {
+            let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+            assert_eq!(stats.get(MyStats::Strength).current_value, 30.);
+            assert_eq!(effects.iter().len(), 1);
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+        }
+
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(tag), None)));
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 10.);
+        assert_eq!(effects.iter().len(), 0);
+    }
+
+    #[test]
+    fn test_tracked_effect_excluded_from_recalculation() {
+        let strength_after = |full_recalculation: bool| {
+            let mut app = setup_app();
+            app.world_mut().resource_mut::<GameplayEffectsSettings>().full_recalculation = full_recalculation;
+            let (entity, mut query) = setup_entity(&mut app);
+            let (tracked, persistent) = (TagId::from(1), TagId::from(2));
+
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, GameplayEffect::new(
+                Some(tracked), MyStats::Strength, EffectMagnitude::Fixed(20.), EffectCalculation::Additive, EffectDuration::tracked(),
+            ), None)));
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, GameplayEffect::new(
+                Some(persistent), MyStats::Strength, EffectMagnitude::Fixed(5.), EffectCalculation::Additive, EffectDuration::persistent(),
+            ), None)));
+            let with_both = query.get(app.world(), entity).unwrap().1.get(MyStats::Strength).current_value;
+            app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(tracked), None)));
+            let persistent_only = query.get(app.world(), entity).unwrap().1.get(MyStats::Strength).current_value;
+            (with_both, persistent_only)
+        };
+
+        // 30 of 10 rescaled to a modified base of 15, then the tracked 20 taken back out
+        assert_eq!(strength_after(false), (45., 25.));
+        assert_eq!(strength_after(true), strength_after(false));
+    }
+
+    #[repr(u8)]
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum OverflowStats {
+        Mana = 0,
+        Rage = 20,
+        None = 255,
+    }
+
+    impl From<OverflowStats> for usize {
+        fn from(stat: OverflowStats) -> Self {
+            stat as usize
+        }
+    }
+
+    impl StatTrait for OverflowStats {
+        const NONE: Self = OverflowStats::None;
+
+        fn variants() -> &'static [Self] {
+            &[OverflowStats::Mana, OverflowStats::Rage]
+        }
+
+        // Indexing by discriminant like the macro, which only works while discriminants stay below the limit
+        fn slot(self) -> Option<usize> {
+            if self == OverflowStats::None { None } else { Some(self as usize) }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Stat variant 1 of 2 in variants() has slot 20, but GameplayStats holds at most 16 stats")]
+    fn test_stat_slot_beyond_limit() {
+        GameplayStats::<OverflowStats>::new(|_| 1.);
+    }
+
+
+    #[test]
+    fn test_falloff_magnitude() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let blast = |distance: f32, curve: FalloffCurve| GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::Falloff { base: -20., distance, curve },
+            EffectCalculation::Additive,
+            EffectDuration::Immediate,
+        );
+
+        let mut rng = EffectRng::default();
+        let linear = FalloffCurve::Linear { radius: 10. };
+        let magnitudes: Vec<f32> = [0., 5., 10., 15.].into_iter()
+            .map(|distance| base_magnitude(&blast(distance, linear), None, &mut rng))
+            .collect();
+        assert_eq!(magnitudes, vec![-20., -10., 0., 0.]);
+        let inverse_square = FalloffCurve::InverseSquare { min_distance: 1. };
+        assert_eq!(base_magnitude(&blast(0.5, inverse_square), None, &mut rng), -20.);
+        assert_eq!(base_magnitude(&blast(2., inverse_square), None, &mut rng), -5.);
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, blast(7.5, linear), None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 95.);
+    }
+
+
+    #[test]
+    fn test_effect_events_param() {
+        #[derive(Resource, Default)]
+        struct Seen {
+            added: usize,
+            removed: usize,
+            repeating: usize,
+            breached: Vec<EffectCalculation>,
+        }
+
+        fn count_events(mut events: EffectEvents<MyStats>, mut seen: ResMut<Seen>) {
+            seen.added += events.added().count();
+            seen.removed += events.removed().count();
+            seen.repeating += events.repeating().count();
+            let breached: Vec<_> = events.breached().map(|e| e.bound.clone()).collect();
+            seen.breached.extend(breached);
+        }
+
+        let mut app = setup_app();
+        app.init_resource::<Seen>();
+        app.add_systems(Update, count_events.after(GameplayEffectsSystemSet));
+        let (entity, _) = setup_entity(&mut app);
+
+        for effect in [
+            GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(0.), EffectCalculation::LowerBound, EffectDuration::Persistent(None)),
+            GameplayEffect::new(None, MyStats::Strength, EffectMagnitude::Fixed(-1.), EffectCalculation::Additive, EffectDuration::Repeating(1.0.into(), Some(2.0.into()))),
+            GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(-200.), EffectCalculation::Additive, EffectDuration::Immediate),
+        ] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect, None)));
+        }
+        for _ in 0..2 {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+        }
+
+        let seen = app.world().resource::<Seen>();
+        assert_eq!((seen.added, seen.removed, seen.repeating), (3, 1, 2));
+        assert_eq!(seen.breached, vec![EffectCalculation::LowerBound]);
+    }
+
+    #[test]
+    fn test_plugin_added_twice() {
+        let mut app = setup_app();
+        app.add_plugins(GameplayEffectsPlugin::<MyStats>::default());
+        let (entity, mut query) = setup_entity(&mut app);
+
+        let effect = GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(-10.), EffectCalculation::Additive, EffectDuration::Immediate);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect, None)));
+        app.update();
+
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 90.);
+        let added = app.world().resource::<Events<OnEffectAdded>>();
+        assert_eq!(added.get_cursor().read(added).count(), 1);
+    }
+
+    #[test]
+    fn test_keep_strongest_stacking() {
+        let mut app = setup_app();
+        let haste = TagId::from(1);
+        app.insert_resource(StackingBehaviors::new().stack(haste, StackingPolicy::KeepStrongest));
+        let (entity, mut query) = setup_entity(&mut app);
+
+        let tier = |amount: f32| GameplayEffect::new(
+            Some(haste), MyStats::Strength, EffectMagnitude::Fixed(amount), EffectCalculation::Additive, EffectDuration::persistent(),
+        );
+        for amount in [2., 5., 2.] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, tier(amount), None)));
+        }
+        app.update();
+
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(effects.0.len(), 1);
+        assert!(effects.0[0].magnitude == EffectMagnitude::Fixed(5.));
+        assert_eq!(stats.get(MyStats::Strength).current_value, 15.);
+        let removed = app.world().resource::<Events<OnEffectRemoved>>();
+        assert_eq!(removed.get_cursor().read(removed).count(), 1);
+        // Replacing the weaker tier keeps the shared tag
+        let tag_removed = app.world().resource::<Events<OnTagRemoved>>();
+        assert_eq!(tag_removed.get_cursor().read(tag_removed).count(), 0);
+    }
+
+    #[test]
+    fn test_keep_strongest_multiplicative() {
+        let mut app = setup_app();
+        let slow = TagId::from(1);
+        app.insert_resource(StackingBehaviors::new().stack(slow, StackingPolicy::KeepStrongest));
+        let (entity, mut query) = setup_entity(&mut app);
+
+        let tier = |factor: f32| GameplayEffect::new(
+            Some(slow), MyStats::Strength, EffectMagnitude::Fixed(factor), EffectCalculation::Multiplicative, EffectDuration::persistent(),
+        );
+        // 0.5 is further from 1 than 0.8, so it replaces the 0.8 slow and blocks the next one
+        for factor in [0.8, 0.5, 0.8] {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, tier(factor), None)));
+        }
+
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(effects.0.len(), 1);
+        assert!(effects.0[0].magnitude == EffectMagnitude::Fixed(0.5));
+        assert_eq!(stats.get(MyStats::Strength).current_value, 5.);
+    }
+
+    #[test]
+    fn test_source_tag() {
+        let mut app = setup_app();
+        let (target, _) = setup_entity(&mut app);
+        let (caster, _) = setup_entity(&mut app);
+        let (drain, channeling) = (TagId::from(1), TagId::from(2));
+        let is_channeling = |app: &App| app.world().get::<ActiveTags>(caster).unwrap().contains(&channeling);
+
+        let effect = GameplayEffect::new(
+            Some(drain), MyStats::Health, EffectMagnitude::Fixed(-5.), EffectCalculation::Additive, EffectDuration::continuous(2.),
+        ).with_source_tag(channeling);
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, effect.clone(), Some(caster))));
+        assert!(is_channeling(&app));
+        assert!(!app.world().get::<ActiveTags>(target).unwrap().contains(&channeling));
+
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(target, Some(drain), None)));
+        assert!(!is_channeling(&app));
+
+        // Expiring also ends the channel, and a despawned source is skipped
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, effect.clone(), Some(caster))));
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(3));
+        app.update();
+        assert!(!is_channeling(&app));
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(target, effect, Some(caster))));
+        app.world_mut().despawn(caster);
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(target, Some(drain), None)));
+        app.update();
+        assert!(app.world().get::<ActiveEffects<MyStats>>(target).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn test_capped_duration_stacks() {
+        let mut app = setup_app();
+        let tag = TagId::from(1);
+        app.insert_resource(StackingBehaviors::new().stack(tag, StackingPolicy::CappedDurationStacks { max_stacks: 3, max_total_secs: 10. }));
+        let (entity, mut query) = setup_entity(&mut app);
+        let debuff = GameplayEffect::new(
+            Some(tag), MyStats::Strength, EffectMagnitude::Fixed(-1.), EffectCalculation::Additive, EffectDuration::persistent_for(4.),
+        );
+        let mut add = |app: &mut App| {
+            app.world_mut().trigger(AddEffect(AddEffectData::new(entity, debuff.clone(), None)));
+            let (_, _, effects) = query.iter(app.world_mut()).next().unwrap();
+            effects.0.iter().map(|e| e.get_duration_timer().unwrap().remaining).collect::<Vec<f32>>()
+        };
+        let tick = |app: &mut App| {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            app.update();
+        };
+
+        // A third 4s stack would take the total past 10s
+        add(&mut app);
+        add(&mut app);
+        assert_eq!(add(&mut app), vec![4., 4.]);
+        tick(&mut app);
+        assert_eq!(add(&mut app), vec![3., 3., 4.]);
+        tick(&mut app);
+        // At the stack cap the stacks are topped up towards 4s, keeping the total at 10s
+        assert_eq!(add(&mut app), vec![4., 3., 3.]);
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 7.);
+    }
+
+    #[test]
+    fn test_capped_duration_stacks_untimed() {
+        let mut app = setup_app();
+        let tag = TagId::from(1);
+        app.insert_resource(StackingBehaviors::new().stack(tag, StackingPolicy::CappedDurationStacks { max_stacks: 2, max_total_secs: 1. }));
+        let (entity, mut query) = setup_entity(&mut app);
+        let debuff = GameplayEffect::new(
+            Some(tag), MyStats::Strength, EffectMagnitude::Fixed(-1.), EffectCalculation::Additive, EffectDuration::persistent(),
+        );
+        let blocked: Vec<_> = (0..3).map(|_| {
+            let (data, receiver) = AddEffectData::new(entity, debuff.clone(), None).with_outcome();
+            app.world_mut().trigger(AddEffect(data));
+            receiver.try_recv().unwrap().blocked_reason
+        }).collect();
+
+        // Untimed stacks ignore the time cap and are blocked at the count cap instead of refreshing
+        assert_eq!(blocked, vec![None, None, Some(EffectBlockedReason::StackLimit)]);
+        let (_, stats, effects) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(effects.iter().len(), 2);
+        assert_eq!(stats.get(MyStats::Strength).current_value, 8.);
+    }
+
+    #[test]
+    fn test_nonlocal_stat_sourced_from_target() {
+        let mut app = setup_app();
+        app.world_mut().resource_mut::<GameplayEffectsSettings>().snapshot_sources = true;
+        let (entity, mut query) = setup_entity(&mut app);
+        let own_strength = |duration| GameplayEffect::new(
+            None,
+            MyStats::Health,
+            EffectMagnitude::NonlocalStat(MyStats::Strength, StatScalingParams { multiplier: -1., ..default() }, entity),
+            EffectCalculation::Additive,
+            duration,
+        );
+
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, own_strength(EffectDuration::immediate()), Some(entity))));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, own_strength(EffectDuration::continuous(1.)).with_cached_magnitude(), Some(entity))));
+        app.world_mut().resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        app.update();
+
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Health).current_value, 80.);
+        // The continuous effect ran out, it was not dropped for a missing source
+        let removed = app.world().resource::<Events<OnEffectRemoved>>();
+        let reasons: Vec<_> = removed.get_cursor().read(removed).map(|e| e.0.removal_reason).collect();
+        assert_eq!(reasons, vec![Some(RemovalReason::Expired)]);
+    }
+
+    #[test]
+    fn test_unmodified_stat_skips_recalculation() {
+        let mut app = setup_app();
+        app.world_mut().resource_mut::<GameplayEffectsSettings>().full_recalculation = true;
+        let (entity, mut query) = setup_entity(&mut app);
+        let tag = TagId::from(1);
+
+        let set = GameplayEffect::new(None, MyStats::Strength, EffectMagnitude::Fixed(3.3), EffectCalculation::SetValue, EffectDuration::immediate());
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, set, None)));
+        // Removing a non persistent effect recalculates Strength, which would rescale 3.3 by 3.3 / 10 * 10
+        let drain = GameplayEffect::new(Some(tag), MyStats::Strength, EffectMagnitude::Fixed(0.), EffectCalculation::Additive, EffectDuration::continuous_forever());
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, drain, None)));
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(tag), None)));
+
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        let strength = stats.get(MyStats::Strength);
+        assert_eq!(strength.modified_base, 10.);
+        assert_eq!(strength.current_value, 3.3);
+    }
+
+    #[test]
+    fn test_tracked_only_stat_skips_recalculation() {
+        let mut app = setup_app();
+        app.world_mut().resource_mut::<GameplayEffectsSettings>().full_recalculation = true;
+        let (entity, mut query) = setup_entity(&mut app);
+        let tag = TagId::from(1);
+
+        let set = GameplayEffect::new(None, MyStats::Strength, EffectMagnitude::Fixed(3.3), EffectCalculation::SetValue, EffectDuration::immediate());
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, set, None)));
+        let tracked = GameplayEffect::new(Some(tag), MyStats::Strength, EffectMagnitude::Fixed(2.), EffectCalculation::Additive, EffectDuration::tracked());
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, tracked, None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).modified_base, 10.);
+
+        // Reverting the tracked change recalculates Strength, which must not rescale 3.3 by 3.3 / 10 * 10
+        app.world_mut().trigger(RemoveEffect(EffectMetadata::new(entity, Some(tag), None)));
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        let strength = stats.get(MyStats::Strength);
+        assert_eq!(strength.modified_base, 10.);
+        assert_eq!(strength.current_value, 3.3);
+    }
+
+    #[test]
+    fn test_apply_effect_now() {
+        let mut app = setup_app();
+        let (entity, _) = setup_entity(&mut app);
+        let hit = GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(-30.), EffectCalculation::Additive, EffectDuration::immediate());
+
+        assert_eq!(app.world_mut().apply_effect_now(entity, hit.clone()), Some(70.));
+        assert_eq!(app.world_mut().apply_effect_now(entity, hit.with_warmup(1.)), Some(70.));
+        let missing = GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(-30.), EffectCalculation::Additive, EffectDuration::immediate());
+        let bystander = app.world_mut().spawn_empty().id();
+        assert_eq!(app.world_mut().apply_effect_now(bystander, missing), None);
+    }
+
+    #[test]
+    fn test_repeating_constructor_period() {
+        let mut app = setup_app();
+        let (entity, mut query) = setup_entity(&mut app);
+        let effect = GameplayEffect::new(None, MyStats::Strength, EffectMagnitude::Fixed(-1.), EffectCalculation::Additive, EffectDuration::repeating(0.5, 2.));
+        app.world_mut().trigger(AddEffect(AddEffectData::new(entity, effect, None)));
+        let mut cursor = app.world().resource::<Events<OnRepeatingEffectTriggered>>().get_cursor();
+        let mut triggers = 0;
+        for _ in 0..4 {
+            app.world_mut().resource_mut::<Time>().advance_by(Duration::from_millis(500));
+            app.update();
+            triggers += cursor.read(app.world().resource::<Events<OnRepeatingEffectTriggered>>()).count();
+        }
+
+        assert_eq!(triggers, 4);
+        let (_, stats, _) = query.iter(app.world_mut()).next().unwrap();
+        assert_eq!(stats.get(MyStats::Strength).current_value, 6.);
+    }
+
+    #[test]
+    fn test_school_modifiers() {
+        let mut app = setup_app();
+        let (fire, physical) = (SchoolId(0), SchoolId(1));
+        app.insert_resource(SchoolModifiers::new().multiplier(fire, 1.3));
+        let (entity, _) = setup_entity(&mut app);
+        let hit = |school| GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(-10.), EffectCalculation::Additive, EffectDuration::immediate())
+            .with_school(school);
+
+        assert_eq!(app.world_mut().apply_effect_now(entity, hit(fire)), Some(87.));
+        assert_eq!(app.world_mut().apply_effect_now(entity, hit(physical)), Some(77.));
+
+        app.world_mut().resource_mut::<SchoolModifiers>().set(fire, 2.);
+        assert_eq!(app.world_mut().apply_effect_now(entity, hit(fire)), Some(57.));
+    }
+
+    #[test]
+    fn test_school_modifiers_headless() {
+        let (fire, physical) = (SchoolId(0), SchoolId(1));
+        let mut entity = HeadlessEntity::new(GameplayStats::<MyStats>::new(|stat| if stat == MyStats::Health { 100. } else { 0. }))
+            .with_schools(SchoolModifiers::new().multiplier(fire, 1.3));
+        let hit = |school| GameplayEffect::new(None, MyStats::Health, EffectMagnitude::Fixed(-10.), EffectCalculation::Additive, EffectDuration::immediate())
+            .with_school(school);
+
+        // Same values as the plugin in test_school_modifiers
+        for (school, health) in [(fire, 87.), (physical, 77.)] {
+            entity.add(hit(school)).unwrap();
+            assert_eq!(entity.stats.get(MyStats::Health).current_value, health);
+        }
+        entity.schools.set(fire, 2.);
+        entity.add(hit(fire)).unwrap();
+        assert_eq!(entity.stats.get(MyStats::Health).current_value, 57.);
+    }
 }
\ No newline at end of file
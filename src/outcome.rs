@@ -0,0 +1,92 @@
+use std::{future::Future, pin::Pin, sync::{Arc, Mutex}, task::{Context, Poll, Waker}};
+use bevy::prelude::*;
+use crate::prelude::*;
+
+/// Why add_effect did not apply an effect
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EffectBlockedReason {
+    MissingActiveEffects,
+    MissingStats,
+    StackLimit,
+    Refreshed, // stacking policy reset the existing effects' durations instead
+    Interrupted, // CancelPending aborted the effect during its warmup
+    Vetoed, // one of the target's EffectVetoes rejected it
+    Expired, // a persistent effect was added with no duration left
+    ImmutableStat, // the effect targets a stat in ImmutableStats
+    OnCooldown, // the effect's cooldown tag is still active
+    Outclassed, // a KeepStrongest stack already holds an effect at least as strong
+}
+
+/// Result of processing an AddEffect
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EffectOutcome {
+    pub applied: bool,
+    pub amount: f32,
+    pub blocked_reason: Option<EffectBlockedReason>,
+}
+
+impl EffectOutcome {
+    pub(crate) fn applied(amount: f32) -> Self {
+        Self { applied: true, amount, blocked_reason: None }
+    }
+
+    pub(crate) fn blocked(reason: EffectBlockedReason) -> Self {
+        Self { applied: false, amount: 0., blocked_reason: Some(reason) }
+    }
+}
+
+#[derive(Default)]
+struct OutcomeSlot {
+    outcome: Option<EffectOutcome>,
+    waker: Option<Waker>,
+}
+
+#[derive(Clone)]
+pub struct EffectOutcomeSender(Arc<Mutex<OutcomeSlot>>);
+
+impl EffectOutcomeSender {
+    pub(crate) fn send(&self, outcome: EffectOutcome) {
+        let mut slot = self.0.lock().unwrap();
+        slot.outcome = Some(outcome);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// One-shot receiver resolved once add_effect has processed the effect.
+/// Poll it with try_recv from a system or await it from an async task.
+pub struct EffectOutcomeReceiver(Arc<Mutex<OutcomeSlot>>);
+
+impl EffectOutcomeReceiver {
+    pub fn try_recv(&self) -> Option<EffectOutcome> {
+        self.0.lock().unwrap().outcome.take()
+    }
+}
+
+impl Future for EffectOutcomeReceiver {
+    type Output = EffectOutcome;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.0.lock().unwrap();
+        match slot.outcome.take() {
+            Some(outcome) => Poll::Ready(outcome),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub(crate) fn outcome_channel() -> (EffectOutcomeSender, EffectOutcomeReceiver) {
+    let slot = Arc::new(Mutex::new(OutcomeSlot::default()));
+    (EffectOutcomeSender(slot.clone()), EffectOutcomeReceiver(slot))
+}
+
+/// Trigger AddEffect and get a receiver for its outcome
+pub fn apply_effect_and_wait<T: StatTrait>(commands: &mut Commands, data: AddEffectData<T>) -> EffectOutcomeReceiver {
+    let (data, receiver) = data.with_outcome();
+    commands.trigger(AddEffect(data));
+    receiver
+}
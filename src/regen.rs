@@ -0,0 +1,72 @@
+use bevy::prelude::*;
+use crate::{prelude::*, calculation::clamp_current, timing::FixedStepClock};
+
+/// Stats which regenerate by the current value of a regen stat each second, up to a max stat,
+/// e.g. Health by HealthRegen up to HealthMax.  Configured with GameplayEffectsPlugin::with_regen.
+#[derive(Resource, Clone)]
+pub struct StatRegen<T: StatTrait>(Vec<(T, T, T)>);
+
+impl<T: StatTrait> Default for StatRegen<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: StatTrait> StatRegen<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn regen(mut self, stat: T, regen_stat: T, max_stat: T) -> Self {
+        self.0.push((stat, regen_stat, max_stat));
+        self
+    }
+}
+
+/// Raises each regenerating stat towards its max.  Stats already at or above the max are left
+/// alone, and only entities whose stats actually change are marked changed.  With a fixed step
+/// the stats rise once per whole step, like the effects, so the result is independent of frame pacing.
+pub(crate) fn apply_stat_regen<T: StatTrait>(
+    time: Res<Time>,
+    regen: Res<StatRegen<T>>,
+    settings: Res<GameplayEffectsSettings>,
+    audit: Option<Res<AuditSink<T>>>,
+    mut clock: Local<FixedStepClock>,
+    mut stats_query: Query<(Entity, &mut GameplayStats<T>)>,
+) {
+    if regen.0.is_empty() {
+        return;
+    }
+    for (delta, _) in clock.steps(&time, settings.fixed_step) {
+        regen_step(&regen, delta, settings.bound_epsilon, audit.as_deref(), &mut stats_query);
+    }
+}
+
+fn regen_step<T: StatTrait>(
+    regen: &StatRegen<T>,
+    delta: f32,
+    epsilon: f32,
+    audit: Option<&AuditSink<T>>,
+    stats_query: &mut Query<(Entity, &mut GameplayStats<T>)>,
+) {
+    for (entity, mut stats) in stats_query.iter_mut() {
+        for &(stat, regen_stat, max_stat) in regen.0.iter() {
+            let (Some(current), Some(rate), Some(max)) = (stats.try_get(stat), stats.try_get(regen_stat), stats.try_get(max_stat)) else {
+                continue
+            };
+            let (current, max) = (current.current_value, max.current_value);
+            let amount = rate.current_value * delta;
+            if current >= max || amount <= 0. { continue }
+            if let Some(stat_value) = stats.try_get_mut(stat) {
+                // Clamped like an effect so is_at_bound reports a stat regen filled, without a breach message
+                stat_value.current_value = current + amount;
+                clamp_current(entity, stat, stat_value, max, f32::MIN, epsilon);
+                if let Some(audit) = audit {
+                    audit.record(StatMutation {
+                        entity, stat, old: current, new: stat_value.current_value, tag: None, source: None, cause: MutationCause::Regen,
+                    });
+                }
+            }
+        }
+    }
+}
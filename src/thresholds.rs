@@ -0,0 +1,90 @@
+use std::{collections::HashMap, marker::PhantomData};
+use bevy::prelude::*;
+use crate::prelude::*;
+
+/// Fractions of a reference stat at which OnThresholdCrossed fires, e.g. Health at 50% of HealthMax
+#[derive(Resource, Clone)]
+pub struct StatThresholds<T: StatTrait>(Vec<(T, T, f32)>);
+
+impl<T: StatTrait> Default for StatThresholds<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: StatTrait> StatThresholds<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn threshold(mut self, stat: T, reference: T, fraction: f32) -> Self {
+        self.0.push((stat, reference, fraction));
+        self
+    }
+}
+
+/// Which side of each threshold every entity was last seen on
+#[derive(Resource)]
+pub(crate) struct ThresholdStates<T: StatTrait>(HashMap<Entity, Vec<bool>>, PhantomData<T>);
+
+impl<T: StatTrait> Default for ThresholdStates<T> {
+    fn default() -> Self {
+        Self(HashMap::new(), PhantomData)
+    }
+}
+
+fn below_thresholds<T: StatTrait>(thresholds: &StatThresholds<T>, stats: &GameplayStats<T>) -> Vec<bool> {
+    thresholds.0.iter().map(|&(stat, reference, fraction)| {
+        match (stats.try_get(stat), stats.try_get(reference)) {
+            (Some(stat), Some(reference)) => stat.current_value < reference.current_value * fraction,
+            _ => false,
+        }
+    }).collect()
+}
+
+/// Record a new entity's side of each threshold from its stats as spawned, so effects applied before
+/// check_thresholds first sees it still count as crossings
+pub(crate) fn record_initial_thresholds<T: StatTrait>(
+    trigger: On<Add, GameplayStats<T>>,
+    thresholds: Res<StatThresholds<T>>,
+    stats_query: Query<&GameplayStats<T>>,
+    mut states: ResMut<ThresholdStates<T>>,
+) {
+    if thresholds.0.is_empty() {
+        return;
+    }
+    if let Ok(stats) = stats_query.get(trigger.entity) {
+        states.0.insert(trigger.entity, below_thresholds(&thresholds, stats));
+    }
+}
+
+/// Compares each changed entity's stats against the thresholds, remembering which side
+/// of each threshold they were on so events only fire on crossing.
+pub(crate) fn check_thresholds<T: StatTrait>(
+    thresholds: Res<StatThresholds<T>>,
+    stats_query: Query<(Entity, &GameplayStats<T>), Changed<GameplayStats<T>>>,
+    mut removed: RemovedComponents<GameplayStats<T>>,
+    mut states: ResMut<ThresholdStates<T>>,
+    mut crossed_writer: MessageWriter<OnThresholdCrossed<T>>,
+) {
+    for entity in removed.read() {
+        states.0.remove(&entity);
+    }
+    if thresholds.0.is_empty() {
+        return;
+    }
+    for (entity, stats) in stats_query.iter() {
+        let current = below_thresholds(&thresholds, stats);
+
+        // An entity spawned before the thresholds were set just records its state the first time
+        if let Some(previous) = states.0.get(&entity) {
+            for (i, (&was_below, &is_below)) in previous.iter().zip(current.iter()).enumerate() {
+                if was_below == is_below { continue }
+                let (stat, _, threshold) = thresholds.0[i];
+                let direction = if is_below { CrossingDirection::Downward } else { CrossingDirection::Upward };
+                crossed_writer.write(OnThresholdCrossed { entity, stat, threshold, direction });
+            }
+        }
+        states.0.insert(entity, current);
+    }
+}
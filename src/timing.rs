@@ -1,15 +1,88 @@
+use std::time::Duration;
+use bevy::prelude::Time;
+use smallvec::{SmallVec, smallvec};
+
 
 #[derive(Clone, PartialEq)]
 pub enum EffectDuration {
     Immediate,
     Persistent(Option<SmallTimer>),
+    /// Applied once when added like Immediate, then kept in ActiveEffects until removed or expired,
+    /// which takes the change back out.  Recalculations leave it alone.
+    Tracked(Option<SmallTimer>),
     Continuous(Option<SmallTimer>),
     Repeating(RepeatingSmallTimer, Option<SmallTimer>),
+    Scheduled(Vec<f32>), // absolute elapsed times in seconds, removed after the last one fires
+}
+
+/// Shorthands hiding the Option and timer conversions of the variants
+///
+/// ```
+/// use bevy_gameplay_effects::prelude::{EffectDuration, RepeatingSmallTimer};
+///
+/// assert!(EffectDuration::immediate() == EffectDuration::Immediate);
+/// assert!(EffectDuration::persistent() == EffectDuration::Persistent(None));
+/// assert!(EffectDuration::persistent_for(10.) == EffectDuration::Persistent(Some(10.0.into())));
+/// assert!(EffectDuration::tracked() == EffectDuration::Tracked(None));
+/// assert!(EffectDuration::tracked_for(10.) == EffectDuration::Tracked(Some(10.0.into())));
+/// assert!(EffectDuration::continuous(5.) == EffectDuration::Continuous(Some(5.0.into())));
+/// assert!(EffectDuration::continuous_forever() == EffectDuration::Continuous(None));
+/// assert!(EffectDuration::repeating(0.5, 3.) == EffectDuration::Repeating(RepeatingSmallTimer::new(0.5), Some(3.0.into())));
+/// assert!(EffectDuration::repeating_forever(2.) == EffectDuration::Repeating(RepeatingSmallTimer::new(2.), None));
+/// assert!(EffectDuration::scheduled([0.5_f32, 1.5]) == EffectDuration::Scheduled(vec![0.5, 1.5]));
+/// ```
+impl EffectDuration {
+    pub fn immediate() -> Self {
+        Self::Immediate
+    }
+
+    /// Stays until removed
+    pub fn persistent() -> Self {
+        Self::Persistent(None)
+    }
+
+    pub fn persistent_for(secs: f32) -> Self {
+        Self::Persistent(Some(secs.into()))
+    }
+
+    /// Stays until removed
+    pub fn tracked() -> Self {
+        Self::Tracked(None)
+    }
+
+    pub fn tracked_for(secs: f32) -> Self {
+        Self::Tracked(Some(secs.into()))
+    }
+
+    pub fn continuous(secs: f32) -> Self {
+        Self::Continuous(Some(secs.into()))
+    }
+
+    /// Keeps applying until removed
+    pub fn continuous_forever() -> Self {
+        Self::Continuous(None)
+    }
+
+    /// Triggers every period seconds until total seconds have passed
+    pub fn repeating(period: f32, total: f32) -> Self {
+        Self::Repeating(RepeatingSmallTimer::new(period), Some(total.into()))
+    }
+
+    /// Triggers every period seconds until removed
+    pub fn repeating_forever(period: f32) -> Self {
+        Self::Repeating(RepeatingSmallTimer::new(period), None)
+    }
+
+    /// Fires at each absolute elapsed time in seconds
+    pub fn scheduled(times: impl Into<Vec<f32>>) -> Self {
+        Self::Scheduled(times.into())
+    }
 }
 
 #[derive(Clone, PartialEq)]
 pub struct SmallTimer {
     pub(crate) remaining: f32,
+    pub(crate) duration: f32,
 }
 
 impl SmallTimer {
@@ -21,14 +94,28 @@ impl SmallTimer {
         self.remaining <= 0.
     }
 
+    /// Fraction of the original duration still left, from 1 down to 0
+    pub(crate) fn fraction_remaining(&self) -> f32 {
+        if self.duration <= 0. { return 0. }
+        (self.remaining / self.duration).clamp(0., 1.)
+    }
+
+    /// Timer of the same length which already ran partway, e.g. 3 of 10 seconds left
+    pub fn with_remaining(mut self, remaining: f32) -> Self {
+        self.remaining = f32::min(remaining, self.duration);
+        self
+    }
+
     pub fn set_duration(&mut self, timer: impl Into<SmallTimer>) {
-        self.remaining = timer.into().remaining;
+        let timer = timer.into();
+        self.remaining = timer.remaining;
+        self.duration = timer.duration;
     }
 }
 
 impl From<f32> for SmallTimer {
     fn from(value: f32) -> Self {
-        Self { remaining: value }
+        Self { remaining: value, duration: value }
     }
 }
 
@@ -40,6 +127,12 @@ pub struct RepeatingSmallTimer {
 }
 
 impl RepeatingSmallTimer {
+    /// Triggers after one period and then every period seconds.  Converting an f32 instead
+    /// sets the delay before the first trigger and a 1s period.
+    pub fn new(period: f32) -> Self {
+        Self { period, remaining: period, triggered: false }
+    }
+
     pub(crate) fn tick(&mut self, secs: f32) {
         self.remaining -= secs;
         if self.remaining <= 0. {
@@ -58,6 +151,13 @@ impl RepeatingSmallTimer {
     pub fn set_duration(&mut self, timer: impl Into<RepeatingSmallTimer>) {
         self.remaining = timer.into().remaining;
     }
+
+    /// Move the first trigger to the next multiple of the period in the clock's elapsed time
+    pub(crate) fn align(&mut self, clock: &FixedStepClock, time: &Time, fixed_step: Option<f32>) {
+        if self.period > 0. {
+            self.remaining = self.period - clock.elapsed_secs(time, fixed_step).rem_euclid(self.period);
+        }
+    }
 }
 
 impl From<f32> for RepeatingSmallTimer {
@@ -70,3 +170,37 @@ impl From<f32> for RepeatingSmallTimer {
         Self { period: 1., remaining: value, triggered: false }
     }
 }
+
+/// Quantizes frame time into whole steps for GameplayEffectsSettings::fixed_step.  Time is accumulated
+/// as a Duration so clients with different frame pacing count exactly the same steps.
+#[derive(Default)]
+pub(crate) struct FixedStepClock {
+    accumulated: Duration,
+    steps: u64,
+}
+
+impl FixedStepClock {
+    /// (delta, elapsed) of each step to run this frame, a single step of the frame time without a fixed step
+    pub(crate) fn steps(&mut self, time: &Time, fixed_step: Option<f32>) -> SmallVec<[(f32, f32); 4]> {
+        let Some(step) = fixed_step.filter(|&step| step > 0.) else {
+            return smallvec![(time.delta_secs(), time.elapsed_secs())];
+        };
+        let step_duration = Duration::from_secs_f32(step);
+        self.accumulated += time.delta();
+        let mut steps = SmallVec::new();
+        while self.accumulated >= step_duration {
+            self.accumulated -= step_duration;
+            self.steps += 1;
+            steps.push((step, self.steps as f32 * step));
+        }
+        steps
+    }
+
+    /// Elapsed time of the last step run, the frame's elapsed time without a fixed step
+    pub(crate) fn elapsed_secs(&self, time: &Time, fixed_step: Option<f32>) -> f32 {
+        match fixed_step.filter(|&step| step > 0.) {
+            Some(step) => self.steps as f32 * step,
+            None => time.elapsed_secs(),
+        }
+    }
+}